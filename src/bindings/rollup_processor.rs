@@ -1,7 +1,7 @@
 pub use rollup_processor::*;
 
-#[allow(clippy::too_many_arguments, non_camel_case_types)]
 pub mod rollup_processor {
+    #![allow(clippy::module_inception, clippy::too_many_arguments, non_camel_case_types)]
     #![allow(clippy::enum_variant_names)]
     #![allow(dead_code)]
     #![allow(clippy::type_complexity)]
@@ -23,7 +23,7 @@ pub mod rollup_processor {
     #[doc = r" The parsed human readable ABI of the contract."]
     pub static ROLLUPPROCESSOR_ABI: ethers::contract::Lazy<ethers::core::abi::Abi> =
         ethers::contract::Lazy::new(|| {
-            ethers :: core :: abi :: parse_abi_str ("[\n        function processRollup(bytes calldata proofData, bytes calldata signatures) external\n        event RollupProcessed(uint256 indexed rollupId, bytes32[] nextExpectedDefiHashes, address sender)\n    ]") . expect ("invalid abi")
+            ethers :: core :: abi :: parse_abi_str ("[\n        function processRollup(bytes calldata proofData, bytes calldata signatures) external\n        event RollupProcessed(uint256 indexed rollupId, bytes32[] nextExpectedDefiHashes, address sender)\n        event DefiBridgeProcessed(uint256 indexed encodedBridgeCallData, uint256 indexed nonce, uint256 totalInputValue, uint256 totalOutputValueA, uint256 totalOutputValueB, bool result, bytes errorReason)\n    ]") . expect ("invalid abi")
         });
     pub struct RollupProcessor<M>(ethers::contract::Contract<M>);
     impl<M> Clone for RollupProcessor<M> {
@@ -65,14 +65,20 @@ pub mod rollup_processor {
                 .method_hash([248, 28, 204, 190], (proof_data, signatures))
                 .expect("method not found (this should never happen)")
         }
+        #[doc = "Gets the contract's `DefiBridgeProcessed` event"]
+        pub fn defi_bridge_processed_filter(
+            &self,
+        ) -> ethers::contract::builders::Event<'_, M, DefiBridgeProcessedFilter> {
+            self.0.event()
+        }
         #[doc = "Gets the contract's `RollupProcessed` event"]
         pub fn rollup_processed_filter(
             &self,
-        ) -> ethers::contract::builders::Event<M, RollupProcessedFilter> {
+        ) -> ethers::contract::builders::Event<'_, M, RollupProcessedFilter> {
             self.0.event()
         }
         #[doc = r" Returns an [`Event`](#ethers_contract::builders::Event) builder for all events of this contract"]
-        pub fn events(&self) -> ethers::contract::builders::Event<M, RollupProcessedFilter> {
+        pub fn events(&self) -> ethers::contract::builders::Event<'_, M, RollupProcessorEvents> {
             self.0.event_with_filter(Default::default())
         }
     }
@@ -90,6 +96,30 @@ pub mod rollup_processor {
         ethers :: contract :: EthDisplay,
         Default,
     )]
+    #[ethevent(
+        name = "DefiBridgeProcessed",
+        abi = "DefiBridgeProcessed(uint256,uint256,uint256,uint256,uint256,bool,bytes)"
+    )]
+    pub struct DefiBridgeProcessedFilter {
+        #[ethevent(indexed)]
+        pub encoded_bridge_call_data: ethers::core::types::U256,
+        #[ethevent(indexed)]
+        pub nonce: ethers::core::types::U256,
+        pub total_input_value: ethers::core::types::U256,
+        pub total_output_value_a: ethers::core::types::U256,
+        pub total_output_value_b: ethers::core::types::U256,
+        pub result: bool,
+        pub error_reason: ethers::core::types::Bytes,
+    }
+    #[derive(
+        Clone,
+        Debug,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthEvent,
+        ethers :: contract :: EthDisplay,
+        Default,
+    )]
     #[ethevent(
         name = "RollupProcessed",
         abi = "RollupProcessed(uint256,bytes32[],address)"
@@ -100,6 +130,35 @@ pub mod rollup_processor {
         pub next_expected_defi_hashes: Vec<[u8; 32]>,
         pub sender: ethers::core::types::Address,
     }
+    #[derive(Debug, Clone, PartialEq, Eq, ethers :: contract :: EthAbiType)]
+    pub enum RollupProcessorEvents {
+        DefiBridgeProcessedFilter(DefiBridgeProcessedFilter),
+        RollupProcessedFilter(RollupProcessedFilter),
+    }
+    impl ethers::contract::EthLogDecode for RollupProcessorEvents {
+        fn decode_log(
+            log: &ethers::core::abi::RawLog,
+        ) -> ::std::result::Result<Self, ethers::core::abi::Error>
+        where
+            Self: Sized,
+        {
+            if let Ok(decoded) = DefiBridgeProcessedFilter::decode_log(log) {
+                return Ok(RollupProcessorEvents::DefiBridgeProcessedFilter(decoded));
+            }
+            if let Ok(decoded) = RollupProcessedFilter::decode_log(log) {
+                return Ok(RollupProcessorEvents::RollupProcessedFilter(decoded));
+            }
+            Err(ethers::core::abi::Error::InvalidData)
+        }
+    }
+    impl ::std::fmt::Display for RollupProcessorEvents {
+        fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+            match self {
+                RollupProcessorEvents::DefiBridgeProcessedFilter(element) => element.fmt(f),
+                RollupProcessorEvents::RollupProcessedFilter(element) => element.fmt(f),
+            }
+        }
+    }
     #[doc = "Container type for all input parameters for the `processRollup` function with signature `processRollup(bytes,bytes)` and selector `[248, 28, 204, 190]`"]
     #[derive(
         Clone,
@@ -0,0 +1,230 @@
+use crate::bindings::rollup_processor::ProcessRollupCall;
+use crate::types::rollup_view::{DecodeError, RollupView};
+use ethers::{
+    abi::AbiDecode,
+    providers::{Http, Middleware, Provider},
+    types::{Bytes, TxHash},
+};
+
+/// A labelled byte range of the rollup calldata.
+pub struct Annotation {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+}
+
+const HEADER_FIELDS: [&str; 11] = [
+    "rollup_id",
+    "rollup_size",
+    "data_start_index",
+    "old_data_root",
+    "new_data_root",
+    "old_null_root",
+    "new_null_root",
+    "old_data_roots_root",
+    "new_data_roots_root",
+    "old_defi_root",
+    "new_defi_root",
+];
+
+const INNER_TX_FIELDS: [(&str, usize); 8] = [
+    ("proof_id", 1),
+    ("note_commitment_1", 32),
+    ("note_commitment_2", 32),
+    ("nullifier_1", 32),
+    ("nullifier_2", 32),
+    ("public_value", 32),
+    ("public_owner", 20),
+    ("public_asset_id", 4),
+];
+
+const PROOF_ELEMENTS: [&str; 22] = [
+    "w1.x",
+    "w1.y",
+    "w2.x",
+    "w2.y",
+    "w3.x",
+    "w3.y",
+    "t1.x",
+    "t1.y",
+    "t2.x",
+    "t2.y",
+    "t3.x",
+    "t3.y",
+    "w1_eval",
+    "w2_eval",
+    "w3_eval",
+    "sigma_1_eval",
+    "sigma_2_eval",
+    "z_omega_eval",
+    "pi_z.x",
+    "pi_z.y",
+    "pi_z_omega.x",
+    "pi_z_omega.y",
+];
+
+/// Fetches the rollup calldata of a block from L1, as the store only keeps the decoded block.
+pub async fn fetch_proof_data(client: &Provider<Http>, tx_hash: TxHash) -> Result<Bytes, String> {
+    let tx = client
+        .get_transaction(tx_hash)
+        .await
+        .map_err(|err| format!("Failed to fetch rollup transaction {:?}: {}", tx_hash, err))?
+        .ok_or_else(|| format!("Rollup transaction {:?} not found", tx_hash))?;
+    let call = ProcessRollupCall::decode(&tx.input)
+        .map_err(|err| format!("Transaction {:?} is not a processRollup call: {}", tx_hash, err))?;
+    Ok(call.proof_data)
+}
+
+/// Labels every byte range of the calldata with the field the decoder reads from it, or returns why
+/// the inner txs can't be read.
+pub fn annotate(view: &RollupView) -> Result<Vec<Annotation>, DecodeError> {
+    let layout = view.layout();
+    let mut annotations = vec![];
+    let mut push = |start: usize, end: usize, label: String| {
+        annotations.push(Annotation { start, end, label });
+    };
+
+    for (i, field) in HEADER_FIELDS.iter().enumerate() {
+        push(32 * i, 32 * i + 32, field.to_string());
+    }
+
+    let lists = [
+        ("bridge_call_data", layout.bridge_call_data_offset(), layout.number_of_bridge_calls),
+        ("defi_deposit_sums", layout.defi_deposit_sums_offset(), layout.number_of_bridge_calls),
+        ("asset_ids", layout.asset_ids_offset(), layout.number_of_fee_assets),
+        ("total_tx_fees", layout.total_tx_fees_offset(), layout.number_of_fee_assets),
+        (
+            "prev_defi_interaction_hashes",
+            layout.prev_defi_interaction_hashes_offset(),
+            layout.number_of_bridge_calls,
+        ),
+    ];
+    for (name, offset, count) in lists {
+        for slot in 0..count {
+            let start = offset + 32 * slot;
+            push(start, start + 32, format!("{}[{}]", name, slot));
+        }
+    }
+
+    let offset = layout.prev_defi_interaction_hash_offset();
+    push(offset, offset + 32, String::from("prev_defi_interaction_hash"));
+    let offset = layout.rollup_beneficiary_offset();
+    push(offset, offset + 32, String::from("rollup_beneficiary"));
+    let offset = layout.num_rollup_txs_offset();
+    push(offset, offset + 32, String::from("num_rollup_txs"));
+    let offset = layout.num_real_txs_offset();
+    push(offset, offset + 4, String::from("num_real_txs"));
+    let offset = layout.encoded_inner_tx_data_length_offset();
+    push(offset, offset + 4, String::from("encoded_inner_tx_data_length"));
+
+    for tx in view.inner_txs() {
+        let tx = tx?;
+        let mut start = tx.offset;
+        for (field, size) in INNER_TX_FIELDS.iter() {
+            if start >= tx.offset + tx.data.len() {
+                break;
+            }
+            push(start, start + size, format!("tx[{}] {:?} {}", tx.position, tx.proof_id, field));
+            start += size;
+        }
+    }
+
+    let proof = view.proof_data_offset();
+    push(proof, proof + 32, String::from("proof public_input_hash"));
+    for (i, point) in ["recursive_p1.x", "recursive_p1.y", "recursive_p2.x", "recursive_p2.y"].iter().enumerate() {
+        for limb in 0..4 {
+            let start = proof + 32 + 128 * i + 32 * limb;
+            push(start, start + 32, format!("proof {} limb {}", point, limb));
+        }
+    }
+    for (i, element) in PROOF_ELEMENTS.iter().enumerate() {
+        let start = proof + 544 + 32 * i;
+        push(start, start + 32, format!("proof {}", element));
+    }
+
+    let end = proof + 544 + 32 * PROOF_ELEMENTS.len();
+    if end < view.calldata().len() {
+        push(end, view.calldata().len(), String::from("unparsed"));
+    }
+
+    Ok(annotations)
+}
+
+/// Prints the annotated calldata, one line per 32 bytes.
+pub fn print_annotated(calldata: &[u8], annotations: &[Annotation]) {
+    for annotation in annotations.iter() {
+        let end = annotation.end.min(calldata.len());
+        if annotation.start >= end {
+            continue;
+        }
+        let bytes = &calldata[annotation.start..end];
+
+        for (i, chunk) in bytes.chunks(32).enumerate() {
+            let label = match i {
+                0 => annotation.label.as_str(),
+                _ => "",
+            };
+            let hex: String = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let start = annotation.start + 32 * i;
+            println!("{:>6}..{:<6} {:<48} {}", start, start + chunk.len(), label, hex);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::layout::AZTEC_CONNECT;
+    use crate::types::rollup_view::tests::calldata_with_txs;
+
+    #[test]
+    fn labels_every_byte_at_the_layout_offsets() {
+        let layout = &AZTEC_CONNECT;
+        // A padding tx followed by a deposit.
+        let mut txs = vec![0, 1];
+        txs.extend_from_slice(&[8; 184]);
+        let calldata = calldata_with_txs(&txs);
+        let view = RollupView::new(&calldata, layout).unwrap();
+        let annotations = annotate(&view).unwrap();
+
+        let mut end = 0;
+        for annotation in annotations.iter() {
+            assert_eq!(annotation.start, end, "{} doesn't follow the field before it", annotation.label);
+            end = annotation.end;
+        }
+        assert_eq!(end, calldata.len());
+
+        let range = |label: &str| {
+            let annotation = annotations.iter().find(|a| a.label == label).unwrap();
+            (annotation.start, annotation.end)
+        };
+        let bridge_call_data = layout.bridge_call_data_offset();
+        assert_eq!(range("new_defi_root"), (320, 352));
+        assert_eq!(range("bridge_call_data[0]"), (bridge_call_data, bridge_call_data + 32));
+        let fees = layout.total_tx_fees_offset() + 32 * 15;
+        assert_eq!(range("total_tx_fees[15]"), (fees, fees + 32));
+        let hash = layout.prev_defi_interaction_hashes_offset() + 32 * 31;
+        assert_eq!(range("prev_defi_interaction_hashes[31]"), (hash, hash + 32));
+        let real_txs = layout.num_real_txs_offset();
+        assert_eq!(range("num_real_txs"), (real_txs, real_txs + 4));
+
+        let inner = layout.inner_txs_offset();
+        assert_eq!(range("tx[0] Padding proof_id"), (inner, inner + 1));
+        assert_eq!(range("tx[1] Deposit proof_id"), (inner + 1, inner + 2));
+        assert_eq!(range("tx[1] Deposit public_asset_id"), (inner + 182, inner + 186));
+
+        let proof = view.proof_data_offset();
+        assert_eq!(proof, inner + txs.len());
+        assert_eq!(range("proof public_input_hash"), (proof, proof + 32));
+        assert_eq!(range("proof recursive_p2.y limb 3"), (proof + 512, proof + 544));
+        assert_eq!(range("proof pi_z_omega.y"), (calldata.len() - 32, calldata.len()));
+    }
+
+    #[test]
+    fn reports_inner_txs_it_cant_read() {
+        let calldata = calldata_with_txs(&[0, 9]);
+        let view = RollupView::new(&calldata, &AZTEC_CONNECT).unwrap();
+
+        assert!(annotate(&view).is_err());
+    }
+}
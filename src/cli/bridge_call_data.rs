@@ -0,0 +1,40 @@
+use crate::types::bridge_call_data::BridgeCallData;
+use ethers::types::U256;
+
+/// Parses a value given either as `0x` prefixed hex or as a decimal string.
+pub fn parse_u256(src: &str) -> Result<U256, String> {
+    match src.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).map_err(|err| err.to_string()),
+        None => U256::from_dec_str(src).map_err(|err| err.to_string()),
+    }
+}
+
+/// Prints the fields of `value`. Returns whether it is valid bridge call data.
+pub fn decode_bridge_call_data(value: U256) -> bool {
+    match BridgeCallData::try_from(value) {
+        Ok(bridge_call_data) => {
+            println!("{}", bridge_call_data);
+            true
+        }
+        Err(err) => {
+            println!("Invalid bridge call data {:#x}: {:?}", value, err);
+            false
+        }
+    }
+}
+
+/// Prints the encoding of `bridge_call_data`. Returns whether its fields fit.
+pub fn encode_bridge_call_data(bridge_call_data: BridgeCallData) -> bool {
+    match U256::try_from(bridge_call_data) {
+        Ok(value) => {
+            println!("{}", bridge_call_data);
+            println!("hex:               {:#066x}", value);
+            println!("decimal:           {}", value);
+            true
+        }
+        Err(err) => {
+            println!("Cannot encode bridge call data: {:?}", err);
+            false
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::bindings::rollup_processor::RollupProcessor;
+use crate::cli::collector::{decode_rollup_log, fetch_block, write_blocks};
+use crate::cli::search::reindex;
+use crate::store::{BlockStore, Parts};
+use ethers::{
+    providers::{Http, Middleware, Provider, StreamExt},
+    types::{Address, H256},
+};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Something wrong with the stored blocks.
+#[derive(Debug)]
+pub enum Problem {
+    /// The record can't be deserialized as a block.
+    Corrupt { rollup_id: u64, error: String },
+    /// The record is stored under another rollup id than the one in its header.
+    IdMismatch { rollup_id: u64, header_rollup_id: u64 },
+    /// Synced before the timestamp was recorded.
+    MissingTimestamp { rollup_id: u64 },
+    /// Synced before the calldata layout was recorded.
+    MissingLayoutVersion { rollup_id: u64 },
+    /// Synced before the calldata size and gas of the L1 tx were recorded.
+    MissingL1Cost { rollup_id: u64 },
+    /// Two rollups claim to be published by the same L1 tx.
+    DuplicateTxHash { rollup_id: u64, other: u64, tx_hash: H256 },
+    /// Rollups missing from the store.
+    Gap { from: u64, to: u64 },
+}
+
+impl Problem {
+    /// Fields that builds before them didn't record, as in every migrated MicroKV data dir. They
+    /// are reported and filled in by `--repair`, but don't fail the check.
+    pub fn is_expected(&self) -> bool {
+        matches!(
+            self,
+            Problem::MissingTimestamp { .. } | Problem::MissingLayoutVersion { .. } | Problem::MissingL1Cost { .. }
+        )
+    }
+
+    /// The rollups that fetching from L1 again would fix.
+    fn refetch(&self) -> Vec<u64> {
+        match self {
+            Problem::Corrupt { rollup_id, .. }
+            | Problem::IdMismatch { rollup_id, .. }
+            | Problem::MissingTimestamp { rollup_id }
+            | Problem::MissingLayoutVersion { rollup_id }
+            | Problem::MissingL1Cost { rollup_id } => vec![*rollup_id],
+            Problem::DuplicateTxHash { rollup_id, other, .. } => vec![*other, *rollup_id],
+            Problem::Gap { from, to } => (*from..=*to).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Corrupt { rollup_id, error } => write!(f, "Rollup {} can't be deserialized: {}", rollup_id, error),
+            Problem::IdMismatch { rollup_id, header_rollup_id } => {
+                write!(f, "Rollup {} holds a block with rollup id {} in its header", rollup_id, header_rollup_id)
+            }
+            Problem::MissingTimestamp { rollup_id } => write!(f, "Rollup {} has no timestamp", rollup_id),
+            Problem::MissingLayoutVersion { rollup_id } => write!(f, "Rollup {} has no layout version", rollup_id),
+            Problem::MissingL1Cost { rollup_id } => write!(f, "Rollup {} has no calldata size or gas used", rollup_id),
+            Problem::DuplicateTxHash { rollup_id, other, tx_hash } => {
+                write!(f, "Rollups {} and {} are both published by L1 tx {:?}", other, rollup_id, tx_hash)
+            }
+            Problem::Gap { from, to } => write!(f, "Rollups {}..={} are missing from the store", from, to),
+        }
+    }
+}
+
+/// Reads every stored record and reports the problems found.
+pub fn check_db(store: &dyn BlockStore) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut tx_hashes: HashMap<H256, u64> = HashMap::new();
+    let mut next_id = 0;
+    let mut count = 0;
+
+    store.for_each_record(0..=u64::MAX, Parts::ALL, &mut |rollup_id, block| {
+        count += 1;
+        if rollup_id != next_id {
+            problems.push(Problem::Gap {
+                from: next_id,
+                to: rollup_id - 1,
+            });
+        }
+        next_id = rollup_id + 1;
+
+        let block = match block {
+            Ok(block) => block,
+            Err(err) => {
+                problems.push(Problem::Corrupt {
+                    rollup_id,
+                    error: err.to_string(),
+                });
+                return;
+            }
+        };
+
+        let header_rollup_id = block.inner.header.rollup_id.as_u64();
+        if header_rollup_id != rollup_id {
+            problems.push(Problem::IdMismatch {
+                rollup_id,
+                header_rollup_id,
+            });
+        }
+        if block.metadata.timestamp.is_none() {
+            problems.push(Problem::MissingTimestamp { rollup_id });
+        }
+        if block.metadata.layout_version.is_none() {
+            problems.push(Problem::MissingLayoutVersion { rollup_id });
+        }
+        if block.metadata.calldata_size.is_none() || block.metadata.gas_used.is_none() {
+            problems.push(Problem::MissingL1Cost { rollup_id });
+        }
+        if let Some(other) = tx_hashes.insert(block.metadata.tx_hash, rollup_id) {
+            problems.push(Problem::DuplicateTxHash {
+                rollup_id,
+                other,
+                tx_hash: block.metadata.tx_hash,
+            });
+        }
+    });
+
+    for problem in problems.iter() {
+        match problem.is_expected() {
+            true => println!("Warning: {}", problem),
+            false => println!("{}", problem),
+        }
+    }
+    let expected = problems.iter().filter(|problem| problem.is_expected()).count();
+    println!(
+        "Checked {} rollups: {} problems, {} fields older builds didn't record (filled in by --repair)",
+        count,
+        problems.len() - expected,
+        expected
+    );
+
+    problems
+}
+
+/// Fetches the rollups affected by `problems` from L1 again and rebuilds the indexes, as entries of
+/// replaced blocks may be stale. The `RollupProcessed` events are read once and looked up by rollup
+/// id. Rollups that can't be fetched are reported and left as they are, while failing to read the
+/// events at all is returned as an error.
+pub async fn repair(client: &Arc<Provider<Http>>, store: &dyn BlockStore, problems: &[Problem]) -> Result<(), String> {
+    let rollup_ids: BTreeSet<u64> = problems.iter().flat_map(|problem| problem.refetch()).collect();
+    if rollup_ids.is_empty() {
+        return Ok(());
+    }
+
+    let rollup: Address = client
+        .resolve_name("rollup.aztec.eth")
+        .await
+        .map_err(|err| format!("Failed to resolve the rollup processor: {}", err))?;
+    let rollup = RollupProcessor::new(rollup, Arc::clone(client));
+
+    let filter = rollup.rollup_processed_filter().from_block(crate::DEPLOYMENT_BLOCK).filter;
+    let mut logs = client.get_logs_paginated(&filter, 5000);
+    let mut events = HashMap::new();
+    while let Some(log) = logs.next().await {
+        let log = log.map_err(|err| format!("Failed to fetch RollupProcessed logs: {}", err))?;
+        let (tx_hash, event) = decode_rollup_log(log)?;
+        if let Some(rollup_id) = u64::try_from(event.rollup_id).ok().filter(|id| rollup_ids.contains(id)) {
+            events.entry(rollup_id).or_insert((tx_hash, event));
+        }
+    }
+
+    for rollup_id in rollup_ids {
+        let (tx_hash, event) = match events.remove(&rollup_id) {
+            Some(found) => found,
+            None => {
+                println!("Rollup {} was not found on L1", rollup_id);
+                continue;
+            }
+        };
+        println!("Fetching rollup {} from L1 tx {:?}", rollup_id, tx_hash);
+        match fetch_block(client, tx_hash, event).await {
+            Ok(block) => write_blocks(store, vec![block]),
+            Err(err) => println!("Failed to repair rollup {}: {}", rollup_id, err),
+        }
+    }
+
+    match reindex(store) {
+        true => Ok(()),
+        false => Err(String::from("Failed to rebuild the indexes")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::records::tests::baseline_block;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::WriteBatch;
+    use crate::types::block::Block;
+
+    #[test]
+    fn migrated_blocks_only_warn() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in [0, 1, 3] {
+            let mut block = Block::try_from(baseline_block(rollup_id)).unwrap();
+            block.metadata.tx_hash = H256::from_low_u64_be(rollup_id);
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+
+        let problems = check_db(&store);
+        let failing: Vec<String> = problems
+            .iter()
+            .filter(|problem| !problem.is_expected())
+            .map(|problem| problem.to_string())
+            .collect();
+        assert_eq!(failing, vec!["Rollups 2..=2 are missing from the store"]);
+        assert!(problems.iter().any(|problem| matches!(problem, Problem::MissingL1Cost { rollup_id: 3 })));
+    }
+}
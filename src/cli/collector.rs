@@ -1,42 +1,100 @@
 use std::fmt::Write;
 use std::sync::Arc;
-use crate::bindings::rollup_processor::{ProcessRollupCall, RollupProcessedFilter, RollupProcessor};
+use crate::bindings::rollup_processor::{DefiBridgeProcessedFilter, ProcessRollupCall, RollupProcessedFilter, RollupProcessor};
+use crate::cli::nullifiers::{index_nullifiers, report_anomalies};
+use crate::cli::search::index_block;
+use crate::store::{BlockStore, WriteBatch};
 use crate::types::block::Block;
+use crate::types::defi_interaction::DefiInteractionResult;
 use ethers::{
     abi::{AbiDecode, RawLog},
     prelude::EthEvent,
     providers::{Http, Middleware, Provider, StreamExt},
-    types::{Address, H256},
+    types::{Address, Log, H256},
 };
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use microkv::MicroKV;
-
-async fn decode_block(client: &Arc<Provider<Http>>, db: &MicroKV, tx_hash: H256, event: RollupProcessedFilter) {
-    let tx = match client.get_transaction(tx_hash).await.unwrap() {
-        Some(tx) => tx,
-        None => {
-            println!("No decodable transaction found at: {:?}", tx_hash);
-            todo!();
-        }
-    };
 
-    let proof_calldata = ProcessRollupCall::decode(&tx.input).unwrap().proof_data;
-    let l1_block = client.get_block(tx.block_number.unwrap()).await.unwrap().unwrap();
-    let block = Block::from((tx_hash, tx.block_number.unwrap(), l1_block.timestamp, proof_calldata, event));
+/// Rollups are fetched this many at a time, and written to the store in one transaction per page.
+const ROLLUPS_PER_PAGE: usize = 20;
+
+/// The number of rollups fetched from L1 at the same time.
+const CONCURRENT_FETCHES: usize = 10;
+
+/// Fetches and decodes the rollup published by the L1 tx `tx_hash`, or says why it can't.
+pub async fn fetch_block(client: &Provider<Http>, tx_hash: H256, event: RollupProcessedFilter) -> Result<Block, String> {
+    let tx = client
+        .get_transaction(tx_hash)
+        .await
+        .map_err(|err| format!("Failed to fetch L1 tx {:?}: {}", tx_hash, err))?
+        .ok_or_else(|| format!("L1 tx {:?} was not found", tx_hash))?;
+    let block_number = tx.block_number.ok_or_else(|| format!("L1 tx {:?} is still pending", tx_hash))?;
+
+    let proof_calldata = ProcessRollupCall::decode(&tx.input)
+        .map_err(|err| format!("L1 tx {:?} is not a processRollup call: {}", tx_hash, err))?
+        .proof_data;
+    let l1_block = client
+        .get_block(block_number)
+        .await
+        .map_err(|err| format!("Failed to fetch L1 block {}: {}", block_number, err))?
+        .ok_or_else(|| format!("L1 block {} was not found", block_number))?;
+    let receipt = client
+        .get_transaction_receipt(tx_hash)
+        .await
+        .map_err(|err| format!("Failed to fetch the receipt of L1 tx {:?}: {}", tx_hash, err))?;
 
-    let id = format!("{:?}", block.inner.header.rollup_id);
+    let mut block = Block::try_from((tx_hash, block_number, l1_block.timestamp, proof_calldata, event))
+        .map_err(|err| format!("The rollup of {:?} can't be decoded: {}", tx_hash, err))?;
+    block.metadata.calldata_size = Some(tx.input.len() as u64);
+    if let Some(receipt) = receipt {
+        block.metadata.gas_used = receipt.gas_used;
+        // Nodes from before EIP-1559 don't report the effective price, which is the gas price then.
+        block.metadata.effective_gas_price = receipt.effective_gas_price.or(tx.gas_price);
+    }
+    Ok(block)
+}
+
+/// Decodes a `RollupProcessed` log, along with the L1 tx that emitted it.
+pub fn decode_rollup_log(log: Log) -> Result<(H256, RollupProcessedFilter), String> {
+    let tx_hash = log.transaction_hash.ok_or("RollupProcessed log without a tx hash")?;
+    let event = RollupProcessedFilter::decode_log(&RawLog {
+        topics: log.topics,
+        data: log.data.to_vec(),
+    })
+        .map_err(|err| format!("RollupProcessed log of L1 tx {:?} can't be decoded: {}", tx_hash, err))?;
+    Ok((tx_hash, event))
+}
 
-    match db.put(id, &block) {
-        Ok(_) => (),
+/// Writes blocks to the store in a single transaction, reporting any nullifiers they spend a second
+/// time.
+pub fn write_blocks(store: &dyn BlockStore, blocks: Vec<Block>) {
+    let rollup_ids = match (blocks.first(), blocks.last()) {
+        (Some(first), Some(last)) => format!("{}..={}", first.inner.header.rollup_id, last.inner.header.rollup_id),
+        _ => return,
+    };
+
+    // The blocks and their index entries are written together, so an interrupted sync never leaves
+    // a block without them.
+    let mut batch = WriteBatch::default();
+    let mut anomalies = vec![];
+    for block in blocks {
+        anomalies.extend(index_nullifiers(store, &mut batch, &block));
+        index_block(&mut batch, &block);
+        batch.put_block(block);
+    }
+
+    match store.write(batch) {
+        Ok(_) => report_anomalies(&anomalies),
         Err(err) => {
-            println!("Database err when writing {:?}: {:?}", tx_hash, err);
+            println!("Database err when writing rollups {}: {}", rollup_ids, err);
         }
     }
 }
 
-pub async fn sync_blocks(client: &Arc<Provider<Http>>, db: &MicroKV, starting_block: u64) {
+/// Fetches and stores the rollups published from `starting_block` on. Stops at the first rollup
+/// that can't be fetched, after storing the ones before it, and returns false.
+pub async fn sync_blocks(client: &Arc<Provider<Http>>, store: &dyn BlockStore, starting_block: u64) -> bool {
     let rollup: Address = client.resolve_name("rollup.aztec.eth").await.unwrap();
-    let rollup = RollupProcessor::new(rollup, Arc::clone(&client));
+    let rollup = RollupProcessor::new(rollup, Arc::clone(client));
 
     let block_number = client.get_block_number().await.unwrap().as_u64();
     let pb = ProgressBar::new(block_number - starting_block);
@@ -50,22 +108,77 @@ pub async fn sync_blocks(client: &Arc<Provider<Http>>, db: &MicroKV, starting_bl
         .from_block(starting_block)
         .filter;
 
-    let stream = client.get_logs_paginated(&filter, 5000);
-
-    stream
-        .for_each_concurrent(10, |res| async {
-            let log = res.unwrap();
-            let event = RollupProcessedFilter::decode_log(&RawLog {
-                topics: log.topics,
-                data: log.data.to_vec(),
-            })
-                .unwrap();
-            decode_block(&client, &db, log.transaction_hash.unwrap(), event).await;
-
-            let current_block = log.block_number.unwrap().as_u64();
-            if pb.position() <= current_block - starting_block {
-                pb.set_position(current_block - starting_block);
+    // Rollups are fetched concurrently but handed on in the order of their events, so every page
+    // is written after the ones before it.
+    let mut pages = client
+        .get_logs_paginated(&filter, 5000)
+        .map(|res| async {
+            let log = res.map_err(|err| format!("Failed to fetch RollupProcessed logs: {}", err))?;
+            let (tx_hash, event) = decode_rollup_log(log)?;
+            fetch_block(client, tx_hash, event).await
+        })
+        .buffered(CONCURRENT_FETCHES)
+        .chunks(ROLLUPS_PER_PAGE);
+
+    while let Some(page) = pages.next().await {
+        let mut blocks = vec![];
+        let mut failed = None;
+        for block in page {
+            match block {
+                Ok(block) => blocks.push(block),
+                Err(err) => {
+                    failed = Some(err);
+                    break;
+                }
             }
+        }
+
+        let current_block = blocks.last().map(|block| block.metadata.block_number.as_u64());
+        write_blocks(store, blocks);
+        if let Some(current_block) = current_block {
+            pb.set_position(current_block.saturating_sub(starting_block));
+        }
+        if let Some(err) = failed {
+            pb.abandon();
+            println!("{}, sync again to continue from here", err);
+            return false;
+        }
+    }
+    true
+}
+/// Collects the `DefiBridgeProcessed` events, which carry the outputs needed to compute the hashes
+/// that later rollups insert into the defi tree. Async interactions emit theirs when finalised.
+pub async fn sync_defi_results(client: &Arc<Provider<Http>>, store: &dyn BlockStore, starting_block: u64) {
+    let rollup: Address = client.resolve_name("rollup.aztec.eth").await.unwrap();
+    let rollup = RollupProcessor::new(rollup, Arc::clone(client));
+
+    let filter = rollup
+        .defi_bridge_processed_filter()
+        .from_block(starting_block)
+        .filter;
+
+    let mut stream = client.get_logs_paginated(&filter, 5000);
+    let mut batch = WriteBatch::default();
+    while let Some(res) = stream.next().await {
+        let log = res.unwrap();
+        let event = DefiBridgeProcessedFilter::decode_log(&RawLog {
+            topics: log.topics,
+            data: log.data.to_vec(),
         })
-        .await;
-}
\ No newline at end of file
+            .unwrap();
+        let result = DefiInteractionResult::from((log.transaction_hash.unwrap(), log.block_number.unwrap(), event));
+        batch.put_defi_result(result);
+
+        if batch.defi_results.len() >= 1000 {
+            write_defi_results(store, std::mem::take(&mut batch));
+        }
+    }
+    write_defi_results(store, batch);
+}
+
+fn write_defi_results(store: &dyn BlockStore, batch: WriteBatch) {
+    let count = batch.defi_results.len();
+    if let Err(err) = store.write(batch) {
+        println!("Database err when writing {} defi interaction results: {}", count, err);
+    }
+}
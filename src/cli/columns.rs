@@ -0,0 +1,737 @@
+use crate::cli::export::ExportDefiLifecycle;
+use crate::store::Parts;
+use crate::types::block::{Block, FeeAsset};
+use crate::types::defi_interaction::DefiInteraction;
+use crate::types::transaction::{InnerProofData, ProofId};
+use chrono::{DateTime, SecondsFormat};
+use ethers::types::U256;
+use serde_json::{json, Value};
+
+/// How a column reads its value from a row.
+pub enum Getter<C> {
+    Value(fn(&Block, &C) -> Value),
+    /// 256 bit values, written as hex like the fields they come from. Every one of them can also be
+    /// selected as `<name>_decimal`.
+    U256(fn(&Block, &C) -> Option<U256>),
+}
+
+pub struct Column<C> {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub getter: Getter<C>,
+}
+
+fn column<C>(name: &'static str, description: &'static str, value: fn(&Block, &C) -> Value) -> Column<C> {
+    Column {
+        name,
+        description,
+        getter: Getter::Value(value),
+    }
+}
+
+fn u256_column<C>(name: &'static str, description: &'static str, value: fn(&Block, &C) -> Option<U256>) -> Column<C> {
+    Column {
+        name,
+        description,
+        getter: Getter::U256(value),
+    }
+}
+
+/// The columns an export can write, and named sets of them. The `default` preset holds the columns
+/// the export had before they could be chosen, `full` holds every column.
+pub struct Table<C> {
+    pub export: &'static str,
+    /// The parts of the blocks the rows and their columns are read from.
+    pub parts: Parts,
+    pub columns: Vec<Column<C>>,
+    pub presets: &'static [(&'static str, &'static [&'static str])],
+}
+
+/// The columns to export, chosen by name or preset.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ColumnSelection {
+    #[clap(long, value_delimiter = ',', conflicts_with = "preset", help = "The columns to export, see --list-columns")]
+    pub columns: Vec<String>,
+    #[clap(long, help = "A named set of columns to export, see --list-columns [default: default]")]
+    pub preset: Option<String>,
+    #[clap(long, help = "Print the columns and presets of the export and exit")]
+    pub list_columns: bool,
+}
+
+/// The selected columns of a table, each with whether it is written in decimal.
+pub struct Columns<C> {
+    table: Table<C>,
+    selected: Vec<(usize, bool)>,
+}
+
+impl<C> Columns<C> {
+    /// Resolves the selection against `table`. Prints the columns and exits with `--list-columns`,
+    /// and exits on unknown names.
+    pub fn select(table: Table<C>, selection: &ColumnSelection) -> Columns<C> {
+        if selection.list_columns {
+            print_columns(&table);
+            std::process::exit(0);
+        }
+
+        let names: Vec<String> = match (&selection.columns, &selection.preset) {
+            (columns, _) if !columns.is_empty() => columns.clone(),
+            (_, Some(preset)) if preset == "full" => table.columns.iter().map(|column| column.name.to_string()).collect(),
+            (_, preset) => {
+                let preset = preset.as_deref().unwrap_or("default");
+                match table.presets.iter().find(|(name, _)| *name == preset) {
+                    Some((_, columns)) => columns.iter().map(|column| column.to_string()).collect(),
+                    None => {
+                        eprintln!("{} has no preset {}, see --list-columns", table.export, preset);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        };
+
+        let selected = names
+            .iter()
+            .map(|name| match find_column(&table, name) {
+                Some(selected) => selected,
+                None => {
+                    eprintln!("{} has no column {}, see --list-columns", table.export, name);
+                    std::process::exit(1);
+                }
+            })
+            .collect();
+
+        Columns { table, selected }
+    }
+
+    pub fn parts(&self) -> Parts {
+        self.table.parts
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.selected
+            .iter()
+            .map(|(index, decimal)| match decimal {
+                true => format!("{}_decimal", self.table.columns[*index].name),
+                false => self.table.columns[*index].name.to_string(),
+            })
+            .collect()
+    }
+
+    /// The selected columns for the manifest of incremental exports, or `None` for the default
+    /// preset so manifests written before columns could be chosen still match.
+    pub fn option(&self) -> Option<String> {
+        let names = self.names();
+        let default = self.table.presets.iter().find(|(name, _)| *name == "default").map(|(_, columns)| *columns);
+        match default {
+            Some(default) if names == default => None,
+            _ => Some(names.join(",")),
+        }
+    }
+
+    pub fn values(&self, block: &Block, row: &C) -> Vec<Value> {
+        self.selected
+            .iter()
+            .map(|(index, decimal)| match (&self.table.columns[*index].getter, decimal) {
+                (Getter::Value(value), _) => value(block, row),
+                (Getter::U256(value), false) => json!(value(block, row)),
+                (Getter::U256(value), true) => json!(value(block, row).map(|value| value.to_string())),
+            })
+            .collect()
+    }
+}
+
+fn find_column<C>(table: &Table<C>, name: &str) -> Option<(usize, bool)> {
+    if let Some(index) = table.columns.iter().position(|column| column.name == name) {
+        return Some((index, false));
+    }
+    let base = name.strip_suffix("_decimal")?;
+    table
+        .columns
+        .iter()
+        .position(|column| column.name == base && matches!(column.getter, Getter::U256(_)))
+        .map(|index| (index, true))
+}
+
+fn print_columns<C>(table: &Table<C>) {
+    println!("Columns of {}, 256 bit values marked with * can also be selected as <name>_decimal:", table.export);
+    for column in table.columns.iter() {
+        let marker = match column.getter {
+            Getter::U256(_) => "*",
+            Getter::Value(_) => " ",
+        };
+        println!("  {:<28}{} {}", column.name, marker, column.description);
+    }
+    println!("\nPresets:");
+    println!("  {:<28}  every column", "full");
+    for (name, columns) in table.presets.iter() {
+        println!("  {:<28}  {}", name, columns.join(","));
+    }
+}
+
+/// ISO-8601 in UTC, like `2022-06-01T12:00:00Z`.
+pub fn iso_time(timestamp: &U256) -> Option<String> {
+    DateTime::from_timestamp(timestamp.as_u64() as i64, 0).map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// The columns every export has, of the rollup the row is from.
+fn block_columns<C>() -> Vec<Column<C>> {
+    vec![
+        u256_column("rollup_id", "The rollup the row is from", |block, _| Some(block.inner.header.rollup_id)),
+        column("l1_tx_hash", "The L1 tx that published the rollup", |block, _| json!(block.metadata.tx_hash)),
+        column("l1_block_number", "The L1 block the rollup was published in", |block, _| {
+            json!(block.metadata.block_number.as_u64())
+        }),
+        u256_column("timestamp", "The L1 time of the rollup in unix seconds, 0 if not recorded", |block, _| {
+            Some(block.metadata.timestamp.unwrap_or_default())
+        }),
+        column("time", "The L1 time of the rollup as ISO-8601 in UTC", |block, _| {
+            json!(block.metadata.timestamp.as_ref().and_then(iso_time))
+        }),
+        column("sequencer", "The address that published the rollup", |block, _| json!(block.sequencer)),
+        column("rollup_beneficiary", "The address the rollup fees are paid to", |block, _| {
+            json!(block.inner.header.rollup_beneficiary)
+        }),
+        u256_column("rollup_size", "The number of txs the rollup has room for", |block, _| Some(block.inner.header.rollup_size)),
+        u256_column("data_start_index", "The leaf index of the first note commitment of the rollup", |block, _| {
+            Some(block.inner.header.data_start_index)
+        }),
+        u256_column("num_real_txs", "The number of txs in the rollup that aren't padding", |block, _| Some(block.inner.num_real_txs)),
+        u256_column("num_rollup_txs", "The number of inner rollups", |block, _| Some(block.inner.header.num_rollup_txs)),
+        column("old_data_root", "The data root before the rollup", |block, _| json!(block.inner.header.old_data_root)),
+        column("new_data_root", "The data root after the rollup", |block, _| json!(block.inner.header.new_data_root)),
+        column("old_null_root", "The nullifier root before the rollup", |block, _| json!(block.inner.header.old_null_root)),
+        column("new_null_root", "The nullifier root after the rollup", |block, _| json!(block.inner.header.new_null_root)),
+        column("old_defi_root", "The defi root before the rollup", |block, _| json!(block.inner.header.old_defi_root)),
+        column("new_defi_root", "The defi root after the rollup", |block, _| json!(block.inner.header.new_defi_root)),
+        column("calldata_size", "The size of the input of the L1 tx in bytes, if recorded", |block, _| {
+            json!(block.metadata.calldata_size)
+        }),
+        u256_column("gas_used", "The gas used by the L1 tx, if recorded", |block, _| block.metadata.gas_used),
+        u256_column("effective_gas_price", "The price paid per gas in wei, if recorded", |block, _| {
+            block.metadata.effective_gas_price
+        }),
+        u256_column("gas_cost", "What publishing the rollup cost in wei, if recorded", |block, _| block.metadata.gas_cost()),
+    ]
+}
+
+pub fn tx_table() -> Table<InnerProofData> {
+    let mut columns = block_columns();
+    columns.extend([
+        column("tx_position", "The position of the tx in the rollup", |_, row: &InnerProofData| json!(row.tx_position)),
+        column("proof_id", "The type of the tx", |_, row| json!(row.proof_id)),
+        column("note_commitment_1", "The first note created", |_, row| json!(row.note_commitment_1)),
+        column("note_commitment_2", "The second note created", |_, row| json!(row.note_commitment_2)),
+        column("note_commitment_1_index", "The leaf index of the first note in the data tree", |_, row| {
+            json!(row.note_commitment_1_index)
+        }),
+        column("note_commitment_2_index", "The leaf index of the second note in the data tree", |_, row| {
+            json!(row.note_commitment_2_index)
+        }),
+        column("nullifier_1", "The first note spent", |_, row| json!(row.nullifier_1)),
+        column("nullifier_2", "The second note spent", |_, row| json!(row.nullifier_2)),
+        u256_column("public_value", "The value deposited or withdrawn", |_, row| Some(row.public_value)),
+        column("public_owner", "The L1 address depositing or withdrawing", |_, row| json!(row.public_owner)),
+        u256_column("public_asset_id", "The asset deposited or withdrawn", |_, row| Some(row.public_asset_id)),
+    ]);
+
+    Table {
+        export: "export-txs",
+        parts: Parts::TXS,
+        columns,
+        presets: &[
+            (
+                "default",
+                &[
+                    "rollup_id",
+                    "timestamp",
+                    "proof_id",
+                    "note_commitment_1",
+                    "note_commitment_2",
+                    "nullifier_1",
+                    "nullifier_2",
+                    "public_value",
+                    "public_owner",
+                    "public_asset_id",
+                    "tx_position",
+                    "note_commitment_1_index",
+                    "note_commitment_2_index",
+                ],
+            ),
+            (
+                "readable",
+                &[
+                    "rollup_id_decimal",
+                    "time",
+                    "l1_tx_hash",
+                    "tx_position",
+                    "proof_id",
+                    "public_value_decimal",
+                    "public_asset_id_decimal",
+                    "public_owner",
+                    "note_commitment_1_index",
+                    "note_commitment_2_index",
+                ],
+            ),
+        ],
+    }
+}
+
+pub fn fee_table() -> Table<FeeAsset> {
+    let mut columns = block_columns();
+    columns.extend([
+        column("slot", "The fee slot in the rollup header", |_, row: &FeeAsset| json!(row.slot)),
+        u256_column("asset_id", "The asset the fee is paid in", |_, row| Some(row.asset_id)),
+        u256_column("amount", "The fee paid", |_, row| Some(row.amount)),
+        column("beneficiary", "The address the fee is paid to, the same as rollup_beneficiary", |block, _| {
+            json!(block.inner.header.rollup_beneficiary)
+        }),
+    ]);
+
+    Table {
+        export: "export-fees",
+        parts: Parts::HEAD,
+        columns,
+        presets: &[
+            ("default", &["rollup_id", "asset_id", "amount", "beneficiary", "slot"]),
+            ("readable", &["rollup_id_decimal", "time", "l1_tx_hash", "asset_id_decimal", "amount_decimal", "beneficiary"]),
+        ],
+    }
+}
+
+pub struct DefiRow {
+    pub interaction: DefiInteraction,
+    /// The interactions the virtual inputs of the interaction come from, formatted.
+    pub virtual_chain: String,
+}
+
+pub fn defi_table() -> Table<DefiRow> {
+    let mut columns = block_columns();
+    columns.extend([
+        column("slot", "The bridge call slot in the rollup header", |_, row: &DefiRow| json!(row.interaction.slot)),
+        u256_column("interaction_nonce", "The nonce of the interaction, also its leaf index in the defi tree", |_, row| {
+            Some(row.interaction.interaction_nonce)
+        }),
+        u256_column("encoded_bridge_call_data", "The bridge call data as it appears in the header", |_, row| {
+            Some(row.interaction.encoded_bridge_call_data)
+        }),
+        u256_column("bridge_address_id", "The bridge called", |_, row| Some(row.interaction.bridge_address_id())),
+        u256_column("input_asset_a_id", "The first input asset", |_, row| Some(row.interaction.input_asset_a().asset_id)),
+        column("input_asset_a_type", "Whether the first input is real, virtual or unused", |_, row| {
+            json!(row.interaction.input_asset_a().asset_type)
+        }),
+        u256_column("input_asset_b_id", "The second input asset", |_, row| Some(row.interaction.input_asset_b().asset_id)),
+        column("input_asset_b_type", "Whether the second input is real, virtual or unused", |_, row| {
+            json!(row.interaction.input_asset_b().asset_type)
+        }),
+        u256_column("output_asset_a_id", "The first output asset", |_, row| Some(row.interaction.output_asset_a().asset_id)),
+        column("output_asset_a_type", "Whether the first output is real, virtual or unused", |_, row| {
+            json!(row.interaction.output_asset_a().asset_type)
+        }),
+        u256_column("output_asset_b_id", "The second output asset", |_, row| Some(row.interaction.output_asset_b().asset_id)),
+        column("output_asset_b_type", "Whether the second output is real, virtual or unused", |_, row| {
+            json!(row.interaction.output_asset_b().asset_type)
+        }),
+        u256_column("aux_data", "The auxiliary data passed to the bridge", |_, row| Some(row.interaction.aux_data())),
+        u256_column("total_input_value", "The summed value of the defi deposits", |_, row| Some(row.interaction.total_input_value)),
+        u256_column("input_asset_a_origin_nonce", "The interaction that created the first input, if virtual", |_, row| {
+            row.interaction.input_asset_a().origin_nonce
+        }),
+        u256_column("input_asset_b_origin_nonce", "The interaction that created the second input, if virtual", |_, row| {
+            row.interaction.input_asset_b().origin_nonce
+        }),
+        column("virtual_chain", "Every earlier interaction the virtual inputs pass through", |_, row| json!(row.virtual_chain)),
+    ]);
+
+    Table {
+        export: "export-defi",
+        parts: Parts::HEAD,
+        columns,
+        presets: &[
+            (
+                "default",
+                &[
+                    "rollup_id",
+                    "bridge_address_id",
+                    "input_asset_a_id",
+                    "input_asset_a_type",
+                    "input_asset_b_id",
+                    "input_asset_b_type",
+                    "output_asset_a_id",
+                    "output_asset_a_type",
+                    "output_asset_b_id",
+                    "output_asset_b_type",
+                    "aux_data",
+                    "total_input_value",
+                    "interaction_nonce",
+                    "input_asset_a_origin_nonce",
+                    "input_asset_b_origin_nonce",
+                    "virtual_chain",
+                    "slot",
+                ],
+            ),
+            (
+                "readable",
+                &[
+                    "rollup_id_decimal",
+                    "time",
+                    "interaction_nonce_decimal",
+                    "bridge_address_id_decimal",
+                    "input_asset_a_id_decimal",
+                    "input_asset_b_id_decimal",
+                    "output_asset_a_id_decimal",
+                    "output_asset_b_id_decimal",
+                    "aux_data_decimal",
+                    "total_input_value_decimal",
+                    "virtual_chain",
+                ],
+            ),
+        ],
+    }
+}
+
+pub fn lifecycle_table() -> Table<ExportDefiLifecycle> {
+    let mut columns = block_columns();
+    columns.extend([
+        u256_column("interaction_nonce", "The nonce of the interaction", |_, row: &ExportDefiLifecycle| Some(row.interaction_nonce)),
+        column("slot", "The bridge call slot in the rollup header", |_, row| json!(row.slot)),
+        u256_column("bridge_address_id", "The bridge called", |_, row| Some(row.bridge_address_id)),
+        u256_column("submitted_rollup", "The rollup the interaction was submitted in", |_, row| Some(row.submitted_rollup)),
+        column("submitted_block", "The L1 block the interaction was submitted in", |_, row| json!(row.submitted_block)),
+        column("is_async", "Whether the result came in a later L1 tx", |_, row| json!(row.is_async)),
+        column("result", "Whether the bridge call succeeded", |_, row| json!(row.result)),
+        column("result_block", "The L1 block the result was emitted in", |_, row| json!(row.result_block)),
+        column("result_hash", "The hash of the result inserted into the defi tree", |_, row| json!(row.result_hash)),
+        u256_column("finalised_rollup", "The rollup that inserted the result into the defi tree", |_, row| row.finalised_rollup),
+        u256_column("delay", "The number of rollups between submission and finalisation", |_, row| row.delay),
+        column("pending_at_block", "The last synced L1 block, if the interaction is still pending", |_, row| {
+            json!(row.pending_at_block)
+        }),
+    ]);
+
+    Table {
+        export: "export-defi-lifecycle",
+        parts: Parts::HEAD,
+        columns,
+        presets: &[
+            (
+                "default",
+                &[
+                    "interaction_nonce",
+                    "slot",
+                    "bridge_address_id",
+                    "submitted_rollup",
+                    "submitted_block",
+                    "is_async",
+                    "result",
+                    "result_block",
+                    "result_hash",
+                    "finalised_rollup",
+                    "delay",
+                    "pending_at_block",
+                ],
+            ),
+            (
+                "readable",
+                &[
+                    "interaction_nonce_decimal",
+                    "bridge_address_id_decimal",
+                    "submitted_rollup_decimal",
+                    "time",
+                    "result",
+                    "finalised_rollup_decimal",
+                    "delay_decimal",
+                ],
+            ),
+        ],
+    }
+}
+
+fn tx_count(block: &Block, proof_id: ProofId) -> Value {
+    json!(block.inner.inner_proofs.iter().filter(|tx| tx.proof_id == proof_id).count())
+}
+
+/// The fee collected in `slot`, if the slot is in use.
+fn fee_in_slot(block: &Block, slot: u64) -> Option<&FeeAsset> {
+    block.inner.header.fees().find(|fee| fee.slot == slot)
+}
+
+/// A `fee_asset_id_<slot>` and `fee_amount_<slot>` column for each of the given fee slots, empty
+/// when the slot is unused. Column names are static, so they are spelled out per slot.
+macro_rules! fee_slot_columns {
+    ($($slot:literal)*) => {
+        [$(
+            u256_column(concat!("fee_asset_id_", $slot), concat!("The asset of fee slot ", $slot), |block: &Block, _: &()| {
+                fee_in_slot(block, $slot).map(|fee| fee.asset_id)
+            }),
+            u256_column(concat!("fee_amount_", $slot), concat!("The fee collected in slot ", $slot), |block: &Block, _: &()| {
+                fee_in_slot(block, $slot).map(|fee| fee.amount)
+            }),
+        )*]
+    };
+}
+
+/// One row per rollup, so the row itself holds nothing.
+pub fn rollup_table() -> Table<()> {
+    let mut columns = block_columns();
+    columns.extend([
+        column("num_deposits", "The number of deposits", |block: &Block, _: &()| tx_count(block, ProofId::Deposit)),
+        column("num_withdrawals", "The number of withdrawals", |block, _| tx_count(block, ProofId::Withdraw)),
+        column("num_sends", "The number of private sends", |block, _| tx_count(block, ProofId::Send)),
+        column("num_accounts", "The number of account txs", |block, _| tx_count(block, ProofId::Account)),
+        column("num_defi_deposits", "The number of defi deposits", |block, _| tx_count(block, ProofId::DefiDeposit)),
+        column("num_defi_claims", "The number of defi claims", |block, _| tx_count(block, ProofId::DefiClaim)),
+        column("num_defi_interactions", "The number of bridge calls", |block, _| {
+            json!(block.inner.header.defi_interactions().count())
+        }),
+    ]);
+    columns.extend(fee_slot_columns!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15));
+
+    Table {
+        export: "export-rollups",
+        parts: Parts::TXS,
+        columns,
+        presets: &[
+            (
+                "default",
+                &[
+                    "rollup_id",
+                    "l1_tx_hash",
+                    "l1_block_number",
+                    "timestamp",
+                    "sequencer",
+                    "rollup_beneficiary",
+                    "rollup_size",
+                    "num_real_txs",
+                    "num_deposits",
+                    "num_withdrawals",
+                    "num_sends",
+                    "num_accounts",
+                    "num_defi_deposits",
+                    "num_defi_claims",
+                    "num_defi_interactions",
+                    "calldata_size",
+                    "gas_used",
+                    "effective_gas_price",
+                    "gas_cost",
+                    "fee_asset_id_0",
+                    "fee_amount_0",
+                    "fee_asset_id_1",
+                    "fee_amount_1",
+                    "fee_asset_id_2",
+                    "fee_amount_2",
+                    "fee_asset_id_3",
+                    "fee_amount_3",
+                    "fee_asset_id_4",
+                    "fee_amount_4",
+                    "fee_asset_id_5",
+                    "fee_amount_5",
+                    "fee_asset_id_6",
+                    "fee_amount_6",
+                    "fee_asset_id_7",
+                    "fee_amount_7",
+                    "fee_asset_id_8",
+                    "fee_amount_8",
+                    "fee_asset_id_9",
+                    "fee_amount_9",
+                    "fee_asset_id_10",
+                    "fee_amount_10",
+                    "fee_asset_id_11",
+                    "fee_amount_11",
+                    "fee_asset_id_12",
+                    "fee_amount_12",
+                    "fee_asset_id_13",
+                    "fee_amount_13",
+                    "fee_asset_id_14",
+                    "fee_amount_14",
+                    "fee_asset_id_15",
+                    "fee_amount_15",
+                ],
+            ),
+            (
+                "readable",
+                &[
+                    "rollup_id_decimal",
+                    "time",
+                    "l1_tx_hash",
+                    "l1_block_number",
+                    "sequencer",
+                    "rollup_beneficiary",
+                    "rollup_size_decimal",
+                    "num_real_txs_decimal",
+                    "num_deposits",
+                    "num_withdrawals",
+                    "num_sends",
+                    "num_accounts",
+                    "num_defi_deposits",
+                    "num_defi_claims",
+                    "num_defi_interactions",
+                    "calldata_size",
+                    "gas_used_decimal",
+                    "effective_gas_price_decimal",
+                    "gas_cost_decimal",
+                    "fee_asset_id_0_decimal",
+                    "fee_amount_0_decimal",
+                    "fee_asset_id_1_decimal",
+                    "fee_amount_1_decimal",
+                    "fee_asset_id_2_decimal",
+                    "fee_amount_2_decimal",
+                    "fee_asset_id_3_decimal",
+                    "fee_amount_3_decimal",
+                    "fee_asset_id_4_decimal",
+                    "fee_amount_4_decimal",
+                    "fee_asset_id_5_decimal",
+                    "fee_amount_5_decimal",
+                    "fee_asset_id_6_decimal",
+                    "fee_amount_6_decimal",
+                    "fee_asset_id_7_decimal",
+                    "fee_amount_7_decimal",
+                    "fee_asset_id_8_decimal",
+                    "fee_amount_8_decimal",
+                    "fee_asset_id_9_decimal",
+                    "fee_amount_9_decimal",
+                    "fee_asset_id_10_decimal",
+                    "fee_amount_10_decimal",
+                    "fee_asset_id_11_decimal",
+                    "fee_amount_11_decimal",
+                    "fee_asset_id_12_decimal",
+                    "fee_amount_12_decimal",
+                    "fee_asset_id_13_decimal",
+                    "fee_amount_13_decimal",
+                    "fee_asset_id_14_decimal",
+                    "fee_amount_14_decimal",
+                    "fee_asset_id_15_decimal",
+                    "fee_amount_15_decimal",
+                ],
+            ),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(columns: &[&str], preset: Option<&str>) -> ColumnSelection {
+        ColumnSelection {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            preset: preset.map(String::from),
+            list_columns: false,
+        }
+    }
+
+    fn assert_presets_resolve<C>(table: Table<C>) {
+        for (preset, columns) in table.presets.iter() {
+            for column in columns.iter() {
+                assert!(find_column(&table, column).is_some(), "{} preset {} has no column {}", table.export, preset, column);
+            }
+        }
+        for (i, column) in table.columns.iter().enumerate() {
+            assert_eq!(find_column(&table, column.name), Some((i, false)), "{} has column {} twice", table.export, column.name);
+        }
+        assert!(table.presets.iter().any(|(name, _)| *name == "default"), "{} has no default preset", table.export);
+    }
+
+    #[test]
+    fn every_preset_names_existing_columns() {
+        assert_presets_resolve(tx_table());
+        assert_presets_resolve(fee_table());
+        assert_presets_resolve(defi_table());
+        assert_presets_resolve(lifecycle_table());
+        assert_presets_resolve(rollup_table());
+    }
+
+    #[test]
+    fn default_presets_start_with_the_original_columns() {
+        let tx = Columns::select(tx_table(), &selection(&[], None)).names();
+        assert_eq!(tx[..10], [
+            "rollup_id",
+            "timestamp",
+            "proof_id",
+            "note_commitment_1",
+            "note_commitment_2",
+            "nullifier_1",
+            "nullifier_2",
+            "public_value",
+            "public_owner",
+            "public_asset_id",
+        ]);
+        let fees = Columns::select(fee_table(), &selection(&[], None)).names();
+        assert_eq!(fees[..4], ["rollup_id", "asset_id", "amount", "beneficiary"]);
+        let defi = Columns::select(defi_table(), &selection(&[], None)).names();
+        assert_eq!(defi[..12], [
+            "rollup_id",
+            "bridge_address_id",
+            "input_asset_a_id",
+            "input_asset_a_type",
+            "input_asset_b_id",
+            "input_asset_b_type",
+            "output_asset_a_id",
+            "output_asset_a_type",
+            "output_asset_b_id",
+            "output_asset_b_type",
+            "aux_data",
+            "total_input_value",
+        ]);
+    }
+
+    #[test]
+    fn only_256_bit_columns_have_decimal_forms() {
+        let table = tx_table();
+        let public_value = table.columns.iter().position(|column| column.name == "public_value").unwrap();
+        assert_eq!(find_column(&table, "public_value_decimal"), Some((public_value, true)));
+        assert_eq!(find_column(&table, "proof_id_decimal"), None);
+        assert_eq!(find_column(&table, "no_such_column"), None);
+    }
+
+    #[test]
+    fn selects_columns_by_name_or_preset() {
+        let columns = Columns::select(rollup_table(), &selection(&["rollup_id_decimal", "num_deposits"], None));
+        assert_eq!(columns.names(), vec!["rollup_id_decimal", "num_deposits"]);
+
+        let readable = Columns::select(rollup_table(), &selection(&[], Some("readable")));
+        assert_eq!(readable.names()[0], "rollup_id_decimal");
+
+        let full = Columns::select(rollup_table(), &selection(&[], Some("full")));
+        assert_eq!(full.names().len(), rollup_table().columns.len());
+    }
+
+    #[test]
+    fn only_non_default_selections_are_recorded() {
+        assert_eq!(Columns::select(fee_table(), &selection(&[], None)).option(), None);
+        assert_eq!(Columns::select(fee_table(), &selection(&[], Some("default"))).option(), None);
+        assert_eq!(
+            Columns::select(fee_table(), &selection(&["rollup_id", "gas_cost"], None)).option(),
+            Some(String::from("rollup_id,gas_cost"))
+        );
+    }
+
+    #[test]
+    fn writes_256_bit_values_as_hex_or_decimal() {
+        let mut block = Block::default();
+        block.inner.header.rollup_id = U256::from(255);
+        block.metadata.timestamp = Some(U256::from(1_661_990_400));
+        let columns = Columns::select(rollup_table(), &selection(&["rollup_id", "rollup_id_decimal", "time", "gas_cost"], None));
+
+        assert_eq!(
+            columns.values(&block, &()),
+            vec![json!("0xff"), json!("255"), json!("2022-09-01T00:00:00Z"), Value::Null]
+        );
+    }
+
+    #[test]
+    fn fees_have_a_column_per_slot() {
+        let mut block = Block::default();
+        block.inner.header.fee_slots = vec![
+            FeeAsset { slot: 0, asset_id: U256::zero(), amount: U256::from(1000) },
+            FeeAsset { slot: 1, asset_id: U256::one(), amount: U256::zero() },
+            FeeAsset { slot: 15, asset_id: U256::from(2), amount: U256::from(7) },
+        ];
+        let names = ["fee_asset_id_0", "fee_amount_0_decimal", "fee_asset_id_1", "fee_amount_1", "fee_asset_id_15", "fee_amount_15_decimal"];
+        let columns = Columns::select(rollup_table(), &selection(&names, None));
+
+        assert_eq!(
+            columns.values(&block, &()),
+            vec![json!("0x0"), json!("1000"), Value::Null, Value::Null, json!("0x2"), json!("7")]
+        );
+        assert!(find_column(&rollup_table(), "fee_asset_id_16").is_none());
+    }
+}
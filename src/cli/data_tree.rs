@@ -0,0 +1,261 @@
+use crate::cli::export::export_progress_bar;
+use crate::store::{BlockStore, Parts};
+use crate::types::block::Block;
+use crate::types::data_tree::DataTree;
+use crate::types::pedersen::{field, word};
+use ark_bn254::Fr;
+use ark_ff::Zero;
+use ethers::types::{H256, U256};
+use indicatif::ProgressBar;
+
+/// The leaves a rollup inserts: two per tx slot, padding included, where the slots without a tx are zero.
+fn leaves(block: &Block) -> Result<Vec<Fr>, String> {
+    let rollup_id = block.inner.header.rollup_id;
+    let rollup_size = usize::try_from(block.inner.header.rollup_size)
+        .map_err(|_| format!("Rollup {} has a rollup size of {}", rollup_id, block.inner.header.rollup_size))?;
+    let mut leaves = vec![Fr::zero(); 2 * rollup_size];
+
+    for tx in block.inner.inner_proofs.iter() {
+        let slot = tx.tx_position as usize;
+        if slot >= rollup_size {
+            return Err(format!("Rollup {} has a tx at position {}, past its {} slots", rollup_id, slot, rollup_size));
+        }
+        for (leaf, commitment) in [(2 * slot, tx.note_commitment_1), (2 * slot + 1, tx.note_commitment_2)] {
+            leaves[leaf] = field(&commitment)
+                .ok_or_else(|| format!("Note commitment {:?} of rollup {} is not a field element", commitment, rollup_id))?;
+        }
+    }
+    Ok(leaves)
+}
+
+fn check_root(rollup_id: U256, name: &str, stored: H256, tree: &DataTree) -> Result<(), String> {
+    let rebuilt = word(&tree.root());
+    match stored == rebuilt {
+        true => Ok(()),
+        false => Err(format!("Rollup {}: {} is {:?}, the rebuilt tree has {:?}", rollup_id, name, stored, rebuilt)),
+    }
+}
+
+/// Inserts the leaves of a rollup, which has to start where the tree ends, and checks the roots
+/// before and after.
+fn insert(tree: &mut DataTree, block: &Block) -> Result<(), String> {
+    let header = &block.inner.header;
+    if header.data_start_index != U256::from(tree.size()) {
+        return Err(match tree.size() {
+            0 => format!(
+                "Rollup {} starts at leaf {}, the leaves before it aren't in any stored rollup",
+                header.rollup_id, header.data_start_index
+            ),
+            size => format!(
+                "Rollup {} starts at leaf {}, while the rollups before it end at leaf {}, run `ac-gobbler verify-chain` to find what's missing",
+                header.rollup_id, header.data_start_index, size
+            ),
+        });
+    }
+    check_root(header.rollup_id, "old_data_root", header.old_data_root, tree)?;
+    tree.append(leaves(block)?);
+    check_root(header.rollup_id, "new_data_root", header.new_data_root, tree)
+}
+
+/// Inserts the note commitments of every stored rollup in order, checking the root of the tree
+/// against `old_data_root` before and `new_data_root` after each rollup. Hands every block to
+/// `inserted` once its leaves are in. Stops at the first rollup that doesn't line up, as every root
+/// after it would differ as well.
+fn rebuild(store: &dyn BlockStore, pb: &ProgressBar, inserted: &mut dyn FnMut(&Block)) -> Result<(DataTree, u64), String> {
+    let mut tree = DataTree::default();
+    let mut rollups = 0;
+    let mut result = Ok(());
+
+    store
+        .for_each_block(0..=u64::MAX, Parts::TXS, &mut |block| {
+            pb.inc(1);
+            if result.is_err() {
+                return;
+            }
+            result = insert(&mut tree, &block).map(|_| {
+                inserted(&block);
+                rollups += 1;
+            });
+        })
+        .map_err(|err| err.to_string())?;
+    result?;
+    Ok((tree, rollups))
+}
+
+/// Rebuilds the data tree from the stored note commitments and checks every data root in the
+/// headers against it. Returns false at the first root that doesn't match.
+pub fn verify_data_tree(store: &dyn BlockStore) -> bool {
+    let count = store.rollup_ids(0..=u64::MAX).len();
+    if count == 0 {
+        println!("No rollups stored, nothing to verify");
+        return true;
+    }
+
+    let pb = export_progress_bar(count as u64, "rollups inserted");
+    let rebuilt = rebuild(store, &pb, &mut |_| ());
+    pb.finish();
+    match rebuilt {
+        Ok((tree, rollups)) => {
+            println!(
+                "Rebuilt the data tree from {} rollups in {:.2} seconds: {} leaves, every root matches, ending at {:?}",
+                rollups,
+                pb.elapsed().as_secs_f64(),
+                tree.size(),
+                word(&tree.root())
+            );
+            true
+        }
+        Err(err) => {
+            println!("{}", err);
+            false
+        }
+    }
+}
+
+/// Prints the sibling path of `commitment` in the data tree after the last stored rollup, rebuilding
+/// the tree to find it. Returns false if the commitment isn't stored or the tree doesn't match the
+/// headers, as the path would then lead to a root that isn't on L1.
+pub fn merkle_path(store: &dyn BlockStore, commitment: H256) -> bool {
+    let leaf = match field(&commitment).filter(|leaf| !leaf.is_zero()) {
+        Some(leaf) => leaf,
+        None => {
+            println!("{:?} is not a note commitment", commitment);
+            return false;
+        }
+    };
+
+    let mut found = None;
+    let pb = export_progress_bar(store.rollup_ids(0..=u64::MAX).len() as u64, "rollups inserted");
+    let rebuilt = rebuild(store, &pb, &mut |block| {
+        if found.is_some() {
+            return;
+        }
+        found = block.inner.inner_proofs.iter().find_map(|tx| {
+            let index = if tx.note_commitment_1 == commitment {
+                tx.note_commitment_1_index
+            } else if tx.note_commitment_2 == commitment {
+                tx.note_commitment_2_index
+            } else {
+                return None;
+            };
+            Some((block.inner.header.rollup_id, index))
+        });
+    });
+    pb.finish();
+
+    let tree = match rebuilt {
+        Ok((tree, _)) => tree,
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+    let (rollup_id, index) = match found {
+        Some(found) => found,
+        None => {
+            println!("Note commitment {:?} is not in any stored rollup", commitment);
+            return false;
+        }
+    };
+
+    // The leaf index comes from the stored tx, so check it is where the tree has the commitment.
+    let path = tree.path(index).unwrap();
+    if tree.root_of_path(leaf, index, &path) != tree.root() {
+        println!("Note commitment {:?} is stored as leaf {}, which the rebuilt tree doesn't hold", commitment, index);
+        return false;
+    }
+
+    println!("Note commitment {:?} is leaf {} of the data tree, inserted by rollup {}", commitment, index, rollup_id);
+    println!("Root {:?} after {} leaves", word(&tree.root()), tree.size());
+    println!("Siblings from the leaf up:");
+    for (height, sibling) in path.iter().enumerate() {
+        println!("{:>2} {:?}", height, word(sibling));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::WriteBatch;
+    use crate::types::transaction::{InnerProofData, ProofId};
+
+    /// Two rollups of two slots each, the second with a padding slot, and their roots.
+    fn blocks() -> Vec<Block> {
+        let mut tree = DataTree::default();
+        let mut blocks = vec![];
+        for (rollup_id, txs) in [(0u64, 2u64), (1, 1)] {
+            let mut block = Block::default();
+            block.inner.header.rollup_id = U256::from(rollup_id);
+            block.inner.header.rollup_size = U256::from(2);
+            block.inner.header.data_start_index = U256::from(tree.size());
+            for position in 0..txs {
+                let mut tx = InnerProofData {
+                    proof_id: ProofId::Send,
+                    note_commitment_1: H256::from_low_u64_be(10 * rollup_id + 2 * position + 1),
+                    note_commitment_2: H256::from_low_u64_be(10 * rollup_id + 2 * position + 2),
+                    ..Default::default()
+                };
+                tx.set_position(block.inner.header.data_start_index, position).unwrap();
+                block.inner.inner_proofs.push(tx);
+            }
+            block.inner.header.old_data_root = word(&tree.root());
+            tree.append(leaves(&block).unwrap());
+            block.inner.header.new_data_root = word(&tree.root());
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn store(dir: &std::path::Path, blocks: Vec<Block>) -> SqliteStore {
+        let store = SqliteStore::open(&dir.join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for block in blocks {
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+        store
+    }
+
+    #[test]
+    fn padding_slots_insert_zero_leaves() {
+        let blocks = blocks();
+        let leaves = leaves(&blocks[1]).unwrap();
+        assert_eq!(leaves, vec![Fr::from(11u64), Fr::from(12u64), Fr::zero(), Fr::zero()]);
+    }
+
+    #[test]
+    fn rebuilds_the_roots_of_the_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), blocks());
+        assert!(verify_data_tree(&store));
+        assert!(merkle_path(&store, H256::from_low_u64_be(12)));
+        assert!(!merkle_path(&store, H256::from_low_u64_be(13)));
+
+        let (tree, rollups) = rebuild(&store, &ProgressBar::hidden(), &mut |_| ()).unwrap();
+        assert_eq!((tree.size(), rollups), (8, 2));
+        let path = tree.path(5).unwrap();
+        assert_eq!(tree.root_of_path(Fr::from(12u64), 5, &path), tree.root());
+    }
+
+    #[test]
+    fn stops_at_the_first_root_that_differs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blocks = blocks();
+        blocks[1].inner.header.new_data_root = H256::repeat_byte(1);
+        let store = store(dir.path(), blocks);
+
+        assert!(!verify_data_tree(&store));
+        let err = rebuild(&store, &ProgressBar::hidden(), &mut |_| ()).err().unwrap();
+        assert!(err.starts_with("Rollup 1: new_data_root is 0x0101"), "{}", err);
+    }
+
+    #[test]
+    fn refuses_rollups_that_dont_start_where_the_tree_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), blocks().split_off(1));
+        let err = rebuild(&store, &ProgressBar::hidden(), &mut |_| ()).err().unwrap();
+        assert_eq!(err, "Rollup 1 starts at leaf 4, the leaves before it aren't in any stored rollup");
+    }
+}
@@ -0,0 +1,388 @@
+use crate::bindings::rollup_processor::ProcessRollupCall;
+use crate::cli::annotate::{annotate, fetch_proof_data, print_annotated};
+use crate::cli::interactions::{format_chain, origins_from_db, provenance_chain};
+use crate::types::block::{Block, InnerBlock};
+use crate::types::layout::{Layout, AZTEC_CONNECT};
+use crate::types::rollup_view::RollupView;
+use ethers::{
+    abi::AbiDecode,
+    contract::EthCall,
+    providers::{Http, Provider},
+    types::{Address, H256, U256},
+    utils::{hex, to_checksum},
+};
+use crate::store::{BlockStore, Index, StoreError};
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Rust debug output of the decoded structs
+    #[default]
+    Debug,
+    /// Numbers in decimal, addresses checksummed and hashes in hex
+    Json,
+    /// Same values as `json`
+    Yaml,
+    /// Compact human readable tables, with numbers in decimal and checksummed addresses
+    Table,
+}
+
+/// The ways a stored block can be looked up.
+#[derive(Debug, Clone, Copy)]
+pub enum BlockSelector {
+    RollupId(u64),
+    TxHash(H256),
+    BlockNumber(u64),
+}
+
+pub fn find_block(store: &dyn BlockStore, selector: BlockSelector) -> Result<Option<Block>, StoreError> {
+    match selector {
+        BlockSelector::RollupId(rollup_id) => store.get_block(rollup_id),
+        BlockSelector::TxHash(tx_hash) => match store.lookup(Index::L1TxHash, tx_hash.as_bytes()).first() {
+            Some(location) => store.get_block(location.rollup_id),
+            None => Ok(None),
+        },
+        BlockSelector::BlockNumber(block_number) => store.get_block_by_block_number(block_number),
+    }
+}
+
+/// Fields serialized as hex that hold hashes or roots rather than numbers.
+const HASH_FIELDS: [&str; 17] = [
+    "old_data_root",
+    "new_data_root",
+    "old_null_root",
+    "new_null_root",
+    "old_data_roots_root",
+    "new_data_roots_root",
+    "old_defi_root",
+    "new_defi_root",
+    "prev_defi_interaction_hash_slots",
+    "prev_defi_interaction_hash",
+    "next_expected_defi_hashes",
+    "note_commitment_1",
+    "note_commitment_2",
+    "nullifier_1",
+    "nullifier_2",
+    "tx_hash",
+    "public_input_hash",
+];
+
+const ADDRESS_FIELDS: [&str; 3] = ["rollup_beneficiary", "public_owner", "sequencer"];
+
+/// Rewrites the hex values of a serialized block for reading: numbers become decimal strings, so
+/// `jq` doesn't round 256 bit values, and addresses are checksummed. Hashes stay hex.
+fn readable(value: &mut serde_json::Value, field: &str) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (field, value) in fields.iter_mut() {
+                readable(value, field);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                readable(item, field);
+            }
+        }
+        serde_json::Value::String(text) if text.starts_with("0x") && !HASH_FIELDS.contains(&field) => {
+            if ADDRESS_FIELDS.contains(&field) {
+                if let Ok(address) = text.parse::<Address>() {
+                    *text = to_checksum(&address, None);
+                }
+            } else if let Ok(number) = U256::from_str_radix(&text[2..], 16) {
+                *text = number.to_string();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn print_serialized<T: Serialize + std::fmt::Debug>(value: &T, format: OutputFormat) {
+    let readable_value = || {
+        let mut value = serde_json::to_value(value).unwrap();
+        readable(&mut value, "");
+        value
+    };
+    match format {
+        OutputFormat::Debug => println!("{:#?}", value),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&readable_value()).unwrap()),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&readable_value()).unwrap()),
+        OutputFormat::Table => unreachable!("tables are not printed from serialized values"),
+    }
+}
+
+fn address(address: &Address) -> String {
+    to_checksum(address, None)
+}
+
+pub fn print_inner_block_table(inner: &InnerBlock) {
+    let header = &inner.header;
+
+    println!("Rollup {}", header.rollup_id);
+    println!("  {:<22} {}", "rollup size", header.rollup_size);
+    println!("  {:<22} {}", "real txs", inner.num_real_txs);
+    println!("  {:<22} {}", "data start index", header.data_start_index);
+    println!("  {:<22} {}", "rollup beneficiary", address(&header.rollup_beneficiary));
+    for (name, old, new) in [
+        ("data root", header.old_data_root, header.new_data_root),
+        ("null root", header.old_null_root, header.new_null_root),
+        ("data roots root", header.old_data_roots_root, header.new_data_roots_root),
+        ("defi root", header.old_defi_root, header.new_defi_root),
+    ] {
+        println!("  {:<22} {:?} -> {:?}", name, old, new);
+    }
+
+    println!();
+    println!("Transactions");
+    println!(
+        "  {:>5} {:<12} {:>11} {:>11} {:>28} {:>8} {:<42}",
+        "pos", "proof id", "leaf 1", "leaf 2", "public value", "asset", "public owner"
+    );
+    for tx in inner.inner_proofs.iter() {
+        println!(
+            "  {:>5} {:<12} {:>11} {:>11} {:>28} {:>8} {:<42}",
+            tx.tx_position,
+            format!("{:?}", tx.proof_id),
+            tx.note_commitment_1_index,
+            tx.note_commitment_2_index,
+            tx.public_value.to_string(),
+            tx.public_asset_id.to_string(),
+            address(&tx.public_owner)
+        );
+    }
+
+    println!();
+    println!("Fees");
+    println!("  {:>4} {:>8} {:>28}", "slot", "asset", "amount");
+    for fee in header.fees() {
+        println!("  {:>4} {:>8} {:>28}", fee.slot, fee.asset_id.to_string(), fee.amount.to_string());
+    }
+
+    println!();
+    println!("DeFi interactions");
+    println!(
+        "  {:>4} {:>8} {:>6} {:>22} {:>22} {:>22} {:>22} {:>28}",
+        "slot", "nonce", "bridge", "input a", "input b", "output a", "output b", "total input value"
+    );
+    for interaction in header.defi_interactions() {
+        println!(
+            "  {:>4} {:>8} {:>6} {:>22} {:>22} {:>22} {:>22} {:>28}",
+            interaction.slot,
+            interaction.interaction_nonce.to_string(),
+            interaction.bridge_address_id().to_string(),
+            interaction.input_asset_a().to_string(),
+            interaction.input_asset_b().to_string(),
+            interaction.output_asset_a().to_string(),
+            interaction.output_asset_b().to_string(),
+            interaction.total_input_value.to_string()
+        );
+    }
+}
+
+pub fn print_inner_block(inner: &InnerBlock, format: OutputFormat) {
+    match format {
+        OutputFormat::Table => print_inner_block_table(inner),
+        _ => print_serialized(inner, format),
+    }
+}
+
+pub fn print_block(block: &Block, format: OutputFormat) {
+    match format {
+        OutputFormat::Table => {
+            println!("L1 transaction");
+            println!("  {:<22} {:?}", "tx hash", block.metadata.tx_hash);
+            println!("  {:<22} {}", "block number", block.metadata.block_number);
+            if let Some(timestamp) = block.metadata.timestamp {
+                println!("  {:<22} {}", "timestamp", timestamp);
+            }
+            if let Some(calldata_size) = block.metadata.calldata_size {
+                println!("  {:<22} {}", "calldata size", calldata_size);
+            }
+            if let Some(gas_used) = block.metadata.gas_used {
+                println!("  {:<22} {}", "gas used", gas_used);
+            }
+            if let Some(gas_cost) = block.metadata.gas_cost() {
+                println!("  {:<22} {}", "gas cost (wei)", gas_cost);
+            }
+            println!("  {:<22} {}", "sequencer", address(&block.sequencer));
+            println!();
+            print_inner_block_table(&block.inner);
+        }
+        _ => print_serialized(block, format),
+    }
+}
+
+/// Prints the selected block, or its annotated calldata, and returns false if no stored block
+/// matches or the calldata can't be fetched or decoded.
+pub async fn decode_block(
+    store: &dyn BlockStore,
+    selector: BlockSelector,
+    format: OutputFormat,
+    annotate_rpc_url: Option<String>,
+) -> bool {
+    let block = match find_block(store, selector) {
+        Ok(Some(block)) => block,
+        Ok(None) => {
+            println!("No stored block matches {:?}", selector);
+            return false;
+        }
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+
+    if let Some(rpc_url) = annotate_rpc_url {
+        let client = Provider::<Http>::try_from(rpc_url).unwrap();
+        let calldata = match fetch_proof_data(&client, block.metadata.tx_hash).await {
+            Ok(calldata) => calldata,
+            Err(err) => {
+                println!("{}", err);
+                return false;
+            }
+        };
+        let layout = Layout::for_version(block.metadata.layout_version.unwrap_or_default());
+        return match RollupView::new(&calldata, layout).and_then(|view| annotate(&view)) {
+            Ok(annotations) => {
+                print_annotated(&calldata, &annotations);
+                true
+            }
+            Err(err) => {
+                println!("Failed to decode the calldata of rollup {}: {}", block.inner.header.rollup_id, err);
+                false
+            }
+        };
+    }
+
+    print_block(&block, format);
+
+    // Structured output is meant to be piped, so keep it free of anything else.
+    if let OutputFormat::Debug | OutputFormat::Table = format {
+        for interaction in block.inner.header.defi_interactions() {
+            let chain = provenance_chain(interaction, |nonce| origins_from_db(store, nonce));
+            if !chain.is_empty() {
+                println!("Interaction {} uses virtual assets from: {}", interaction.interaction_nonce, format_chain(&chain));
+            }
+        }
+    }
+    true
+}
+
+/// Reads hex calldata from a file, from the argument itself, or from stdin if no input or `-` is given.
+pub fn read_hex_input(input: Option<String>) -> Result<Vec<u8>, String> {
+    let text = match input {
+        Some(input) if input != "-" => match Path::new(&input).is_file() {
+            true => std::fs::read_to_string(&input).map_err(|err| format!("Failed to read {}: {}", input, err))?,
+            false => input,
+        },
+        _ => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|err| format!("Failed to read calldata from stdin: {}", err))?;
+            text
+        }
+    };
+
+    let text: String = text.split_whitespace().collect();
+    hex::decode(text.strip_prefix("0x").unwrap_or(&text)).map_err(|err| format!("Calldata is not valid hex: {}", err))
+}
+
+/// Returns the rollup calldata, unwrapping it from a `processRollup` call if the input is one.
+pub fn proof_data_of(calldata: Vec<u8>) -> Result<Vec<u8>, String> {
+    match calldata.starts_with(&ProcessRollupCall::selector()) {
+        true => ProcessRollupCall::decode(&calldata)
+            .map(|call| call.proof_data.to_vec())
+            .map_err(|err| format!("Failed to decode processRollup call: {}", err)),
+        false => Ok(calldata),
+    }
+}
+
+/// Decodes and prints the calldata, or prints why it can't be decoded and returns false.
+pub fn decode_raw(input: Option<String>, format: OutputFormat, annotated: bool) -> bool {
+    let calldata = match read_hex_input(input).and_then(proof_data_of) {
+        Ok(calldata) => calldata,
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+
+    let view = RollupView::new(&calldata, &AZTEC_CONNECT);
+    let decoded = match annotated {
+        true => view.and_then(|view| annotate(&view)).map(|annotations| print_annotated(&calldata, &annotations)),
+        false => view
+            .and_then(|view| InnerBlock::try_from(&view))
+            .map(|inner| print_inner_block(&inner, format)),
+    };
+    match decoded {
+        Ok(()) => true,
+        Err(err) => {
+            println!("Failed to decode the calldata: {}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rollup_view::tests::calldata_with_txs;
+    use crate::types::transaction::InnerProofData;
+
+    #[test]
+    fn structured_output_has_decimal_numbers_and_checksummed_addresses() {
+        let mut block = Block::default();
+        block.inner.header.rollup_id = U256::from(1234);
+        block.inner.header.new_data_root = H256::repeat_byte(0xab);
+        block.inner.header.prev_defi_interaction_hash_slots = vec![H256::repeat_byte(1)];
+        block.sequencer = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359".parse().unwrap();
+        block.inner.inner_proofs.push(InnerProofData {
+            public_value: U256::MAX,
+            ..Default::default()
+        });
+        let mut value = serde_json::to_value(&block).unwrap();
+        readable(&mut value, "");
+
+        assert_eq!(value["inner"]["header"]["rollup_id"], "1234");
+        assert_eq!(value["inner"]["header"]["new_data_root"], format!("{:?}", H256::repeat_byte(0xab)));
+        assert_eq!(value["inner"]["header"]["prev_defi_interaction_hash_slots"][0], format!("{:?}", H256::repeat_byte(1)));
+        assert_eq!(value["sequencer"], "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359");
+        assert_eq!(value["inner"]["inner_proofs"][0]["public_value"], U256::MAX.to_string());
+        assert_eq!(value["inner"]["inner_proofs"][0]["public_owner"], format!("{:?}", Address::zero()));
+    }
+
+    #[test]
+    fn reads_hex_with_or_without_prefix() {
+        assert_eq!(read_hex_input(Some(String::from("0x0a0b"))).unwrap(), vec![10, 11]);
+        assert_eq!(read_hex_input(Some(String::from("0a 0b\n"))).unwrap(), vec![10, 11]);
+        assert!(read_hex_input(Some(String::from("0xzz"))).is_err());
+        assert!(read_hex_input(Some(String::from("0x0a0"))).is_err());
+    }
+
+    #[test]
+    fn refuses_truncated_process_rollup_calls() {
+        let mut calldata = ProcessRollupCall::selector().to_vec();
+        calldata.extend_from_slice(&[0; 10]);
+        assert!(proof_data_of(calldata).is_err());
+        assert_eq!(proof_data_of(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn decodes_calldata_with_the_chosen_layout() {
+        let input = Some(hex::encode(calldata_with_txs(&[0, 0])));
+        assert!(decode_raw(input, OutputFormat::Table, false));
+        assert!(!decode_raw(Some(hex::encode(calldata_with_txs(&[9]))), OutputFormat::Table, false));
+    }
+
+    #[test]
+    fn refuses_rollup_ids_whose_interaction_nonces_overflow() {
+        let mut calldata = calldata_with_txs(&[0]);
+        calldata[..32].fill(0xff);
+        assert!(!decode_raw(Some(hex::encode(&calldata)), OutputFormat::Json, false));
+
+        let view = RollupView::new(&calldata, &AZTEC_CONNECT).unwrap();
+        assert_eq!(InnerBlock::try_from(&view).unwrap_err().offset, 0);
+    }
+}
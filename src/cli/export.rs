@@ -1,195 +1,394 @@
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
 use std::path::Path;
-use ethers::types::{Address, H256, U256};
-use indicatif::{ProgressBar, ProgressStyle};
-use microkv::MicroKV;
-use crate::types::block::{Block, FeeAsset};
-use csv::Writer;
+use ethers::types::{H256, U256};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use crate::store::{BlockStore, Parts};
+use crate::types::block::Block;
+use crate::types::defi_interaction::DefiInteractionResult;
+use csv::{Writer, WriterBuilder};
 use crate::types::transaction::ProofId;
 use serde::{Deserialize, Serialize};
-use crate::types::defi_interaction::{AssetType, DefiInteraction};
+use serde_json::Value;
+use ethers::types::U64;
+use crate::cli::interactions::{format_chain, origins_from_db, provenance_chain};
+use crate::cli::filter::{ExportFilter, RowFilter};
+use crate::cli::manifest::{Increment, Manifest};
+use crate::cli::columns::{self, ColumnSelection, Columns, DefiRow};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub struct ExportInnerProofData {
-    pub rollup_id: U256,
-    pub timestamp: U256,
-    pub proof_id: ProofId,
-    pub note_commitment_1: H256,
-    pub note_commitment_2: H256,
-    pub nullifier_1: H256,
-    pub nullifier_2: H256,
-    pub public_value: U256,
-    pub public_owner: Address,
-    pub public_asset_id: U256,
+pub struct ExportDefiLifecycle {
+    pub interaction_nonce: U256,
+    pub slot: u64,
+    pub bridge_address_id: U256,
+    pub submitted_rollup: U256,
+    pub submitted_block: U64,
+    pub is_async: Option<bool>,
+    pub result: Option<bool>,
+    pub result_block: Option<U64>,
+    pub result_hash: Option<H256>,
+    pub finalised_rollup: Option<U256>,
+    pub delay: Option<U256>,
+    pub pending_at_block: Option<U64>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct ExportFeeAsset {
-    pub rollup_id: U256,
-    pub asset_id: U256,
-    pub amount: U256,
-    pub beneficiary: Address,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct ExportDefiInteraction {
-    pub rollup_id: U256,
-    pub bridge_address_id: U256,
-    pub input_asset_a_id: U256,
-    pub input_asset_a_type: AssetType,
-    pub input_asset_b_id: U256,
-    pub input_asset_b_type: AssetType,
-    pub output_asset_a_id: U256,
-    pub output_asset_a_type: AssetType,
-    pub output_asset_b_id: U256,
-    pub output_asset_b_type: AssetType,
-    pub aux_data: U256,
-    pub total_input_value: U256,
-}
-
-pub fn export_transactions_csv(db: &MicroKV, path: String, l1_only: bool) {
-    let path = Path::new(&path);
-    match path.parent() {
-        None => (),
-        Some(parent) => match fs::create_dir_all(parent) {
-            Ok(_) => (),
-            Err(_) => todo!(),
-        },
+impl ExportFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
     }
+}
 
-    let mut keys = db.keys().unwrap();
-    // Not robust, but works for now
-    keys.sort_by_key(|k| k.parse::<u64>().unwrap());
-    let mut wtr = Writer::from_path(path).unwrap();
-    let mut tx_count = 0;
+enum Sink {
+    Csv(Box<Writer<Box<dyn Write>>>),
+    Jsonl(BufWriter<Box<dyn Write>>),
+}
 
-    let pb = ProgressBar::new(keys.len() as u64);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] blocks exported {pos}/{len}")
-        .unwrap()
-        .progress_chars("#>-"));
+/// Writes exported rows as CSV or JSON lines to a file, or to stdout if the path is `-`. When
+/// appending to an existing file, the CSV header isn't written again.
+pub struct RowWriter {
+    sink: Sink,
+    to_stdout: bool,
+    columns: Vec<String>,
+}
 
-    for key in keys {
-        let block = db.get::<Block>(key).unwrap().unwrap();
-        for tx in block.inner.inner_proofs {
-            if l1_only && tx.proof_id != ProofId::Deposit && tx.proof_id != ProofId::Withdraw {
-                continue;
+impl RowWriter {
+    pub fn create(path: &str, format: ExportFormat, append: bool, columns: Vec<String>) -> Self {
+        let to_stdout = path == "-";
+        let mut headers = !append;
+        let out: Box<dyn Write> = if to_stdout {
+            Box::new(io::stdout().lock())
+        } else {
+            let path = Path::new(path);
+            if let Some(parent) = path.parent() {
+                if let Err(err) = fs::create_dir_all(parent) {
+                    eprintln!("Failed to create {}: {}", parent.display(), err);
+                    std::process::exit(1);
+                }
             }
-
-            let tx = ExportInnerProofData {
-                rollup_id: block.inner.header.rollup_id,
-                timestamp: block.metadata.timestamp.unwrap_or_default(),
-                proof_id: tx.proof_id,
-                note_commitment_1: tx.note_commitment_1,
-                note_commitment_2: tx.note_commitment_2,
-                nullifier_1: tx.nullifier_1,
-                nullifier_2: tx.nullifier_2,
-                public_value: tx.public_value,
-                public_owner: tx.public_owner,
-                public_asset_id: tx.public_asset_id,
+            let file = if append && path.exists() {
+                OpenOptions::new().append(true).open(path)
+            } else {
+                headers = true;
+                File::create(path)
             };
+            match file {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    eprintln!("Failed to open {}: {}", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        };
 
-            wtr.serialize(tx).unwrap();
-            tx_count += 1;
+        let sink = match format {
+            ExportFormat::Csv => Sink::Csv(Box::new(WriterBuilder::new().from_writer(out))),
+            ExportFormat::Jsonl => Sink::Jsonl(BufWriter::new(out)),
+        };
+        let mut wtr = RowWriter { sink, to_stdout, columns };
+        if headers {
+            if let Sink::Csv(csv) = &mut wtr.sink {
+                let result = csv.write_record(&wtr.columns).map_err(csv_error);
+                wtr.check(result);
+            }
         }
-        pb.inc(1);
+        wtr
     }
-    pb.finish();
 
-    println!("Exported {} transactions in {:.2} seconds", tx_count, pb.elapsed().as_secs_f64());
-}
+    /// Writes a row holding a value for every column. Strings are written unquoted in CSV and
+    /// nulls as empty fields, like serde does for the fields of a struct.
+    pub fn write(&mut self, values: Vec<Value>) {
+        let result = match &mut self.sink {
+            Sink::Csv(wtr) => {
+                let record = values.iter().map(|value| match value {
+                    Value::Null => String::new(),
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                });
+                wtr.write_record(record).map_err(csv_error)
+            }
+            // serde_json sorts the keys of its maps, so the object is put together in column order.
+            Sink::Jsonl(wtr) => {
+                let fields: Vec<String> = self
+                    .columns
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(column, value)| format!("{}:{}", Value::from(column.as_str()), value))
+                    .collect();
+                writeln!(wtr, "{{{}}}", fields.join(","))
+            }
+        };
+        self.check(result);
+    }
 
-pub fn export_fees_csv(db: &MicroKV, path: String) {
-    let path = Path::new(&path);
-    match path.parent() {
-        None => (),
-        Some(parent) => match fs::create_dir_all(parent) {
+    fn check(&self, result: io::Result<()>) {
+        match result {
             Ok(_) => (),
-            Err(_) => todo!(),
-        },
+            // The reader went away, like `head` does once it has enough lines.
+            Err(err) if self.to_stdout && err.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
+            Err(err) => {
+                eprintln!("Failed to write export: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn finish(mut self) {
+        let result = match &mut self.sink {
+            Sink::Csv(wtr) => wtr.flush(),
+            Sink::Jsonl(wtr) => wtr.flush(),
+        };
+        self.check(result);
     }
+}
 
-    let mut keys = db.keys().unwrap();
-    // Not robust, but works for now
-    keys.sort_by_key(|k| k.parse::<u64>().unwrap());
-    let mut wtr = Writer::from_path(path).unwrap();
-    let mut tx_count = 0;
+fn csv_error(err: csv::Error) -> io::Error {
+    match err.into_kind() {
+        csv::ErrorKind::Io(err) => err,
+        kind => io::Error::other(format!("{:?}", kind)),
+    }
+}
 
-    let pb = ProgressBar::new(keys.len() as u64);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] blocks exported {pos}/{len}")
+/// A progress bar over `len` blocks on stderr, hidden when stderr isn't a terminal so it doesn't
+/// end up in logs of scheduled jobs.
+pub fn export_progress_bar(len: u64, message: &str) -> ProgressBar {
+    let pb = ProgressBar::new(len);
+    if !io::stderr().is_terminal() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    pb.set_style(ProgressStyle::with_template(&format!("{{spinner:.green}} [{{elapsed_precise}}] [{{wide_bar:.cyan/blue}}] {} {{pos}}/{{len}}", message))
         .unwrap()
         .progress_chars("#>-"));
+    pb
+}
 
-    for key in keys {
-        let block = db.get::<Block>(key).unwrap().unwrap();
+/// Where and how the rows of an export are written.
+pub struct ExportTarget {
+    pub path: String,
+    pub format: ExportFormat,
+    pub incremental: bool,
+    pub since: Option<u64>,
+    pub columns: ColumnSelection,
+}
 
-        let rollup_id = block.inner.header.rollup_id;
-        let beneficiary = block.inner.header.rollup_beneficiary;
-
-        // For every fee in the block, create a new ExportFeeAsset and serialize it
-        block.inner.header.fees.iter().for_each(|fee: &FeeAsset| {
-            let export_fee = ExportFeeAsset {
-                rollup_id: rollup_id.clone(),
-                asset_id: fee.asset_id,
-                amount: fee.amount,
-                beneficiary: beneficiary.clone(),
-            };
-            wtr.serialize(export_fee).unwrap();
-            tx_count += 1;
-        });
+impl ExportTarget {
+    fn begin<C>(&self, export: &str, filter: &ExportFilter, columns: &Columns<C>) -> Increment {
+        Increment::begin(Manifest::path_for_file(&self.path), export, self.format.name(), options(filter, columns), self.incremental, self.since)
+    }
+
+    /// Prints to stderr when the rows go to stdout.
+    fn report(&self, message: String) {
+        if self.path == "-" {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
     }
+}
+
+fn options<C>(filter: &ExportFilter, columns: &Columns<C>) -> BTreeMap<String, String> {
+    let mut options = filter.options();
+    if let Some(names) = columns.option() {
+        options.insert(String::from("columns"), names);
+    }
+    options
+}
+
+/// Exports stop before a rollup missing from the store, so the manifest never skips it.
+pub fn missing_rollup(rollup_id: u64) -> String {
+    format!(
+        "Stopped before rollup {}, which is missing from the store. Run `ac-gobbler check-db --repair` and export again to continue",
+        rollup_id
+    )
+}
+
+/// Reads the blocks within the filter and increment, writes the selected columns of the rows
+/// `rows` makes of every block that matches the filter, and updates the manifest. Returns false,
+/// leaving the manifest as it was, if a block can't be read.
+fn export_rows<C>(
+    store: &dyn BlockStore,
+    target: &ExportTarget,
+    increment: Increment,
+    filter: &ExportFilter,
+    columns: &Columns<C>,
+    noun: &str,
+    mut rows: impl FnMut(&Block) -> Vec<C>,
+) -> bool {
+    let (range, missing) = increment.rollup_range(store, filter);
+    let mut wtr = RowWriter::create(&target.path, target.format, increment.appending(), columns.names());
+    let mut row_count = 0;
+    let mut last_rollup_id = None;
+
+    let pb = export_progress_bar(store.rollup_ids(range.clone()).len() as u64, "blocks exported");
+    let read = store.for_each_block(range, columns.parts(), &mut |block| {
+        last_rollup_id = Some(block.inner.header.rollup_id.as_u64());
+        if filter.matches_block(&block) {
+            for row in rows(&block) {
+                wtr.write(columns.values(&block, &row));
+                row_count += 1;
+            }
+        }
+        pb.inc(1);
+    });
     pb.finish();
+    wtr.finish();
+    if let Err(err) = read {
+        target.report(err.to_string());
+        return false;
+    }
 
-    println!("Exported {} fee transactions in {:.2} seconds", tx_count, pb.elapsed().as_secs_f64());
+    let up_to_date = match (increment.after, last_rollup_id) {
+        (Some(after), None) => format!(", already up to date with rollup {}", after),
+        _ => String::new(),
+    };
+    target.report(format!("Exported {} {} in {:.2} seconds{}", row_count, noun, pb.elapsed().as_secs_f64(), up_to_date));
+    if let Some(missing) = missing {
+        target.report(missing_rollup(missing));
+    }
+    increment.finish(last_rollup_id, row_count);
+    true
 }
 
-pub fn export_defi_csv(db: &MicroKV, path: String) {
-    let path = Path::new(&path);
-    match path.parent() {
-        None => (),
-        Some(parent) => match fs::create_dir_all(parent) {
-            Ok(_) => (),
-            Err(_) => todo!(),
-        },
+pub fn export_transactions(store: &dyn BlockStore, target: ExportTarget, mut filter: ExportFilter, l1_only: bool) -> bool {
+    if l1_only {
+        filter.proof_ids = vec![ProofId::Deposit, ProofId::Withdraw];
     }
+    filter.check("export-txs", &[RowFilter::ProofId, RowFilter::AssetId, RowFilter::Owner]);
 
-    let mut keys = db.keys().unwrap();
-    // Not robust, but works for now
-    keys.sort_by_key(|k| k.parse::<u64>().unwrap());
-    let mut wtr = Writer::from_path(path).unwrap();
-    let mut tx_count = 0;
+    let columns = Columns::select(columns::tx_table(), &target.columns);
+    let increment = target.begin("txs", &filter, &columns);
+    export_rows(store, &target, increment, &filter, &columns, "transactions", |block| {
+        block.inner.inner_proofs.iter().filter(|tx| filter.matches_tx(tx)).cloned().collect()
+    })
+}
 
-    let pb = ProgressBar::new(keys.len() as u64);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] blocks exported {pos}/{len}")
-        .unwrap()
-        .progress_chars("#>-"));
+pub fn export_fees(store: &dyn BlockStore, target: ExportTarget, filter: ExportFilter) -> bool {
+    filter.check("export-fees", &[RowFilter::AssetId]);
+
+    let columns = Columns::select(columns::fee_table(), &target.columns);
+    let increment = target.begin("fees", &filter, &columns);
+    export_rows(store, &target, increment, &filter, &columns, "fee transactions", |block| {
+        block.inner.header.fees().filter(|fee| filter.matches_fee(fee)).cloned().collect()
+    })
+}
+
+pub fn export_defi(store: &dyn BlockStore, target: ExportTarget, filter: ExportFilter) -> bool {
+    filter.check("export-defi", &[RowFilter::AssetId, RowFilter::BridgeId]);
+
+    // Virtual assets are always created before they are used, so the origins of every
+    // interaction a chain can pass through have been seen by the time it is exported. Origins in
+    // rollups outside of the exported range are looked up in the store.
+    let mut origins: HashMap<U256, Vec<U256>> = HashMap::new();
+
+    let columns = Columns::select(columns::defi_table(), &target.columns);
+    let increment = target.begin("defi", &filter, &columns);
+    export_rows(store, &target, increment, &filter, &columns, "defi interactions", |block| {
+        let mut rows = vec![];
+        for interaction in block.inner.header.defi_interactions() {
+            origins.insert(interaction.interaction_nonce, interaction.input_origins());
+            if !filter.matches_interaction(interaction) {
+                continue;
+            }
+            let chain = provenance_chain(interaction, |nonce| origins.get(&nonce).cloned().or_else(|| origins_from_db(store, nonce)));
 
-    for key in keys {
-        let block = db.get::<Block>(key).unwrap().unwrap();
+            rows.push(DefiRow {
+                interaction: interaction.clone(),
+                virtual_chain: format_chain(&chain),
+            });
+        }
+        rows
+    })
+}
+
+pub fn export_rollups(store: &dyn BlockStore, target: ExportTarget, filter: ExportFilter) -> bool {
+    filter.check("export-rollups", &[]);
+
+    let columns = Columns::select(columns::rollup_table(), &target.columns);
+    let increment = target.begin("rollups", &filter, &columns);
+    export_rows(store, &target, increment, &filter, &columns, "rollups", |_| vec![()])
+}
+
+/// Always rewritten in full, as the rows of pending interactions change once they are finalised.
+pub fn export_defi_lifecycle(store: &dyn BlockStore, target: ExportTarget, filter: ExportFilter) -> bool {
+    filter.check("export-defi-lifecycle", &[RowFilter::AssetId, RowFilter::BridgeId]);
+
+    let columns = Columns::select(columns::lifecycle_table(), &target.columns);
+    let pb = export_progress_bar(store.block_count(), "blocks read");
+
+    // A result hash is inserted into the defi tree by a rollup that lists it in its header. The
+    // rollups are kept in order, as the same hash can be listed again by a later rollup.
+    let mut inserted_in: HashMap<H256, Vec<U256>> = HashMap::new();
+    let mut latest_block = U64::zero();
+    let read = store.for_each_block(0..=u64::MAX, Parts::HEAD, &mut |block| {
+        for hash in block.inner.header.prev_defi_interaction_hashes() {
+            inserted_in.entry(*hash).or_default().push(block.inner.header.rollup_id);
+        }
+        latest_block = latest_block.max(block.metadata.block_number);
+        pb.inc(1);
+    });
+    pb.finish();
+
+    let results: HashMap<U256, DefiInteractionResult> = match read.and_then(|_| store.defi_results()) {
+        Ok(results) => results.into_iter().map(|result| (result.interaction_nonce, result)).collect(),
+        Err(err) => {
+            target.report(err.to_string());
+            return false;
+        }
+    };
 
+    let mut pending_count = 0;
+    let increment = Increment::begin(None, "defi-lifecycle", target.format.name(), options(&filter, &columns), false, None);
+    let exported = export_rows(store, &target, increment, &filter, &columns, "defi interaction lifecycles", |block| {
         let rollup_id = block.inner.header.rollup_id;
+        let mut rows = vec![];
 
-        // For every fee in the block, create a new ExportFeeAsset and serialize it
-        block.inner.header.defi_interactions.iter().for_each(|interaction: &DefiInteraction| {
-            let export_interaction = ExportDefiInteraction {
-                rollup_id,
-                bridge_address_id: interaction.bridge_address_id,
-                input_asset_a_id: interaction.input_asset_a.asset_id,
-                input_asset_a_type: interaction.input_asset_a.asset_type,
-                input_asset_b_id: interaction.input_asset_b.asset_id,
-                input_asset_b_type: interaction.input_asset_b.asset_type,
-                output_asset_a_id: interaction.output_asset_a.asset_id,
-                output_asset_a_type: interaction.output_asset_a.asset_type,
-                output_asset_b_id: interaction.output_asset_b.asset_id,
-                output_asset_b_type: interaction.output_asset_b.asset_type,
-                aux_data: interaction.aux_data.clone(),
-                total_input_value: interaction.total_input_value,
+        for interaction in block.inner.header.defi_interactions().filter(|interaction| filter.matches_interaction(interaction)) {
+            let result = results.get(&interaction.interaction_nonce);
+            let result_hash = result.map(|result| result.hash());
+            // The result can only be inserted after the interaction was submitted, so a listing of
+            // the same hash in an earlier rollup isn't its finalisation.
+            let finalised_rollup = result_hash
+                .and_then(|hash| inserted_in.get(&hash))
+                .and_then(|rollups| rollups.iter().find(|finalised| **finalised > rollup_id).copied());
+
+            let lifecycle = ExportDefiLifecycle {
+                interaction_nonce: interaction.interaction_nonce,
+                slot: interaction.slot,
+                bridge_address_id: interaction.bridge_address_id(),
+                submitted_rollup: rollup_id,
+                submitted_block: block.metadata.block_number,
+                is_async: result.map(|result| result.tx_hash != block.metadata.tx_hash),
+                result: result.map(|result| result.result),
+                result_block: result.map(|result| result.block_number),
+                result_hash,
+                finalised_rollup,
+                delay: finalised_rollup.and_then(|finalised| finalised.checked_sub(rollup_id)),
+                pending_at_block: match finalised_rollup {
+                    Some(_) => None,
+                    None => Some(latest_block),
+                },
             };
 
-            wtr.serialize(export_interaction).unwrap();
-            tx_count += 1;
-        });
-    }
+            if lifecycle.pending_at_block.is_some() {
+                pending_count += 1;
+            }
+            rows.push(lifecycle);
+        }
+        rows
+    });
 
-    pb.finish();
-    println!("Exported {} defi interactions in {:.2} seconds", tx_count, pb.elapsed().as_secs_f64());
-}
\ No newline at end of file
+    if exported {
+        target.report(format!("{} of them pending at block {}", pending_count, latest_block));
+    }
+    exported
+}
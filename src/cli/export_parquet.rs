@@ -0,0 +1,818 @@
+use crate::cli::export::{export_progress_bar, missing_rollup};
+use crate::cli::filter::ExportFilter;
+use crate::cli::manifest::{Increment, Manifest};
+use crate::store::{BlockStore, Parts};
+use crate::types::block::Block;
+use crate::types::defi_interaction::Asset;
+use crate::types::rollup_proof::G1Point;
+use arrow::array::{ArrayRef, Decimal256Array, FixedSizeBinaryArray, StringArray, TimestampSecondArray, UInt64Array};
+use arrow::datatypes::{i256, DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::DateTime;
+use ethers::types::{Address, H256, U256};
+use indicatif::ProgressBar;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Rows buffered per file before they are written as a row group.
+const BATCH_ROWS: usize = 8192;
+
+/// The widest precision of `Decimal256`. Amounts take 32 bytes of calldata, so they can be wider than this.
+const DECIMAL_PRECISION: u8 = 76;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ParquetCompression {
+    Zstd,
+    Snappy,
+    None,
+}
+
+impl ParquetCompression {
+    fn codec(&self) -> Compression {
+        match self {
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+        }
+    }
+}
+
+/// The column type of public values, fee amounts and total input values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ParquetAmounts {
+    /// Decimal strings, which hold every amount exactly.
+    String,
+    /// `decimal(76, 0)`, which DuckDB reads as a lossy double and which fails the export on an
+    /// amount of more than 76 digits.
+    Decimal,
+}
+
+/// How `export-parquet` lays out its files and columns.
+#[derive(Debug, Clone, Copy, clap::Args)]
+pub struct ParquetOptions {
+    #[clap(long, help = "Write a file per month of L1 time for every table, in a hive style month=YYYY-MM layout")]
+    pub partition_by_month: bool,
+    #[clap(long, value_enum, default_value_t = ParquetCompression::Zstd)]
+    pub compression: ParquetCompression,
+    #[clap(long, value_enum, default_value_t = ParquetAmounts::String, help = "The column type of public values, fee amounts and total input values")]
+    pub amounts: ParquetAmounts,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    UInt64,
+    /// Values and amounts, as decimal strings or `decimal(76, 0)`.
+    Amount,
+    /// Field elements and other 256 bit values that aren't amounts, as decimal strings.
+    Utf8,
+    Hash,
+    Address,
+    Timestamp,
+}
+
+impl Kind {
+    fn data_type(&self, amounts: ParquetAmounts) -> DataType {
+        match self {
+            Kind::UInt64 => DataType::UInt64,
+            Kind::Amount => match amounts {
+                ParquetAmounts::String => DataType::Utf8,
+                ParquetAmounts::Decimal => DataType::Decimal256(DECIMAL_PRECISION, 0),
+            },
+            Kind::Utf8 => DataType::Utf8,
+            Kind::Hash => DataType::FixedSizeBinary(32),
+            Kind::Address => DataType::FixedSizeBinary(20),
+            Kind::Timestamp => DataType::Timestamp(TimeUnit::Second, Some("+00:00".into())),
+        }
+    }
+}
+
+enum Value {
+    UInt64(Option<u64>),
+    Amount(U256),
+    Utf8(String),
+    Hash(H256),
+    Address(Address),
+    Timestamp(Option<i64>),
+}
+
+/// A column name, its kind and whether it is nullable.
+type Column = (&'static str, Kind, bool);
+
+type Rows = Result<Vec<Vec<Value>>, String>;
+
+struct Table {
+    name: &'static str,
+    columns: &'static [Column],
+    /// The rows of a block that match the filter, or the first value that doesn't fit its column.
+    rows: fn(&Block, &ExportFilter, ParquetAmounts) -> Rows,
+}
+
+const ROLLUPS: &[Column] = &[
+    ("rollup_id", Kind::UInt64, false),
+    ("tx_hash", Kind::Hash, false),
+    ("block_number", Kind::UInt64, false),
+    ("timestamp", Kind::Timestamp, true),
+    ("sequencer", Kind::Address, false),
+    ("rollup_beneficiary", Kind::Address, false),
+    ("rollup_size", Kind::UInt64, false),
+    ("data_start_index", Kind::UInt64, false),
+    ("num_real_txs", Kind::UInt64, false),
+    ("num_rollup_txs", Kind::UInt64, false),
+    ("old_data_root", Kind::Hash, false),
+    ("new_data_root", Kind::Hash, false),
+    ("old_null_root", Kind::Hash, false),
+    ("new_null_root", Kind::Hash, false),
+    ("old_data_roots_root", Kind::Hash, false),
+    ("new_data_roots_root", Kind::Hash, false),
+    ("old_defi_root", Kind::Hash, false),
+    ("new_defi_root", Kind::Hash, false),
+    ("prev_defi_interaction_hash", Kind::Hash, false),
+];
+
+const INNER_TXS: &[Column] = &[
+    ("rollup_id", Kind::UInt64, false),
+    ("tx_position", Kind::UInt64, false),
+    ("proof_id", Kind::Utf8, false),
+    ("note_commitment_1", Kind::Hash, false),
+    ("note_commitment_2", Kind::Hash, false),
+    ("note_commitment_1_index", Kind::UInt64, false),
+    ("note_commitment_2_index", Kind::UInt64, false),
+    ("nullifier_1", Kind::Hash, false),
+    ("nullifier_2", Kind::Hash, false),
+    ("public_value", Kind::Amount, false),
+    ("public_owner", Kind::Address, false),
+    ("public_asset_id", Kind::UInt64, false),
+];
+
+const FEES: &[Column] = &[
+    ("rollup_id", Kind::UInt64, false),
+    ("slot", Kind::UInt64, false),
+    ("asset_id", Kind::UInt64, false),
+    ("amount", Kind::Amount, false),
+];
+
+const DEFI_INTERACTIONS: &[Column] = &[
+    ("rollup_id", Kind::UInt64, false),
+    ("slot", Kind::UInt64, false),
+    ("interaction_nonce", Kind::UInt64, false),
+    ("encoded_bridge_call_data", Kind::Utf8, false),
+    ("bridge_address_id", Kind::UInt64, false),
+    ("input_asset_a_id", Kind::UInt64, false),
+    ("input_asset_a_type", Kind::Utf8, false),
+    ("input_asset_a_origin_nonce", Kind::UInt64, true),
+    ("input_asset_b_id", Kind::UInt64, false),
+    ("input_asset_b_type", Kind::Utf8, false),
+    ("input_asset_b_origin_nonce", Kind::UInt64, true),
+    ("output_asset_a_id", Kind::UInt64, false),
+    ("output_asset_a_type", Kind::Utf8, false),
+    ("output_asset_b_id", Kind::UInt64, false),
+    ("output_asset_b_type", Kind::Utf8, false),
+    ("aux_data", Kind::UInt64, false),
+    ("total_input_value", Kind::Amount, false),
+];
+
+const PROOFS: &[Column] = &[
+    ("rollup_id", Kind::UInt64, false),
+    ("public_input_hash", Kind::Hash, false),
+    ("recursive_p1_x", Kind::Utf8, false),
+    ("recursive_p1_y", Kind::Utf8, false),
+    ("recursive_p2_x", Kind::Utf8, false),
+    ("recursive_p2_y", Kind::Utf8, false),
+    ("w1_x", Kind::Utf8, false),
+    ("w1_y", Kind::Utf8, false),
+    ("w2_x", Kind::Utf8, false),
+    ("w2_y", Kind::Utf8, false),
+    ("w3_x", Kind::Utf8, false),
+    ("w3_y", Kind::Utf8, false),
+    ("t1_x", Kind::Utf8, false),
+    ("t1_y", Kind::Utf8, false),
+    ("t2_x", Kind::Utf8, false),
+    ("t2_y", Kind::Utf8, false),
+    ("t3_x", Kind::Utf8, false),
+    ("t3_y", Kind::Utf8, false),
+    ("w1_eval", Kind::Utf8, false),
+    ("w2_eval", Kind::Utf8, false),
+    ("w3_eval", Kind::Utf8, false),
+    ("sigma_1_eval", Kind::Utf8, false),
+    ("sigma_2_eval", Kind::Utf8, false),
+    ("z_omega_eval", Kind::Utf8, false),
+    ("pi_z_x", Kind::Utf8, false),
+    ("pi_z_y", Kind::Utf8, false),
+    ("pi_z_omega_x", Kind::Utf8, false),
+    ("pi_z_omega_y", Kind::Utf8, false),
+];
+
+/// The same tables as `export-sqlite`.
+static TABLES: [Table; 5] = [
+    Table {
+        name: "rollups",
+        columns: ROLLUPS,
+        rows: rollup_rows,
+    },
+    Table {
+        name: "inner_txs",
+        columns: INNER_TXS,
+        rows: inner_tx_rows,
+    },
+    Table {
+        name: "fees",
+        columns: FEES,
+        rows: fee_rows,
+    },
+    Table {
+        name: "defi_interactions",
+        columns: DEFI_INTERACTIONS,
+        rows: defi_interaction_rows,
+    },
+    Table {
+        name: "proofs",
+        columns: PROOFS,
+        rows: proof_rows,
+    },
+];
+
+/// For ids and asset ids, which are 256 bits in calldata but fit in 64 bits on any real rollup.
+fn uint(value: &U256, field: &str) -> Result<u64, String> {
+    u64::try_from(*value).map_err(|_| format!("{} {} doesn't fit in a UInt64 column", field, value))
+}
+
+fn int(value: &U256, field: &str) -> Result<Value, String> {
+    uint(value, field).map(|value| Value::UInt64(Some(value)))
+}
+
+/// Strings hold any amount, while `decimal(76, 0)` fails on an amount of more than 76 digits,
+/// rather than writing it wrapped or negative.
+fn amount(value: &U256, field: &str, amounts: ParquetAmounts) -> Result<Value, String> {
+    if amounts == ParquetAmounts::Decimal && *value >= U256::exp10(DECIMAL_PRECISION as usize) {
+        return Err(format!(
+            "{} {} doesn't fit decimal({}, 0), export with `--amounts string`",
+            field, value, DECIMAL_PRECISION
+        ));
+    }
+    Ok(Value::Amount(*value))
+}
+
+fn decimal_string(value: &U256) -> Value {
+    Value::Utf8(value.to_string())
+}
+
+fn rollup_rows(block: &Block, _filter: &ExportFilter, _amounts: ParquetAmounts) -> Rows {
+    let header = &block.inner.header;
+    Ok(vec![vec![
+        int(&header.rollup_id, "rollup_id")?,
+        Value::Hash(block.metadata.tx_hash),
+        Value::UInt64(Some(block.metadata.block_number.as_u64())),
+        Value::Timestamp(block.metadata.timestamp.map(|timestamp| timestamp.as_u64() as i64)),
+        Value::Address(block.sequencer),
+        Value::Address(header.rollup_beneficiary),
+        int(&header.rollup_size, "rollup_size")?,
+        int(&header.data_start_index, "data_start_index")?,
+        int(&block.inner.num_real_txs, "num_real_txs")?,
+        int(&header.num_rollup_txs, "num_rollup_txs")?,
+        Value::Hash(header.old_data_root),
+        Value::Hash(header.new_data_root),
+        Value::Hash(header.old_null_root),
+        Value::Hash(header.new_null_root),
+        Value::Hash(header.old_data_roots_root),
+        Value::Hash(header.new_data_roots_root),
+        Value::Hash(header.old_defi_root),
+        Value::Hash(header.new_defi_root),
+        Value::Hash(header.prev_defi_interaction_hash),
+    ]])
+}
+
+fn inner_tx_rows(block: &Block, filter: &ExportFilter, amounts: ParquetAmounts) -> Rows {
+    let rollup_id = uint(&block.inner.header.rollup_id, "rollup_id")?;
+    block
+        .inner
+        .inner_proofs
+        .iter()
+        .filter(|inner| filter.matches_tx(inner))
+        .map(|inner| {
+            Ok(vec![
+                Value::UInt64(Some(rollup_id)),
+                Value::UInt64(Some(inner.tx_position)),
+                Value::Utf8(format!("{:?}", inner.proof_id)),
+                Value::Hash(inner.note_commitment_1),
+                Value::Hash(inner.note_commitment_2),
+                Value::UInt64(Some(inner.note_commitment_1_index)),
+                Value::UInt64(Some(inner.note_commitment_2_index)),
+                Value::Hash(inner.nullifier_1),
+                Value::Hash(inner.nullifier_2),
+                amount(&inner.public_value, "public_value", amounts)?,
+                Value::Address(inner.public_owner),
+                int(&inner.public_asset_id, "public_asset_id")?,
+            ])
+        })
+        .collect()
+}
+
+fn fee_rows(block: &Block, filter: &ExportFilter, amounts: ParquetAmounts) -> Rows {
+    let header = &block.inner.header;
+    header
+        .fees()
+        .filter(|fee| filter.matches_fee(fee))
+        .map(|fee| {
+            Ok(vec![
+                int(&header.rollup_id, "rollup_id")?,
+                Value::UInt64(Some(fee.slot)),
+                int(&fee.asset_id, "asset_id")?,
+                amount(&fee.amount, "amount", amounts)?,
+            ])
+        })
+        .collect()
+}
+
+fn asset(asset: &Asset, field: &str) -> Result<[Value; 2], String> {
+    Ok([int(&asset.asset_id, field)?, Value::Utf8(format!("{:?}", asset.asset_type))])
+}
+
+fn origin_nonce(asset: &Asset) -> Value {
+    Value::UInt64(asset.origin_nonce.map(|nonce| nonce.as_u64()))
+}
+
+fn defi_interaction_rows(block: &Block, filter: &ExportFilter, amounts: ParquetAmounts) -> Rows {
+    let header = &block.inner.header;
+    header
+        .defi_interactions()
+        .filter(|interaction| filter.matches_interaction(interaction))
+        .map(|interaction| {
+            let mut row = vec![
+                int(&header.rollup_id, "rollup_id")?,
+                Value::UInt64(Some(interaction.slot)),
+                int(&interaction.interaction_nonce, "interaction_nonce")?,
+                decimal_string(&interaction.encoded_bridge_call_data),
+                int(&interaction.bridge_address_id(), "bridge_address_id")?,
+            ];
+            row.extend(asset(&interaction.input_asset_a(), "input_asset_a_id")?);
+            row.push(origin_nonce(&interaction.input_asset_a()));
+            row.extend(asset(&interaction.input_asset_b(), "input_asset_b_id")?);
+            row.push(origin_nonce(&interaction.input_asset_b()));
+            row.extend(asset(&interaction.output_asset_a(), "output_asset_a_id")?);
+            row.extend(asset(&interaction.output_asset_b(), "output_asset_b_id")?);
+            row.push(int(&interaction.aux_data(), "aux_data")?);
+            row.push(amount(&interaction.total_input_value, "total_input_value", amounts)?);
+            Ok(row)
+        })
+        .collect()
+}
+
+fn point(point: &G1Point) -> [Value; 2] {
+    [decimal_string(&point.x), decimal_string(&point.y)]
+}
+
+fn proof_rows(block: &Block, _filter: &ExportFilter, _amounts: ParquetAmounts) -> Rows {
+    let proof = match &block.inner.proof {
+        Some(proof) => proof,
+        None => return Ok(vec![]),
+    };
+    let mut row = vec![int(&block.inner.header.rollup_id, "rollup_id")?, Value::Hash(proof.public_input_hash)];
+    for g1 in [&proof.recursive_p1, &proof.recursive_p2, &proof.w1, &proof.w2, &proof.w3, &proof.t1, &proof.t2, &proof.t3] {
+        row.extend(point(g1));
+    }
+    for eval in [
+        &proof.w1_eval,
+        &proof.w2_eval,
+        &proof.w3_eval,
+        &proof.sigma_1_eval,
+        &proof.sigma_2_eval,
+        &proof.z_omega_eval,
+    ] {
+        row.push(decimal_string(eval));
+    }
+    row.extend(point(&proof.pi_z));
+    row.extend(point(&proof.pi_z_omega));
+    Ok(vec![row])
+}
+
+fn schema(table: &Table, amounts: ParquetAmounts) -> SchemaRef {
+    let fields: Vec<Field> = table
+        .columns
+        .iter()
+        .map(|(name, kind, nullable)| Field::new(*name, kind.data_type(amounts), *nullable))
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Builds the array of column `index` of `rows`.
+fn column(kind: Kind, amounts: ParquetAmounts, rows: &[Vec<Value>], index: usize) -> Result<ArrayRef, String> {
+    let values = rows.iter().map(|row| &row[index]);
+    let array: ArrayRef = match (kind, amounts) {
+        (Kind::UInt64, _) => Arc::new(UInt64Array::from_iter(values.map(|value| match value {
+            Value::UInt64(value) => *value,
+            _ => unreachable!(),
+        }))),
+        (Kind::Amount, ParquetAmounts::String) => Arc::new(StringArray::from_iter_values(values.map(|value| match value {
+            Value::Amount(value) => value.to_string(),
+            _ => unreachable!(),
+        }))),
+        (Kind::Amount, ParquetAmounts::Decimal) => Arc::new(
+            Decimal256Array::from_iter_values(values.map(|value| match value {
+                Value::Amount(value) => {
+                    let mut bytes = [0u8; 32];
+                    value.to_big_endian(&mut bytes);
+                    i256::from_be_bytes(bytes)
+                }
+                _ => unreachable!(),
+            }))
+            .with_precision_and_scale(DECIMAL_PRECISION, 0)
+            .map_err(|err| err.to_string())?,
+        ),
+        (Kind::Utf8, _) => Arc::new(StringArray::from_iter_values(values.map(|value| match value {
+            Value::Utf8(value) => value,
+            _ => unreachable!(),
+        }))),
+        (Kind::Hash | Kind::Address, _) => Arc::new(
+            FixedSizeBinaryArray::try_from_iter(values.map(|value| match value {
+                Value::Hash(value) => value.as_bytes(),
+                Value::Address(value) => value.as_bytes(),
+                _ => unreachable!(),
+            }))
+            .map_err(|err| err.to_string())?,
+        ),
+        (Kind::Timestamp, _) => Arc::new(
+            TimestampSecondArray::from_iter(values.map(|value| match value {
+                Value::Timestamp(value) => *value,
+                _ => unreachable!(),
+            }))
+            .with_timezone("+00:00"),
+        ),
+    };
+    Ok(array)
+}
+
+struct Partition {
+    writer: ArrowWriter<File>,
+    rows: Vec<Vec<Value>>,
+}
+
+/// Writes the rows of a table to a directory of files, one per month when partitioned. Every run
+/// writes its own `part-<first rollup id>.parquet` files, so an incremental run adds files next to
+/// the ones of earlier runs.
+struct TableWriter {
+    table: &'static Table,
+    schema: SchemaRef,
+    properties: WriterProperties,
+    amounts: ParquetAmounts,
+    dir: PathBuf,
+    part: String,
+    partitioned: bool,
+    appending: bool,
+    partitions: BTreeMap<Option<String>, Partition>,
+    /// The files this run created, removed again if it fails.
+    files: Vec<PathBuf>,
+    row_count: usize,
+}
+
+impl TableWriter {
+    fn new(table: &'static Table, dir: &Path, options: ParquetOptions, increment: &Increment) -> Result<Self, String> {
+        let dir = dir.join(table.name);
+        if !increment.appending() && dir.exists() {
+            fs::remove_dir_all(&dir).map_err(|err| format!("Can't replace {:?}: {}", dir, err))?;
+        }
+
+        Ok(TableWriter {
+            table,
+            schema: schema(table, options.amounts),
+            properties: WriterProperties::builder().set_compression(options.compression.codec()).build(),
+            amounts: options.amounts,
+            dir,
+            part: format!("part-{}.parquet", increment.first_rollup_id()),
+            partitioned: options.partition_by_month,
+            appending: increment.appending(),
+            partitions: BTreeMap::new(),
+            files: vec![],
+            row_count: 0,
+        })
+    }
+
+    /// Files are named `part-<first rollup id>.parquet` after the first rollup id the run exports
+    /// from, 0 unless appending, so runs don't overwrite each other's files. Partitioned
+    /// files follow the hive layout `<table>/month=YYYY-MM/part-<first rollup id>.parquet`, which
+    /// DuckDB and pyarrow read as a `month` column.
+    fn path(&self, month: &Option<String>) -> PathBuf {
+        match month {
+            Some(month) => self.dir.join(format!("month={}", month)).join(&self.part),
+            None => self.dir.join(&self.part),
+        }
+    }
+
+    fn open(&mut self, month: &Option<String>) -> Result<&mut Partition, String> {
+        if !self.partitions.contains_key(month) {
+            let path = self.path(month);
+            let cant_create = |err: &dyn std::fmt::Display| format!("Can't create {:?}: {}", path, err);
+            fs::create_dir_all(path.parent().unwrap()).map_err(|err| cant_create(&err))?;
+            let file = File::create(&path).map_err(|err| cant_create(&err))?;
+            self.files.push(path.clone());
+            let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(self.properties.clone()))
+                .map_err(|err| cant_create(&err))?;
+            self.partitions.insert(month.clone(), Partition { writer, rows: vec![] });
+        }
+        Ok(self.partitions.get_mut(month).unwrap())
+    }
+
+    fn push(&mut self, block: &Block, filter: &ExportFilter) -> Result<(), String> {
+        let rows = (self.table.rows)(block, filter, self.amounts)?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.row_count += rows.len();
+
+        let month = self.partitioned.then(|| month(block));
+        let (table, schema, amounts) = (self.table, self.schema.clone(), self.amounts);
+        let partition = self.open(&month)?;
+        partition.rows.extend(rows);
+        if partition.rows.len() >= BATCH_ROWS {
+            Self::flush(table, &schema, amounts, partition)?;
+        }
+        Ok(())
+    }
+
+    fn flush(table: &Table, schema: &SchemaRef, amounts: ParquetAmounts, partition: &mut Partition) -> Result<(), String> {
+        let rows = std::mem::take(&mut partition.rows);
+        let columns = table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, (_, kind, _))| column(*kind, amounts, &rows, index))
+            .collect::<Result<_, _>>()?;
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(|err| err.to_string())?;
+        partition.writer.write(&batch).map_err(|err| format!("Can't write the {} table: {}", table.name, err))
+    }
+
+    /// Closes the files. An unpartitioned table without rows still gets a file when written from
+    /// scratch, so readers find every table.
+    fn finish(&mut self) -> Result<usize, String> {
+        if !self.partitioned && !self.appending {
+            self.open(&None)?;
+        }
+        for (_, mut partition) in std::mem::take(&mut self.partitions) {
+            if !partition.rows.is_empty() {
+                Self::flush(self.table, &self.schema, self.amounts, &mut partition)?;
+            }
+            partition.writer.close().map_err(|err| format!("Can't close the {} table: {}", self.table.name, err))?;
+        }
+        Ok(self.row_count)
+    }
+
+    /// Removes the files of a failed run, so an incremental run doesn't find half of its rows.
+    fn discard(mut self) {
+        self.partitions.clear();
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// The month a block was published in, or `unknown` for blocks synced before timestamps were recorded.
+fn month(block: &Block) -> String {
+    block
+        .metadata
+        .timestamp
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp.as_u64() as i64, 0))
+        .map(|time| time.format("%Y-%m").to_string())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// What a run wrote: the rollups, the last rollup id it read and the rows per table.
+struct Written {
+    rollups: u64,
+    last_rollup_id: Option<u64>,
+    tables: Vec<(&'static str, usize)>,
+}
+
+/// Writes the blocks within `range` that match `filter` to every table, removing the files of this
+/// run again if a block can't be read or has a value that doesn't fit its column.
+fn write_tables(
+    store: &dyn BlockStore,
+    writers: &mut [TableWriter],
+    range: RangeInclusive<u64>,
+    filter: &ExportFilter,
+    pb: &ProgressBar,
+) -> Result<Written, String> {
+    let mut written = Written { rollups: 0, last_rollup_id: None, tables: vec![] };
+    let mut pushed = Ok(());
+
+    store
+        .for_each_block(range, Parts::ALL, &mut |block| {
+            pb.inc(1);
+            if pushed.is_err() {
+                return;
+            }
+            written.last_rollup_id = Some(block.inner.header.rollup_id.as_u64());
+            if filter.matches_block(&block) {
+                pushed = writers
+                    .iter_mut()
+                    .try_for_each(|writer| writer.push(&block, filter))
+                    .map_err(|err| format!("Failed to export rollup {}: {}", block.inner.header.rollup_id, err));
+                written.rollups += 1;
+            }
+        })
+        .map_err(|err| err.to_string())?;
+    pushed?;
+
+    for writer in writers.iter_mut() {
+        written.tables.push((writer.table.name, writer.finish()?));
+    }
+    Ok(written)
+}
+
+/// Writes the stored blocks that match `filter` to a directory of Parquet files per table in `dir`,
+/// replacing earlier exports unless appending. Row filters narrow the tables that have their field,
+/// while the rollups and proofs tables hold every rollup within the rollup and time filters.
+/// Returns false if a block can't be read or has a value that doesn't fit its column, in which
+/// case the files of this run are removed and the manifest isn't updated.
+pub fn export_parquet(
+    store: &dyn BlockStore,
+    dir: String,
+    options: ParquetOptions,
+    incremental: bool,
+    since: Option<u64>,
+    filter: ExportFilter,
+) -> bool {
+    let mut manifest_options = filter.options();
+    manifest_options.insert(String::from("partition_by_month"), options.partition_by_month.to_string());
+    manifest_options.insert(String::from("amounts"), format!("{:?}", options.amounts).to_lowercase());
+    let increment = Increment::begin(Manifest::path_for_dir(&dir), "parquet", "parquet", manifest_options, incremental, since);
+    let dir = Path::new(&dir);
+
+    let writers = fs::create_dir_all(dir)
+        .map_err(|err| format!("Can't create {:?}: {}", dir, err))
+        .and_then(|_| {
+            TABLES
+                .iter()
+                .map(|table| TableWriter::new(table, dir, options, &increment))
+                .collect::<Result<Vec<_>, _>>()
+        });
+    let mut writers = match writers {
+        Ok(writers) => writers,
+        Err(err) => {
+            println!("Failed to export to {:?}: {}", dir, err);
+            return false;
+        }
+    };
+
+    let (range, missing) = increment.rollup_range(store, &filter);
+    let pb = export_progress_bar(store.rollup_ids(range.clone()).len() as u64, "blocks exported");
+    let written = write_tables(store, &mut writers, range, &filter, &pb);
+    pb.finish();
+
+    let written = match written {
+        Ok(written) => written,
+        Err(err) => {
+            writers.into_iter().for_each(TableWriter::discard);
+            if !increment.appending() {
+                increment.abandon();
+            }
+            println!("Failed to export to {:?}: {}", dir, err);
+            return false;
+        }
+    };
+
+    let mut row_count = 0;
+    for (name, rows) in written.tables {
+        println!("{:<18} {:>9} rows", name, rows);
+        row_count += rows as u64;
+    }
+    println!("Exported {} rollups to {:?} in {:.2} seconds", written.rollups, dir, pb.elapsed().as_secs_f64());
+    if let Some(missing) = missing {
+        println!("{}", missing_rollup(missing));
+    }
+    increment.finish(written.last_rollup_id, row_count);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::WriteBatch;
+    use crate::types::block::FeeAsset;
+    use crate::types::transaction::{InnerProofData, ProofId};
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{Decimal256Type, TimestampSecondType, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn block(rollup_id: u64, public_value: U256) -> Block {
+        let mut block = Block::default();
+        block.metadata.timestamp = Some(U256::from(1_661_990_400 + rollup_id * 3600));
+        block.inner.header.rollup_id = U256::from(rollup_id);
+        for (position, proof_id) in [ProofId::Deposit, ProofId::Withdraw].into_iter().enumerate() {
+            block.inner.inner_proofs.push(InnerProofData {
+                proof_id,
+                tx_position: position as u64,
+                public_value,
+                public_asset_id: U256::from(position),
+                ..Default::default()
+            });
+        }
+        block.inner.header.fee_slots.push(FeeAsset { slot: 0, asset_id: U256::zero(), amount: U256::from(7) });
+        block
+    }
+
+    fn store(dir: &Path, blocks: Vec<Block>) -> SqliteStore {
+        fs::create_dir_all(dir).unwrap();
+        let store = SqliteStore::open(&dir.join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for block in blocks {
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+        store
+    }
+
+    fn options(amounts: ParquetAmounts) -> ParquetOptions {
+        ParquetOptions { partition_by_month: false, compression: ParquetCompression::Zstd, amounts }
+    }
+
+    fn read(path: &Path) -> RecordBatch {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap()
+    }
+
+    fn export(store: &SqliteStore, dir: &Path, amounts: ParquetAmounts, since: Option<u64>) -> bool {
+        export_parquet(store, dir.to_str().unwrap().to_string(), options(amounts), false, since, ExportFilter::default())
+    }
+
+    #[test]
+    fn writes_typed_columns_that_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        // Wider than decimal(76, 0), so only a string holds it.
+        let store = store(dir.path(), vec![block(0, U256::MAX), block(1, U256::from(1000))]);
+        let path = dir.path().join("parquet");
+        assert!(export(&store, &path, ParquetAmounts::String, None));
+
+        let txs = read(&path.join("inner_txs/part-0.parquet"));
+        let schema = txs.schema();
+        assert_eq!(schema.field_with_name("rollup_id").unwrap().data_type(), &DataType::UInt64);
+        assert_eq!(schema.field_with_name("public_value").unwrap().data_type(), &DataType::Utf8);
+        assert_eq!(schema.field_with_name("nullifier_1").unwrap().data_type(), &DataType::FixedSizeBinary(32));
+        assert_eq!(schema.field_with_name("public_owner").unwrap().data_type(), &DataType::FixedSizeBinary(20));
+        assert_eq!(txs.num_rows(), 4);
+        let rollup_ids = txs.column_by_name("rollup_id").unwrap().as_primitive::<UInt64Type>();
+        assert_eq!(rollup_ids.values().to_vec(), vec![0, 0, 1, 1]);
+        let public_values = txs.column_by_name("public_value").unwrap().as_string::<i32>();
+        assert_eq!(public_values.value(0), U256::MAX.to_string());
+        assert_eq!(public_values.value(3), "1000");
+        assert_eq!(txs.column_by_name("proof_id").unwrap().as_string::<i32>().value(1), "Withdraw");
+
+        let rollups = read(&path.join("rollups/part-0.parquet"));
+        let timestamps = rollups.column_by_name("timestamp").unwrap();
+        assert_eq!(timestamps.data_type(), &DataType::Timestamp(TimeUnit::Second, Some("+00:00".into())));
+        assert_eq!(timestamps.as_primitive::<TimestampSecondType>().value(1), 1_661_994_000);
+
+        let fees = read(&path.join("fees/part-0.parquet"));
+        assert_eq!(fees.column_by_name("amount").unwrap().as_string::<i32>().value(0), "7");
+    }
+
+    #[test]
+    fn amounts_can_be_decimals() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), vec![block(0, U256::from(1000))]);
+        let path = dir.path().join("parquet");
+        assert!(export(&store, &path, ParquetAmounts::Decimal, None));
+
+        let fees = read(&path.join("fees/part-0.parquet"));
+        let amounts = fees.column_by_name("amount").unwrap();
+        assert_eq!(amounts.data_type(), &DataType::Decimal256(76, 0));
+        assert_eq!(amounts.as_primitive::<Decimal256Type>().value(0), i256::from(7));
+    }
+
+    #[test]
+    fn values_that_dont_fit_fail_the_export_and_leave_no_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parquet");
+        let narrow = store(&dir.path().join("narrow"), vec![block(0, U256::MAX)]);
+        assert!(export(&narrow, &path, ParquetAmounts::String, None));
+
+        let mut wide = block(1, U256::from(1000));
+        wide.inner.inner_proofs[0].public_asset_id = U256::from(u64::MAX) + 1;
+        let store = store(dir.path(), vec![block(0, U256::MAX), wide]);
+
+        // Appending fails on rollup 1 and removes the files of the run, keeping the earlier ones.
+        assert!(!export(&store, &path, ParquetAmounts::String, Some(0)));
+        assert!(!path.join("inner_txs/part-1.parquet").exists());
+        assert!(path.join("inner_txs/part-0.parquet").exists());
+        assert!(path.join("manifest.json").exists());
+
+        // Replacing the export fails on rollup 0 and also removes the manifest of the replaced export.
+        assert!(!export(&store, &path, ParquetAmounts::Decimal, None));
+        assert!(!path.join("inner_txs/part-0.parquet").exists());
+        assert!(!path.join("manifest.json").exists());
+
+        let increment = Increment::begin(None, "parquet", "parquet", BTreeMap::new(), false, None);
+        let mut writers: Vec<TableWriter> = TABLES
+            .iter()
+            .map(|table| TableWriter::new(table, &path, options(ParquetAmounts::String), &increment).unwrap())
+            .collect();
+        let err = write_tables(&store, &mut writers, 0..=u64::MAX, &ExportFilter::default(), &ProgressBar::hidden()).err();
+        assert_eq!(err.unwrap(), "Failed to export rollup 1: public_asset_id 18446744073709551616 doesn't fit in a UInt64 column");
+    }
+}
@@ -0,0 +1,390 @@
+use crate::cli::export::export_progress_bar;
+use crate::cli::filter::ExportFilter;
+use crate::store::{BlockStore, Parts};
+use crate::types::block::Block;
+use crate::types::rollup_proof::G1Point;
+use ethers::types::{Address, H256, U256};
+use indicatif::ProgressBar;
+use rusqlite::{params, Connection, Transaction};
+use std::fs;
+use std::path::Path;
+
+// Values that can exceed 64 bits are stored as decimal strings, hashes and addresses as 0x prefixed hex.
+const SCHEMA: &str = "
+    CREATE TABLE rollups (
+        rollup_id INTEGER PRIMARY KEY,
+        tx_hash TEXT NOT NULL,
+        block_number INTEGER NOT NULL,
+        timestamp INTEGER,
+        sequencer TEXT NOT NULL,
+        rollup_beneficiary TEXT NOT NULL,
+        rollup_size INTEGER NOT NULL,
+        data_start_index INTEGER NOT NULL,
+        num_real_txs INTEGER NOT NULL,
+        num_rollup_txs INTEGER NOT NULL,
+        old_data_root TEXT NOT NULL,
+        new_data_root TEXT NOT NULL,
+        old_null_root TEXT NOT NULL,
+        new_null_root TEXT NOT NULL,
+        old_data_roots_root TEXT NOT NULL,
+        new_data_roots_root TEXT NOT NULL,
+        old_defi_root TEXT NOT NULL,
+        new_defi_root TEXT NOT NULL,
+        prev_defi_interaction_hash TEXT NOT NULL
+    );
+    CREATE TABLE inner_txs (
+        rollup_id INTEGER NOT NULL REFERENCES rollups (rollup_id),
+        tx_position INTEGER NOT NULL,
+        proof_id TEXT NOT NULL,
+        note_commitment_1 TEXT NOT NULL,
+        note_commitment_2 TEXT NOT NULL,
+        note_commitment_1_index INTEGER NOT NULL,
+        note_commitment_2_index INTEGER NOT NULL,
+        nullifier_1 TEXT NOT NULL,
+        nullifier_2 TEXT NOT NULL,
+        public_value TEXT NOT NULL,
+        public_owner TEXT NOT NULL,
+        public_asset_id INTEGER NOT NULL,
+        PRIMARY KEY (rollup_id, tx_position)
+    );
+    CREATE TABLE fees (
+        rollup_id INTEGER NOT NULL REFERENCES rollups (rollup_id),
+        slot INTEGER NOT NULL,
+        asset_id INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        PRIMARY KEY (rollup_id, slot)
+    );
+    CREATE TABLE defi_interactions (
+        rollup_id INTEGER NOT NULL REFERENCES rollups (rollup_id),
+        slot INTEGER NOT NULL,
+        interaction_nonce INTEGER NOT NULL,
+        encoded_bridge_call_data TEXT NOT NULL,
+        bridge_address_id INTEGER NOT NULL,
+        input_asset_a_id INTEGER NOT NULL,
+        input_asset_a_type TEXT NOT NULL,
+        input_asset_a_origin_nonce INTEGER,
+        input_asset_b_id INTEGER NOT NULL,
+        input_asset_b_type TEXT NOT NULL,
+        input_asset_b_origin_nonce INTEGER,
+        output_asset_a_id INTEGER NOT NULL,
+        output_asset_a_type TEXT NOT NULL,
+        output_asset_b_id INTEGER NOT NULL,
+        output_asset_b_type TEXT NOT NULL,
+        aux_data TEXT NOT NULL,
+        total_input_value TEXT NOT NULL,
+        PRIMARY KEY (rollup_id, slot)
+    );
+    CREATE TABLE proofs (
+        rollup_id INTEGER PRIMARY KEY REFERENCES rollups (rollup_id),
+        public_input_hash TEXT NOT NULL,
+        recursive_p1_x TEXT NOT NULL, recursive_p1_y TEXT NOT NULL,
+        recursive_p2_x TEXT NOT NULL, recursive_p2_y TEXT NOT NULL,
+        w1_x TEXT NOT NULL, w1_y TEXT NOT NULL,
+        w2_x TEXT NOT NULL, w2_y TEXT NOT NULL,
+        w3_x TEXT NOT NULL, w3_y TEXT NOT NULL,
+        t1_x TEXT NOT NULL, t1_y TEXT NOT NULL,
+        t2_x TEXT NOT NULL, t2_y TEXT NOT NULL,
+        t3_x TEXT NOT NULL, t3_y TEXT NOT NULL,
+        w1_eval TEXT NOT NULL,
+        w2_eval TEXT NOT NULL,
+        w3_eval TEXT NOT NULL,
+        sigma_1_eval TEXT NOT NULL,
+        sigma_2_eval TEXT NOT NULL,
+        z_omega_eval TEXT NOT NULL,
+        pi_z_x TEXT NOT NULL, pi_z_y TEXT NOT NULL,
+        pi_z_omega_x TEXT NOT NULL, pi_z_omega_y TEXT NOT NULL
+    );
+";
+
+// Created after the rows are inserted, which is faster than keeping them updated along the way.
+const INDEXES: &str = "
+    CREATE INDEX rollups_by_block_number ON rollups (block_number);
+    CREATE INDEX rollups_by_timestamp ON rollups (timestamp);
+    CREATE INDEX inner_txs_by_proof_id ON inner_txs (proof_id);
+    CREATE INDEX inner_txs_by_public_owner ON inner_txs (public_owner);
+    CREATE INDEX inner_txs_by_public_asset_id ON inner_txs (public_asset_id);
+    CREATE INDEX inner_txs_by_note_commitment_1 ON inner_txs (note_commitment_1);
+    CREATE INDEX inner_txs_by_note_commitment_2 ON inner_txs (note_commitment_2);
+    CREATE INDEX inner_txs_by_nullifier_1 ON inner_txs (nullifier_1);
+    CREATE INDEX inner_txs_by_nullifier_2 ON inner_txs (nullifier_2);
+    CREATE INDEX fees_by_asset_id ON fees (asset_id);
+    CREATE INDEX defi_interactions_by_interaction_nonce ON defi_interactions (interaction_nonce);
+    CREATE INDEX defi_interactions_by_bridge_address_id ON defi_interactions (bridge_address_id);
+";
+
+fn hash(value: &H256) -> String {
+    format!("{:?}", value)
+}
+
+fn address(value: &Address) -> String {
+    format!("{:?}", value)
+}
+
+fn decimal(value: &U256) -> String {
+    value.to_string()
+}
+
+type InsertResult = Result<(), Box<dyn std::error::Error>>;
+
+/// For values that fit in 64 bits by construction, like ids and asset ids. A value that doesn't
+/// fit in an INTEGER column fails the export instead of being truncated.
+fn int(value: &U256, field: &str) -> Result<i64, String> {
+    i64::try_from(*value).map_err(|_| format!("{} {} doesn't fit in an INTEGER column", field, value))
+}
+
+fn int64(value: u64, field: &str) -> Result<i64, String> {
+    i64::try_from(value).map_err(|_| format!("{} {} doesn't fit in an INTEGER column", field, value))
+}
+
+fn nullable_int(value: Option<&U256>, field: &str) -> Result<Option<i64>, String> {
+    value.map(|value| int(value, field)).transpose()
+}
+
+fn point(point: &G1Point) -> [String; 2] {
+    [decimal(&point.x), decimal(&point.y)]
+}
+
+/// Inserts the rollup and its proof, and the txs, fees and defi interactions of it that match `filter`.
+fn insert_block(tx: &Transaction, block: &Block, filter: &ExportFilter) -> InsertResult {
+    let header = &block.inner.header;
+    let rollup_id = int(&header.rollup_id, "rollup_id")?;
+
+    tx.prepare_cached(
+        "INSERT INTO rollups VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+    )?
+    .execute(params![
+        rollup_id,
+        hash(&block.metadata.tx_hash),
+        int64(block.metadata.block_number.as_u64(), "block_number")?,
+        nullable_int(block.metadata.timestamp.as_ref(), "timestamp")?,
+        address(&block.sequencer),
+        address(&header.rollup_beneficiary),
+        int(&header.rollup_size, "rollup_size")?,
+        int(&header.data_start_index, "data_start_index")?,
+        int(&block.inner.num_real_txs, "num_real_txs")?,
+        int(&header.num_rollup_txs, "num_rollup_txs")?,
+        hash(&header.old_data_root),
+        hash(&header.new_data_root),
+        hash(&header.old_null_root),
+        hash(&header.new_null_root),
+        hash(&header.old_data_roots_root),
+        hash(&header.new_data_roots_root),
+        hash(&header.old_defi_root),
+        hash(&header.new_defi_root),
+        hash(&header.prev_defi_interaction_hash),
+    ])?;
+
+    let mut insert_tx = tx.prepare_cached("INSERT INTO inner_txs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")?;
+    for inner in block.inner.inner_proofs.iter().filter(|inner| filter.matches_tx(inner)) {
+        insert_tx.execute(params![
+            rollup_id,
+            int64(inner.tx_position, "tx_position")?,
+            format!("{:?}", inner.proof_id),
+            hash(&inner.note_commitment_1),
+            hash(&inner.note_commitment_2),
+            int64(inner.note_commitment_1_index, "note_commitment_1_index")?,
+            int64(inner.note_commitment_2_index, "note_commitment_2_index")?,
+            hash(&inner.nullifier_1),
+            hash(&inner.nullifier_2),
+            decimal(&inner.public_value),
+            address(&inner.public_owner),
+            int(&inner.public_asset_id, "public_asset_id")?,
+        ])?;
+    }
+
+    let mut insert_fee = tx.prepare_cached("INSERT INTO fees VALUES (?1, ?2, ?3, ?4)")?;
+    for fee in header.fees().filter(|fee| filter.matches_fee(fee)) {
+        insert_fee.execute(params![
+            rollup_id,
+            int64(fee.slot, "fee slot")?,
+            int(&fee.asset_id, "fee asset_id")?,
+            decimal(&fee.amount)
+        ])?;
+    }
+
+    let mut insert_interaction = tx.prepare_cached(
+        "INSERT INTO defi_interactions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+    )?;
+    for interaction in header.defi_interactions().filter(|interaction| filter.matches_interaction(interaction)) {
+        insert_interaction.execute(params![
+            rollup_id,
+            int64(interaction.slot, "interaction slot")?,
+            int(&interaction.interaction_nonce, "interaction_nonce")?,
+            decimal(&interaction.encoded_bridge_call_data),
+            int(&interaction.bridge_address_id(), "bridge_address_id")?,
+            int(&interaction.input_asset_a().asset_id, "input_asset_a_id")?,
+            format!("{:?}", interaction.input_asset_a().asset_type),
+            nullable_int(interaction.input_asset_a().origin_nonce.as_ref(), "input_asset_a_origin_nonce")?,
+            int(&interaction.input_asset_b().asset_id, "input_asset_b_id")?,
+            format!("{:?}", interaction.input_asset_b().asset_type),
+            nullable_int(interaction.input_asset_b().origin_nonce.as_ref(), "input_asset_b_origin_nonce")?,
+            int(&interaction.output_asset_a().asset_id, "output_asset_a_id")?,
+            format!("{:?}", interaction.output_asset_a().asset_type),
+            int(&interaction.output_asset_b().asset_id, "output_asset_b_id")?,
+            format!("{:?}", interaction.output_asset_b().asset_type),
+            decimal(&interaction.aux_data()),
+            decimal(&interaction.total_input_value),
+        ])?;
+    }
+
+    let proof = match &block.inner.proof {
+        Some(proof) => proof,
+        None => return Ok(()),
+    };
+    let mut values = vec![rollup_id.to_string(), hash(&proof.public_input_hash)];
+    for g1 in [&proof.recursive_p1, &proof.recursive_p2, &proof.w1, &proof.w2, &proof.w3, &proof.t1, &proof.t2, &proof.t3] {
+        values.extend(point(g1));
+    }
+    for eval in [
+        &proof.w1_eval,
+        &proof.w2_eval,
+        &proof.w3_eval,
+        &proof.sigma_1_eval,
+        &proof.sigma_2_eval,
+        &proof.z_omega_eval,
+    ] {
+        values.push(decimal(eval));
+    }
+    values.extend(point(&proof.pi_z));
+    values.extend(point(&proof.pi_z_omega));
+
+    let placeholders: Vec<String> = (1..=values.len()).map(|i| format!("?{}", i)).collect();
+    tx.prepare_cached(&format!("INSERT INTO proofs VALUES ({})", placeholders.join(", ")))?
+        .execute(rusqlite::params_from_iter(values.iter()))?;
+
+    Ok(())
+}
+
+/// Writes the stored blocks that match `filter` to a new SQLite database at `path`, replacing any
+/// file already there. Row filters narrow the tables that have their field, while the rollups and
+/// proofs tables hold every rollup within the rollup and time filters. Returns false if the export
+/// fails, in which case no database is left at `path`.
+pub fn export_sqlite(store: &dyn BlockStore, path: String, filter: ExportFilter) -> bool {
+    let path = Path::new(&path);
+    let pb = export_progress_bar(store.rollup_ids(filter.rollup_range(0)).len() as u64, "blocks exported");
+
+    match write_database(store, path, &filter, &pb) {
+        Ok(rollup_count) => {
+            pb.finish();
+            println!("Exported {} rollups to {:?} in {:.2} seconds", rollup_count, path, pb.elapsed().as_secs_f64());
+            true
+        }
+        Err(err) => {
+            pb.finish_and_clear();
+            println!("Failed to export to {:?}: {}", path, err);
+            if path.exists() {
+                let _ = fs::remove_file(path);
+            }
+            false
+        }
+    }
+}
+
+/// Writes the database in one transaction, returning the number of rollups in it.
+fn write_database(store: &dyn BlockStore, path: &Path, filter: &ExportFilter, pb: &ProgressBar) -> Result<u64, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| format!("Failed to create {:?}: {}", parent, err))?;
+    }
+    if path.exists() {
+        fs::remove_file(path).map_err(|err| format!("Failed to remove the earlier export: {}", err))?;
+    }
+
+    let sql = |action: &'static str| move |err: rusqlite::Error| format!("Failed to {}: {}", action, err);
+    let mut conn = Connection::open(path).map_err(sql("create the database"))?;
+    conn.execute_batch(SCHEMA).map_err(sql("create the tables"))?;
+
+    let mut rollup_count = 0;
+    let mut inserted = Ok(());
+    let tx = conn.transaction().map_err(sql("start a transaction"))?;
+    store
+        .for_each_block(filter.rollup_range(0), Parts::ALL, &mut |block| {
+            pb.inc(1);
+            if inserted.is_err() || !filter.matches_block(&block) {
+                return;
+            }
+            inserted = insert_block(&tx, &block, filter)
+                .map_err(|err| format!("Failed to insert rollup {}: {}", block.inner.header.rollup_id, err));
+            rollup_count += 1;
+        })
+        .map_err(|err| err.to_string())?;
+    inserted?;
+
+    tx.execute_batch(INDEXES).map_err(sql("create the indexes"))?;
+    tx.commit().map_err(sql("commit the export"))?;
+    Ok(rollup_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::WriteBatch;
+    use crate::types::block::FeeAsset;
+    use crate::types::rollup_proof::Proof;
+    use crate::types::transaction::{InnerProofData, ProofId};
+
+    fn block(rollup_id: u64) -> Block {
+        let mut block = Block::default();
+        block.inner.proof = Some(Proof::default());
+        block.metadata.timestamp = Some(U256::from(1_661_990_400 + rollup_id * 3600));
+        block.inner.header.rollup_id = U256::from(rollup_id);
+        for (proof_id, asset_id) in [(ProofId::Deposit, 0u64), (ProofId::Send, 0), (ProofId::Withdraw, 1)] {
+            block.inner.inner_proofs.push(InnerProofData { proof_id, public_asset_id: U256::from(asset_id), ..Default::default() });
+        }
+        for (slot, asset_id) in [(0, 0u64), (1, 1)] {
+            block.inner.header.fee_slots.push(FeeAsset { slot, asset_id: U256::from(asset_id), amount: U256::one() });
+        }
+        block
+    }
+
+    #[test]
+    fn row_filters_narrow_the_tables_with_their_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in 0..4 {
+            batch.put_block(block(rollup_id));
+        }
+        store.write(batch).unwrap();
+
+        let filter = ExportFilter { from_rollup: Some(1), to_rollup: Some(2), asset_ids: vec![1], ..Default::default() };
+        let path = dir.path().join("export.sqlite");
+        export_sqlite(&store, path.to_str().unwrap().to_string(), filter);
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT count(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("rollups"), 2);
+        assert_eq!(count("proofs"), 2);
+        assert_eq!(count("inner_txs"), 2);
+        assert_eq!(count("fees"), 2);
+        let proof_id: String = conn.query_row("SELECT DISTINCT proof_id FROM inner_txs", [], |row| row.get(0)).unwrap();
+        assert_eq!(proof_id, "Withdraw");
+    }
+
+    #[test]
+    fn values_that_dont_fit_fail_the_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in 0..2 {
+            let mut block = block(rollup_id);
+            for (position, tx) in block.inner.inner_proofs.iter_mut().enumerate() {
+                tx.tx_position = position as u64;
+            }
+            if rollup_id == 1 {
+                block.inner.inner_proofs[0].public_asset_id = U256::from(u64::MAX);
+            }
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+
+        let path = dir.path().join("export.sqlite");
+        assert!(!export_sqlite(&store, path.to_str().unwrap().to_string(), ExportFilter::default()));
+        assert!(!path.exists());
+
+        let err = write_database(&store, &path, &ExportFilter::default(), &ProgressBar::hidden()).unwrap_err();
+        assert_eq!(err, "Failed to insert rollup 1: public_asset_id 18446744073709551615 doesn't fit in an INTEGER column");
+    }
+}
@@ -0,0 +1,346 @@
+use crate::types::block::{Block, FeeAsset};
+use crate::types::defi_interaction::{AssetType, DefiInteraction};
+use crate::types::transaction::{InnerProofData, ProofId};
+use chrono::{DateTime, NaiveDate};
+use ethers::types::{Address, U256};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+const PROOF_IDS: [ProofId; 7] = [
+    ProofId::Padding,
+    ProofId::Deposit,
+    ProofId::Withdraw,
+    ProofId::Send,
+    ProofId::Account,
+    ProofId::DefiDeposit,
+    ProofId::DefiClaim,
+];
+
+/// Accepts the names as exported, in any case and with or without dashes, like `defi-deposit`.
+fn parse_proof_id(value: &str) -> Result<ProofId, String> {
+    let normalized = value.replace(['-', '_'], "").to_lowercase();
+    PROOF_IDS
+        .iter()
+        .find(|proof_id| format!("{:?}", proof_id).to_lowercase() == normalized)
+        .copied()
+        .ok_or_else(|| {
+            let names: Vec<String> = PROOF_IDS.iter().map(|proof_id| format!("{:?}", proof_id)).collect();
+            format!("expected one of {}", names.join(", "))
+        })
+}
+
+/// Parses `YYYY-MM-DD`, an RFC 3339 time or unix seconds, along with whether only a date was given.
+fn parse_time(value: &str) -> Result<(i64, bool), String> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok((seconds, false));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok((date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp(), true));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| (time.timestamp(), false))
+        .map_err(|_| String::from("expected YYYY-MM-DD, an RFC 3339 time or unix seconds"))
+}
+
+fn parse_from_date(value: &str) -> Result<i64, String> {
+    parse_time(value).map(|(timestamp, _)| timestamp)
+}
+
+/// The first second after the bound, so a date includes the whole day.
+fn parse_to_date(value: &str) -> Result<i64, String> {
+    parse_time(value).map(|(timestamp, whole_day)| match whole_day {
+        true => timestamp + 24 * 60 * 60,
+        false => timestamp + 1,
+    })
+}
+
+/// The filters on rows that only some exports have the fields for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowFilter {
+    ProofId,
+    AssetId,
+    BridgeId,
+    Owner,
+}
+
+impl RowFilter {
+    fn flag(&self) -> &'static str {
+        match self {
+            RowFilter::ProofId => "--proof-id",
+            RowFilter::AssetId => "--asset-id",
+            RowFilter::BridgeId => "--bridge-id",
+            RowFilter::Owner => "--owner",
+        }
+    }
+}
+
+/// Selects the rollups and rows an export writes. Filters left out match everything, and a list
+/// matches any of its values.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ExportFilter {
+    #[clap(long, help = "Only rollups with at least this id")]
+    pub from_rollup: Option<u64>,
+    #[clap(long, help = "Only rollups with at most this id")]
+    pub to_rollup: Option<u64>,
+    #[clap(long, value_parser = parse_from_date, help = "Only rollups published at or after this time, as YYYY-MM-DD, RFC 3339 or unix seconds")]
+    pub from_date: Option<i64>,
+    #[clap(long, value_parser = parse_to_date, help = "Only rollups published at or before this time, a date includes the whole day")]
+    pub to_date: Option<i64>,
+    #[clap(long = "proof-id", value_delimiter = ',', value_parser = parse_proof_id, help = "Only txs of these proof types, like deposit,withdraw")]
+    pub proof_ids: Vec<ProofId>,
+    #[clap(long = "asset-id", value_delimiter = ',', help = "Only rows of these asset ids, for txs only deposits and withdrawals and for defi interactions any of the real input or output assets")]
+    pub asset_ids: Vec<u64>,
+    #[clap(long = "bridge-id", value_delimiter = ',', help = "Only defi interactions with these bridge address ids")]
+    pub bridge_ids: Vec<u64>,
+    #[clap(long = "owner", value_delimiter = ',', help = "Only deposits and withdrawals with these public owners")]
+    pub owners: Vec<Address>,
+}
+
+fn any_of<T: PartialEq>(values: &[T], value: &T) -> bool {
+    values.is_empty() || values.contains(value)
+}
+
+/// Ids are compared as `u64`, like the SQLite and Parquet exports store them. Public asset ids take
+/// 4 bytes of calldata and bridge address ids 32 bits of the bridge call data, but fee asset ids are
+/// whole words, so an id above 64 bits matches no filter rather than panicking.
+fn any_id(ids: &[u64], value: &U256) -> bool {
+    ids.is_empty() || (*value <= U256::from(u64::MAX) && ids.contains(&value.low_u64()))
+}
+
+impl ExportFilter {
+    fn is_set(&self, filter: RowFilter) -> bool {
+        match filter {
+            RowFilter::ProofId => !self.proof_ids.is_empty(),
+            RowFilter::AssetId => !self.asset_ids.is_empty(),
+            RowFilter::BridgeId => !self.bridge_ids.is_empty(),
+            RowFilter::Owner => !self.owners.is_empty(),
+        }
+    }
+
+    /// Exits if a filter is set that the rows of `export` don't have the field for, rather than
+    /// silently exporting everything.
+    pub fn check(&self, export: &str, supported: &[RowFilter]) {
+        let all = [RowFilter::ProofId, RowFilter::AssetId, RowFilter::BridgeId, RowFilter::Owner];
+        let unsupported: Vec<&str> = all
+            .iter()
+            .filter(|filter| self.is_set(**filter) && !supported.contains(filter))
+            .map(|filter| filter.flag())
+            .collect();
+        if !unsupported.is_empty() {
+            eprintln!("{} can't be used with {}", unsupported.join(", "), export);
+            std::process::exit(1);
+        }
+    }
+
+    /// The rollups to read, starting no earlier than `first`.
+    pub fn rollup_range(&self, first: u64) -> RangeInclusive<u64> {
+        first.max(self.from_rollup.unwrap_or(0))..=self.to_rollup.unwrap_or(u64::MAX)
+    }
+
+    /// Blocks without a timestamp never match a time filter.
+    pub fn matches_block(&self, block: &Block) -> bool {
+        if self.from_date.is_none() && self.to_date.is_none() {
+            return true;
+        }
+        match block.metadata.timestamp {
+            Some(timestamp) => {
+                let timestamp = timestamp.as_u64() as i64;
+                self.from_date.is_none_or(|from| timestamp >= from) && self.to_date.is_none_or(|to| timestamp < to)
+            }
+            None => false,
+        }
+    }
+
+    /// The asset and owner filters only match deposits and withdrawals, as the zero asset id and
+    /// owner of other txs aren't theirs.
+    pub fn matches_tx(&self, tx: &InnerProofData) -> bool {
+        let public = tx.proof_id.has_public_fields();
+        any_of(&self.proof_ids, &tx.proof_id)
+            && (self.asset_ids.is_empty() || public && any_id(&self.asset_ids, &tx.public_asset_id))
+            && (self.owners.is_empty() || public && any_of(&self.owners, &tx.public_owner))
+    }
+
+    pub fn matches_fee(&self, fee: &FeeAsset) -> bool {
+        any_id(&self.asset_ids, &fee.asset_id)
+    }
+
+    pub fn matches_interaction(&self, interaction: &DefiInteraction) -> bool {
+        let real_assets: Vec<U256> = [
+            interaction.input_asset_a(),
+            interaction.input_asset_b(),
+            interaction.output_asset_a(),
+            interaction.output_asset_b(),
+        ]
+        .into_iter()
+        .filter(|asset| matches!(asset.asset_type, AssetType::Real))
+        .map(|asset| asset.asset_id)
+        .collect();
+
+        any_id(&self.bridge_ids, &interaction.bridge_address_id())
+            && (self.asset_ids.is_empty() || real_assets.iter().any(|asset_id| any_id(&self.asset_ids, asset_id)))
+    }
+
+    /// The filters that are set, for the manifest of incremental exports.
+    pub fn options(&self) -> BTreeMap<String, String> {
+        fn join<T>(values: &[T], format: impl Fn(&T) -> String) -> String {
+            values.iter().map(format).collect::<Vec<String>>().join(",")
+        }
+
+        let mut options = BTreeMap::new();
+        let mut set = |name: &str, value: String| {
+            options.insert(String::from(name), value);
+        };
+        if let Some(from) = self.from_rollup {
+            set("from_rollup", from.to_string());
+        }
+        if let Some(to) = self.to_rollup {
+            set("to_rollup", to.to_string());
+        }
+        if let Some(from) = self.from_date {
+            set("from_date", from.to_string());
+        }
+        if let Some(to) = self.to_date {
+            set("to_date", to.to_string());
+        }
+        if !self.proof_ids.is_empty() {
+            set("proof_ids", join(&self.proof_ids, |proof_id| format!("{:?}", proof_id)));
+        }
+        if !self.asset_ids.is_empty() {
+            set("asset_ids", join(&self.asset_ids, u64::to_string));
+        }
+        if !self.bridge_ids.is_empty() {
+            set("bridge_ids", join(&self.bridge_ids, u64::to_string));
+        }
+        if !self.owners.is_empty() {
+            set("owners", join(&self.owners, |owner| format!("{:?}", owner)));
+        }
+        options
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::bridge_call_data::{BridgeCallData, VIRTUAL_ASSET_ID_FLAG};
+
+    fn block_at(timestamp: Option<u64>) -> Block {
+        let mut block = Block::default();
+        block.metadata.timestamp = timestamp.map(U256::from);
+        block
+    }
+
+    fn interaction(input_asset_id_a: u32, output_asset_id_a: u32) -> DefiInteraction {
+        let bridge_call_data = BridgeCallData { bridge_address_id: 7, input_asset_id_a, output_asset_id_a, ..Default::default() };
+        DefiInteraction::try_from((U256::try_from(bridge_call_data).unwrap(), U256::zero())).unwrap()
+    }
+
+    #[test]
+    fn parses_dates_times_and_unix_seconds() {
+        assert_eq!(parse_from_date("1661990400"), Ok(1_661_990_400));
+        assert_eq!(parse_from_date("2022-09-01"), Ok(1_661_990_400));
+        assert_eq!(parse_from_date("2022-09-01T02:00:00+02:00"), Ok(1_661_990_400));
+        assert!(parse_from_date("2022-13-01").is_err());
+        assert!(parse_from_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn to_dates_include_the_whole_day() {
+        assert_eq!(parse_to_date("2022-09-01"), Ok(1_661_990_400 + 24 * 60 * 60));
+        assert_eq!(parse_to_date("1661990400"), Ok(1_661_990_401));
+
+        let filter = ExportFilter { to_date: parse_to_date("2022-09-01").ok(), ..Default::default() };
+        assert!(filter.matches_block(&block_at(Some(1_661_990_400 + 24 * 60 * 60 - 1))));
+        assert!(!filter.matches_block(&block_at(Some(1_661_990_400 + 24 * 60 * 60))));
+    }
+
+    #[test]
+    fn time_filters_skip_blocks_without_timestamps() {
+        let filter = ExportFilter { from_date: Some(0), ..Default::default() };
+        assert!(!filter.matches_block(&block_at(None)));
+        assert!(ExportFilter::default().matches_block(&block_at(None)));
+    }
+
+    #[test]
+    fn parses_proof_ids_as_exported() {
+        assert_eq!(parse_proof_id("defi-deposit"), Ok(ProofId::DefiDeposit));
+        assert_eq!(parse_proof_id("DEFI_CLAIM"), Ok(ProofId::DefiClaim));
+        assert_eq!(parse_proof_id("withdraw"), Ok(ProofId::Withdraw));
+        assert!(parse_proof_id("mint").is_err());
+    }
+
+    #[test]
+    fn rollup_range_starts_at_the_later_bound() {
+        let filter = ExportFilter { from_rollup: Some(10), to_rollup: Some(20), ..Default::default() };
+        assert_eq!(filter.rollup_range(0), 10..=20);
+        assert_eq!(filter.rollup_range(15), 15..=20);
+        assert_eq!(ExportFilter::default().rollup_range(3), 3..=u64::MAX);
+    }
+
+    #[test]
+    fn lists_match_any_of_their_values() {
+        let filter = ExportFilter {
+            proof_ids: vec![ProofId::Deposit, ProofId::Withdraw],
+            owners: vec![Address::repeat_byte(1)],
+            ..Default::default()
+        };
+        let tx = |proof_id: ProofId, owner: u8| InnerProofData {
+            proof_id,
+            public_owner: Address::repeat_byte(owner),
+            ..Default::default()
+        };
+        assert!(filter.matches_tx(&tx(ProofId::Withdraw, 1)));
+        assert!(!filter.matches_tx(&tx(ProofId::Send, 1)));
+        assert!(!filter.matches_tx(&tx(ProofId::Deposit, 2)));
+    }
+
+    #[test]
+    fn asset_and_owner_filters_only_match_deposits_and_withdrawals() {
+        let tx = |proof_id: ProofId| InnerProofData { proof_id, ..Default::default() };
+        let by_asset = ExportFilter { asset_ids: vec![0], ..Default::default() };
+        assert!(by_asset.matches_tx(&tx(ProofId::Deposit)));
+        assert!(by_asset.matches_tx(&tx(ProofId::Withdraw)));
+        for proof_id in [ProofId::Send, ProofId::Account, ProofId::DefiDeposit, ProofId::DefiClaim, ProofId::Padding] {
+            assert!(!by_asset.matches_tx(&tx(proof_id)));
+        }
+
+        let by_owner = ExportFilter { owners: vec![Address::zero()], ..Default::default() };
+        assert!(by_owner.matches_tx(&tx(ProofId::Withdraw)));
+        assert!(!by_owner.matches_tx(&tx(ProofId::Send)));
+        assert!(ExportFilter::default().matches_tx(&tx(ProofId::Send)));
+    }
+
+    #[test]
+    fn interactions_match_real_assets_only() {
+        let filter = ExportFilter { asset_ids: vec![3], ..Default::default() };
+        assert!(filter.matches_interaction(&interaction(3, 0)));
+        assert!(filter.matches_interaction(&interaction(0, 3)));
+        // The unused second input has asset id 3 in its bits, but isn't in use.
+        let mut unused = interaction(0, 0);
+        unused.bridge_call_data.input_asset_id_b = 3;
+        assert!(!filter.matches_interaction(&unused));
+        assert!(!filter.matches_interaction(&interaction(VIRTUAL_ASSET_ID_FLAG | 3, 0)));
+
+        let bridges = ExportFilter { bridge_ids: vec![7], ..Default::default() };
+        assert!(bridges.matches_interaction(&interaction(0, 0)));
+        assert!(!ExportFilter { bridge_ids: vec![8], ..Default::default() }.matches_interaction(&interaction(0, 0)));
+    }
+
+    #[test]
+    fn ids_above_64_bits_match_no_filter() {
+        let filter = ExportFilter { asset_ids: vec![3], ..Default::default() };
+        let fee = |asset_id: U256| FeeAsset { asset_id, ..Default::default() };
+        assert!(filter.matches_fee(&fee(U256::from(3))));
+        assert!(!filter.matches_fee(&fee((U256::one() << 64) + 3)));
+        assert!(ExportFilter::default().matches_fee(&fee(U256::MAX)));
+    }
+
+    #[test]
+    fn options_list_the_filters_set() {
+        let filter = ExportFilter { from_rollup: Some(5), asset_ids: vec![0, 1], ..Default::default() };
+        let options = filter.options();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options["from_rollup"], "5");
+        assert_eq!(options["asset_ids"], "0,1");
+    }
+}
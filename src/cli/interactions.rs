@@ -0,0 +1,56 @@
+use crate::types::block::NUMBER_OF_BRIDGE_CALLS;
+use crate::types::defi_interaction::DefiInteraction;
+use ethers::types::U256;
+use crate::store::BlockStore;
+use std::collections::HashSet;
+
+/// Walks the virtual inputs of an interaction back through the interactions that created them.
+/// `origins` returns the origin nonces of the virtual inputs of the interaction with a given nonce,
+/// or `None` if that interaction is unknown. The nonces are returned in the order they are visited,
+/// starting with the direct origins of `interaction`.
+pub fn provenance_chain(
+    interaction: &DefiInteraction,
+    mut origins: impl FnMut(U256) -> Option<Vec<U256>>,
+) -> Vec<U256> {
+    let mut chain = vec![];
+    let mut visited = HashSet::new();
+    let mut pending = interaction.input_origins();
+    pending.reverse();
+
+    while let Some(nonce) = pending.pop() {
+        if !visited.insert(nonce) {
+            continue;
+        }
+        chain.push(nonce);
+        if let Some(mut next) = origins(nonce) {
+            next.reverse();
+            pending.extend(next);
+        }
+    }
+
+    chain
+}
+
+pub fn format_chain(chain: &[U256]) -> String {
+    chain
+        .iter()
+        .map(|nonce| nonce.to_string())
+        .collect::<Vec<String>>()
+        .join(" <- ")
+}
+
+/// Looks up the origins of an interaction in the rollup its nonce belongs to. A rollup that can't
+/// be read ends the chain like a missing one, as `check-db` reports it.
+pub fn origins_from_db(store: &dyn BlockStore, nonce: U256) -> Option<Vec<U256>> {
+    let rollup_id = nonce / U256::from(NUMBER_OF_BRIDGE_CALLS);
+    let slot = (nonce % U256::from(NUMBER_OF_BRIDGE_CALLS)).as_usize();
+    let block = store.get_block(rollup_id.as_u64()).ok().flatten()?;
+
+    let origins = block
+        .inner
+        .header
+        .defi_interaction_slots
+        .get(slot)
+        .map(|interaction| interaction.input_origins());
+    origins
+}
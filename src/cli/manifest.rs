@@ -0,0 +1,229 @@
+use crate::cli::filter::ExportFilter;
+use crate::store::BlockStore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Kept next to an export, recording what it holds so later runs can append to it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub export: String,
+    pub format: String,
+    /// The options that decide which rows are exported. Appending with other options would mix
+    /// rows of two exports in one file.
+    pub options: BTreeMap<String, String>,
+    pub last_rollup_id: Option<u64>,
+    pub rows: u64,
+    /// Seconds since the unix epoch.
+    pub updated_at: u64,
+}
+
+impl Manifest {
+    /// The manifest of a single file export, or `None` when streaming to stdout.
+    pub fn path_for_file(export_path: &str) -> Option<PathBuf> {
+        match export_path {
+            "-" => None,
+            path => Some(PathBuf::from(format!("{}.manifest.json", path))),
+        }
+    }
+
+    /// The manifest of an export that writes a directory of files.
+    pub fn path_for_dir(dir: &str) -> Option<PathBuf> {
+        Some(Path::new(dir).join("manifest.json"))
+    }
+
+    fn read(path: &Path) -> Option<Manifest> {
+        let contents = fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(manifest) => Some(manifest),
+            Err(err) => {
+                eprintln!("Ignoring unreadable manifest {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+}
+
+/// Where an export run starts, and the manifest it updates when done.
+pub struct Increment {
+    manifest_path: Option<PathBuf>,
+    manifest: Manifest,
+    /// The last rollup already in the export, or `None` when it is written from scratch.
+    pub after: Option<u64>,
+    first_rollup_id: u64,
+}
+
+impl Increment {
+    /// Resumes after `since` if given, or after the rollup recorded in the manifest with
+    /// `incremental`. Exits if the existing export was written with another format or options, or
+    /// if `since` is before the last rollup in it, which would write rollups twice.
+    pub fn begin(
+        manifest_path: Option<PathBuf>,
+        export: &str,
+        format: &str,
+        options: BTreeMap<String, String>,
+        incremental: bool,
+        since: Option<u64>,
+    ) -> Increment {
+        if incremental && manifest_path.is_none() {
+            eprintln!("--incremental needs an export path to keep the manifest next to, use --since when streaming");
+            std::process::exit(1);
+        }
+
+        let existing = manifest_path.as_deref().and_then(Manifest::read);
+        if let Some(existing) = existing.as_ref().filter(|_| incremental || since.is_some()) {
+            if existing.export != export || existing.format != format || existing.options != options {
+                eprintln!(
+                    "The existing {} export was written as {} with options {:?}, run without --incremental or --since to replace it",
+                    existing.export, existing.format, existing.options
+                );
+                std::process::exit(1);
+            }
+        }
+
+        if let (Some(since), Some(last)) = (since, existing.as_ref().and_then(|existing| existing.last_rollup_id)) {
+            if since < last {
+                eprintln!(
+                    "--since {} is before rollup {}, the last one in the export, run without --since to replace it",
+                    since, last
+                );
+                std::process::exit(1);
+            }
+        }
+
+        let after = match (since, &existing) {
+            (Some(since), _) => Some(since),
+            (None, Some(existing)) if incremental => existing.last_rollup_id,
+            _ => None,
+        };
+        let first_rollup_id = match after.map(|after| after.checked_add(1)) {
+            None => 0,
+            Some(Some(first)) => first,
+            Some(None) => {
+                eprintln!("The export already holds rollup {}, the last possible one", u64::MAX);
+                std::process::exit(1);
+            }
+        };
+        let rows = match (&existing, after) {
+            (Some(existing), Some(_)) => existing.rows,
+            _ => 0,
+        };
+
+        Increment {
+            manifest_path,
+            manifest: Manifest {
+                export: export.to_string(),
+                format: format.to_string(),
+                options,
+                last_rollup_id: after,
+                rows,
+                updated_at: 0,
+            },
+            after,
+            first_rollup_id,
+        }
+    }
+
+    pub fn first_rollup_id(&self) -> u64 {
+        self.first_rollup_id
+    }
+
+    /// The rollups within `filter` that this run reads. When a manifest is kept, the range ends
+    /// before the first rollup missing from the store between stored ones, along with that rollup,
+    /// as the manifest only records the last rollup exported and one filled in below it later
+    /// would never be exported.
+    pub fn rollup_range(&self, store: &dyn BlockStore, filter: &ExportFilter) -> (RangeInclusive<u64>, Option<u64>) {
+        let range = filter.rollup_range(self.first_rollup_id);
+        if self.manifest_path.is_none() {
+            return (range, None);
+        }
+
+        let ids = store.rollup_ids(range.clone());
+        // An append continues right after the export, while a new export starts at the first stored rollup.
+        let mut expected = match (self.appending(), ids.first()) {
+            (false, Some(first)) => *first,
+            _ => *range.start(),
+        };
+        for id in ids {
+            if id != expected {
+                return (*range.start()..=expected - 1, Some(expected));
+            }
+            match id.checked_add(1) {
+                Some(next) => expected = next,
+                None => break,
+            }
+        }
+        (range, None)
+    }
+
+    pub fn appending(&self) -> bool {
+        self.after.is_some()
+    }
+
+    /// Removes the manifest of an export that a failed run was replacing, as the files it describes
+    /// are gone.
+    pub fn abandon(self) {
+        if let Some(path) = self.manifest_path.filter(|path| path.exists()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Records the last rollup and the rows written by this run in the manifest.
+    pub fn finish(mut self, last_rollup_id: Option<u64>, rows: u64) {
+        let path = match self.manifest_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        self.manifest.last_rollup_id = last_rollup_id.or(self.manifest.last_rollup_id);
+        self.manifest.rows += rows;
+        self.manifest.updated_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        fs::write(&path, serde_json::to_string_pretty(&self.manifest).unwrap()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::WriteBatch;
+    use crate::types::block::Block;
+    use ethers::types::U256;
+
+    fn store(dir: &Path, rollup_ids: &[u64]) -> SqliteStore {
+        let store = SqliteStore::open(&dir.join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in rollup_ids {
+            let mut block = Block::default();
+            block.inner.header.rollup_id = U256::from(*rollup_id);
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+        store
+    }
+
+    fn increment(dir: &Path, since: Option<u64>) -> Increment {
+        Increment::begin(Manifest::path_for_dir(dir.to_str().unwrap()), "txs", "csv", BTreeMap::new(), false, since)
+    }
+
+    #[test]
+    fn exports_stop_before_rollups_missing_from_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), &[3, 4, 6, 7]);
+        let read = |increment: Increment| {
+            let (range, missing) = increment.rollup_range(&store, &ExportFilter::default());
+            (store.rollup_ids(range), missing)
+        };
+
+        assert_eq!(read(increment(dir.path(), None)), (vec![3, 4], Some(5)));
+        assert_eq!(read(increment(dir.path(), Some(2))), (vec![3, 4], Some(5)));
+        assert_eq!(read(increment(dir.path(), Some(4))), (vec![], Some(5)));
+        assert_eq!(read(increment(dir.path(), Some(5))), (vec![6, 7], None));
+        assert_eq!(read(increment(dir.path(), Some(1))), (vec![], Some(2)));
+        // Without a manifest there is nothing to keep consistent.
+        assert_eq!(read(Increment::begin(None, "txs", "csv", BTreeMap::new(), false, None)), (vec![3, 4, 6, 7], None));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::store::schema::SCHEMA_VERSION;
+use crate::store::sqlite::{SqliteStore, DB_FILE};
+use std::path::Path;
+
+/// Upgrades the database in `data_path` to the schema version of this build, keeping a copy of it
+/// at the version it had next to it. Returns whether the database is usable afterwards.
+pub fn migrate(data_path: &Path) -> bool {
+    let path = data_path.join(DB_FILE);
+    let version = match SqliteStore::schema_version(&path) {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            println!("No database at {:?}, nothing to migrate", path);
+            return true;
+        }
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+
+    if version == SCHEMA_VERSION {
+        println!("The database is already at schema version {}", version);
+        return true;
+    }
+    if version > SCHEMA_VERSION {
+        println!(
+            "The database is at schema version {}, which is newer than the version {} of this build",
+            version, SCHEMA_VERSION
+        );
+        return false;
+    }
+
+    let backup = path.with_extension(format!("sqlite.v{}.bak", version));
+    if backup.exists() {
+        println!("A backup already exists at {:?}, move it away before migrating", backup);
+        return false;
+    }
+
+    println!("Backing up the database to {:?}", backup);
+    let result = SqliteStore::upgrade(&path, &backup, |migration| {
+        println!("Migrating to schema version {}: {}", migration.to, migration.description);
+    });
+    match result {
+        Ok(_) => {
+            println!("Migrated the database from schema version {} to {}", version, SCHEMA_VERSION);
+            true
+        }
+        Err(err) => {
+            println!("Migration failed, the database is unchanged: {}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::records::tests::baseline_block;
+    use crate::store::BlockStore;
+    use ethers::types::U256;
+    use rusqlite::{params, Connection};
+
+    /// A store as the first SQLite builds wrote it: no recorded version, no proof column and
+    /// blocks in record layout 0.
+    fn version_0_store(path: &Path, data: impl Fn(u64) -> Vec<u8>) {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE blocks (
+                rollup_id INTEGER PRIMARY KEY,
+                block_number INTEGER NOT NULL,
+                data BLOB NOT NULL
+            );
+            CREATE TABLE defi_results (
+                interaction_nonce INTEGER PRIMARY KEY,
+                block_number INTEGER NOT NULL,
+                data BLOB NOT NULL
+            );
+            CREATE TABLE index_entries (
+                name TEXT NOT NULL,
+                key BLOB NOT NULL,
+                rollup_id INTEGER NOT NULL,
+                tx_position INTEGER NOT NULL,
+                PRIMARY KEY (name, key, rollup_id, tx_position)
+            ) WITHOUT ROWID;",
+        )
+        .unwrap();
+        for rollup_id in 0u64..3 {
+            conn.execute(
+                "INSERT INTO blocks (rollup_id, block_number, data) VALUES (?1, ?2, ?3)",
+                params![rollup_id as i64, 15_000_000 + rollup_id as i64, data(rollup_id)],
+            )
+            .unwrap();
+        }
+    }
+
+    fn baseline_record(rollup_id: u64) -> Vec<u8> {
+        let mut block = baseline_block(rollup_id);
+        block.metadata.block_number = (15_000_000 + rollup_id).into();
+        bincode::serialize(&block).unwrap()
+    }
+
+    #[test]
+    fn upgrades_version_0_stores_after_backing_them_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DB_FILE);
+        version_0_store(&path, baseline_record);
+        assert!(SqliteStore::open(&path).is_err());
+
+        assert!(migrate(dir.path()));
+
+        let backup = dir.path().join("gobbler.sqlite.v0.bak");
+        assert_eq!(SqliteStore::schema_version(&backup).unwrap(), Some(0));
+        assert_eq!(SqliteStore::schema_version(&path).unwrap(), Some(SCHEMA_VERSION));
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.block_count(), 3);
+        let block = store.get_block(1).unwrap().unwrap();
+        assert_eq!(block.inner.inner_proofs[1].note_commitment_1_index, 64 + 2);
+        assert!(block.inner.proof.is_some());
+        let block = store.get_block_by_block_number(15_000_002).unwrap().unwrap();
+        assert_eq!(block.inner.header.rollup_id, U256::from(2));
+
+        // A second run has nothing to do, and leaves the backup alone.
+        assert!(migrate(dir.path()));
+    }
+
+    #[test]
+    fn keeps_version_0_stores_with_unreadable_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DB_FILE);
+        version_0_store(&path, |rollup_id| match rollup_id {
+            1 => vec![0],
+            _ => baseline_record(rollup_id),
+        });
+
+        assert!(!migrate(dir.path()));
+        assert_eq!(SqliteStore::schema_version(&path).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn refuses_stores_of_newer_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DB_FILE);
+        version_0_store(&path, baseline_record);
+        Connection::open(&path).unwrap().pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+
+        assert!(!migrate(dir.path()));
+        assert!(SqliteStore::open(&path).is_err());
+        assert!(!dir.path().join(format!("gobbler.sqlite.v{}.bak", SCHEMA_VERSION + 1)).exists());
+    }
+}
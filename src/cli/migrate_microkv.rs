@@ -0,0 +1,114 @@
+use crate::cli::nullifiers::{index_nullifiers, report_anomalies};
+use crate::cli::search::index_block;
+use crate::store::records;
+use crate::store::{BlockStore, StoreError, WriteBatch};
+use crate::types::block::Block;
+use indicatif::{ProgressBar, ProgressStyle};
+use microkv::MicroKV;
+use std::fs;
+use std::path::Path;
+
+/// The only MicroKV store the releases before the `BlockStore` wrote, holding the blocks.
+const MICROKV_STORE: &str = "gobbler";
+
+fn microkv_path(data_path: &Path) -> std::path::PathBuf {
+    MicroKV::get_db_path_with_base_path(MICROKV_STORE, data_path.to_path_buf())
+}
+
+/// Decodes a MicroKV block record, which is only taken if it reads as the rollup of its key.
+fn decode_microkv_block(rollup_id: u64, data: &[u8]) -> Option<Block> {
+    records::decode_block(0, data)
+        .ok()
+        .filter(|block| block.inner.header.rollup_id == rollup_id.into())
+}
+
+/// Moves the blocks of a MicroKV data dir into `store`, the first time the store is opened in a data
+/// dir that has them. Blocks are converted from the layout of the MicroKV releases to the current
+/// one, and the nullifier and search indexes are built from them. Everything is written in a single
+/// batch, so a data dir holding an entry that can't be read is refused as a whole. The MicroKV file
+/// is renamed afterwards, so this only happens once.
+pub fn migrate_microkv(data_path: &Path, store: &dyn BlockStore) -> Result<(), StoreError> {
+    if !microkv_path(data_path).is_file() || store.block_count() > 0 {
+        return Ok(());
+    }
+
+    println!("Migrating MicroKV database in {:?}", data_path);
+    let blocks = MicroKV::open_with_base_path(MICROKV_STORE, data_path.to_path_buf()).expect("Failed to open database");
+    let mut keys: Vec<u64> = blocks
+        .keys()
+        .unwrap()
+        .iter()
+        .filter_map(|key| key.parse().ok())
+        .collect();
+    keys.sort();
+
+    let pb = ProgressBar::new(keys.len() as u64);
+    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] blocks migrated {pos}/{len}")
+        .unwrap()
+        .progress_chars("#>-"));
+
+    let incompatible = |key: &dyn std::fmt::Debug| {
+        StoreError(format!(
+            "Entry {:?} in {:?} can't be read by this version of the gobbler. Move the data dir away and sync again",
+            key, data_path
+        ))
+    };
+
+    let mut batch = WriteBatch::default();
+    for key in keys.iter() {
+        // Values are kept as their bincode encoding, as no password is set. MicroKV data dirs
+        // predate the L1 cost of blocks, which `check-db --repair` fills in.
+        let data = blocks
+            .lock_read(|kv| kv.get(&format!("{:?}", key)).map(|value| value.unsecure().to_vec()))
+            .unwrap();
+        let block = match data.and_then(|data| decode_microkv_block(*key, &data)) {
+            Some(block) => block,
+            None => return Err(incompatible(key)),
+        };
+        report_anomalies(&index_nullifiers(store, &mut batch, &block));
+        index_block(&mut batch, &block);
+        batch.put_block(block);
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+
+    store.write(batch)?;
+
+    let path = microkv_path(data_path);
+    fs::rename(&path, path.with_extension("kv.migrated"))
+        .map_err(|err| StoreError(format!("Failed to rename {:?} after migrating it: {}", path, err)))?;
+
+    println!("Migrated {} blocks", keys.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::records::tests::baseline_block;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::store::Index;
+    use ethers::types::{H256, U256};
+
+    #[test]
+    fn migrates_baseline_data_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_path = dir.path().to_path_buf();
+        let kv = MicroKV::new_with_base_path("gobbler", data_path.clone());
+        for rollup_id in 0u64..2 {
+            kv.put(format!("{:?}", rollup_id), &baseline_block(rollup_id)).unwrap();
+        }
+        kv.commit().unwrap();
+
+        let store = SqliteStore::open(&data_path.join(DB_FILE)).unwrap();
+        migrate_microkv(&data_path, &store).unwrap();
+
+        assert_eq!(store.block_count(), 2);
+        let block = store.get_block(1).unwrap().unwrap();
+        assert_eq!(block.inner.header.rollup_id, U256::from(1));
+        assert_eq!(block.inner.inner_proofs[1].note_commitment_1_index, 64 + 2);
+        assert_eq!(block.inner.header.defi_interaction_slots[0].interaction_nonce, U256::from(32));
+        assert_eq!(store.lookup(Index::Nullifier, H256::repeat_byte(20).as_bytes()).len(), 2);
+        assert!(!microkv_path(&data_path).exists());
+    }
+}
@@ -1,2 +1,19 @@
 pub(crate) mod export;
-pub(crate) mod collector;
\ No newline at end of file
+pub(crate) mod collector;
+pub(crate) mod verify;
+pub(crate) mod nullifiers;
+pub(crate) mod bridge_call_data;
+pub(crate) mod interactions;
+pub(crate) mod annotate;
+pub(crate) mod decode;
+pub(crate) mod migrate_microkv;
+pub(crate) mod search;
+pub(crate) mod migrate;
+pub(crate) mod check;
+pub(crate) mod export_sqlite;
+pub(crate) mod export_parquet;
+pub(crate) mod manifest;
+pub(crate) mod filter;
+pub(crate) mod columns;
+pub(crate) mod stats;
+pub(crate) mod data_tree;
@@ -0,0 +1,125 @@
+use crate::store::{BlockStore, Index, TxLocation, WriteBatch};
+use crate::types::block::Block;
+use ethers::types::H256;
+
+/// Adds the nullifiers of a block to the batch, returning the nullifiers that were already spent
+/// somewhere else. A nullifier should only ever be spent once, so any of those is an anomaly.
+pub fn index_nullifiers(store: &dyn BlockStore, batch: &mut WriteBatch, block: &Block) -> Vec<(H256, Vec<TxLocation>)> {
+    let mut anomalies = vec![];
+    let rollup_id = block.inner.header.rollup_id.as_u64();
+
+    for tx in block.inner.inner_proofs.iter() {
+        let spend = TxLocation {
+            rollup_id,
+            tx_position: Some(tx.tx_position),
+        };
+
+        for nullifier in [tx.nullifier_1, tx.nullifier_2] {
+            if nullifier.is_zero() {
+                continue;
+            }
+
+            let mut spends = store.lookup(Index::Nullifier, nullifier.as_bytes());
+            spends.extend(
+                batch
+                    .index_entries
+                    .iter()
+                    .filter(|(index, key, _)| *index == Index::Nullifier && key == nullifier.as_bytes())
+                    .map(|(_, _, location)| *location),
+            );
+            // Re-syncing a block must not count as spending its nullifiers again.
+            if spends.contains(&spend) {
+                continue;
+            }
+            spends.push(spend);
+            if spends.len() > 1 {
+                anomalies.push((nullifier, spends));
+            }
+            batch.index(Index::Nullifier, nullifier.as_bytes(), spend);
+        }
+    }
+
+    anomalies
+}
+
+pub fn report_anomalies(anomalies: &[(H256, Vec<TxLocation>)]) {
+    for (nullifier, spends) in anomalies.iter() {
+        println!("Anomaly: nullifier {:?} spent {} times: {:?}", nullifier, spends.len(), spends);
+    }
+}
+
+/// Prints where a nullifier was spent. Returns whether it was spent exactly once, so that scripts
+/// can tell an unspent nullifier or a double spend from the exit status.
+pub fn lookup_nullifier(store: &dyn BlockStore, nullifier: H256) -> bool {
+    let spends = store.lookup(Index::Nullifier, nullifier.as_bytes());
+    if spends.is_empty() {
+        println!("Nullifier {:?} has not been spent", nullifier);
+        return false;
+    }
+
+    for spend in spends.iter() {
+        println!(
+            "Nullifier {:?} spent in rollup {} at tx position {}",
+            nullifier,
+            spend.rollup_id,
+            spend.tx_position.unwrap_or_default()
+        );
+    }
+    if spends.len() > 1 {
+        println!("Anomaly: nullifier spent {} times", spends.len());
+    }
+    spends.len() == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::types::transaction::InnerProofData;
+    use ethers::types::U256;
+
+    /// A block of txs that spend one nullifier each, the second one of every tx is left zero.
+    fn block(rollup_id: u64, nullifiers: &[u8]) -> Block {
+        let mut block = Block::default();
+        block.inner.header.rollup_id = U256::from(rollup_id);
+        for (position, nullifier) in nullifiers.iter().enumerate() {
+            let mut tx = InnerProofData { nullifier_1: H256::repeat_byte(*nullifier), ..Default::default() };
+            tx.set_position(U256::zero(), position as u64).unwrap();
+            block.inner.inner_proofs.push(tx);
+        }
+        block
+    }
+
+    #[test]
+    fn reports_nullifiers_spent_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+
+        let first = block(1, &[1, 2]);
+        let mut batch = WriteBatch::default();
+        assert!(index_nullifiers(&store, &mut batch, &first).is_empty());
+        batch.put_block(first);
+        store.write(batch).unwrap();
+
+        // Indexing the same block again isn't a second spend.
+        let mut batch = WriteBatch::default();
+        assert!(index_nullifiers(&store, &mut batch, &block(1, &[1, 2])).is_empty());
+
+        // A spend in the store and one in the same batch are both found.
+        let mut batch = WriteBatch::default();
+        let anomalies = index_nullifiers(&store, &mut batch, &block(2, &[3, 3, 2]));
+        let nullifiers: Vec<H256> = anomalies.iter().map(|(nullifier, _)| *nullifier).collect();
+        assert_eq!(nullifiers, vec![H256::repeat_byte(3), H256::repeat_byte(2)]);
+        assert_eq!(anomalies[1].1, vec![
+            TxLocation { rollup_id: 1, tx_position: Some(1) },
+            TxLocation { rollup_id: 2, tx_position: Some(2) },
+        ]);
+
+        // Lookups only succeed for nullifiers spent exactly once.
+        batch.put_block(block(2, &[3, 3, 2]));
+        store.write(batch).unwrap();
+        assert!(lookup_nullifier(&store, H256::repeat_byte(1)));
+        assert!(!lookup_nullifier(&store, H256::repeat_byte(2)));
+        assert!(!lookup_nullifier(&store, H256::repeat_byte(9)));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::cli::nullifiers::{index_nullifiers, report_anomalies};
+use crate::store::{BlockStore, Index, Parts, TxLocation, WriteBatch};
+use crate::types::block::Block;
+use ethers::utils::{hex, to_checksum};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{hash_map::Entry, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchKind {
+    Commitment,
+    Nullifier,
+    TxHash,
+    Owner,
+}
+
+impl SearchKind {
+    fn index(&self) -> Index {
+        match self {
+            SearchKind::Commitment => Index::NoteCommitment,
+            SearchKind::Nullifier => Index::Nullifier,
+            SearchKind::TxHash => Index::L1TxHash,
+            SearchKind::Owner => Index::PublicOwner,
+        }
+    }
+}
+
+/// Adds the note commitments, public owners and L1 tx hash of a block to the batch. Nullifiers are
+/// added by `index_nullifiers`, which also checks them for double spends.
+pub fn index_block(batch: &mut WriteBatch, block: &Block) {
+    let rollup_id = block.inner.header.rollup_id.as_u64();
+    batch.index(
+        Index::L1TxHash,
+        block.metadata.tx_hash.as_bytes(),
+        TxLocation {
+            rollup_id,
+            tx_position: None,
+        },
+    );
+
+    for tx in block.inner.inner_proofs.iter() {
+        let location = TxLocation {
+            rollup_id,
+            tx_position: Some(tx.tx_position),
+        };
+
+        for commitment in [tx.note_commitment_1, tx.note_commitment_2] {
+            if !commitment.is_zero() {
+                batch.index(Index::NoteCommitment, commitment.as_bytes(), location);
+            }
+        }
+        if !tx.public_owner.is_zero() {
+            batch.index(Index::PublicOwner, tx.public_owner.as_bytes(), location);
+        }
+    }
+}
+
+/// Drops every index and rebuilds them from the stored blocks, in a single transaction so an
+/// interrupted or failed rebuild leaves the old indexes in place. Returns false if a block can't be
+/// read or the entries can't be written.
+pub fn reindex(store: &dyn BlockStore) -> bool {
+    let pb = ProgressBar::new(store.block_count());
+    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] blocks indexed {pos}/{len}")
+        .unwrap()
+        .progress_chars("#>-"));
+
+    let rebuilt = store.in_transaction(&mut || {
+        for index in Index::ALL {
+            store.clear_index(index)?;
+        }
+
+        let mut written = Ok(());
+        store.for_each_block(0..=u64::MAX, Parts::TXS, &mut |block| {
+            if written.is_err() {
+                return;
+            }
+            let mut batch = WriteBatch::default();
+            report_anomalies(&index_nullifiers(store, &mut batch, &block));
+            index_block(&mut batch, &block);
+            written = store.write(batch);
+            pb.inc(1);
+        })?;
+        written
+    });
+    pb.finish();
+    if let Err(err) = rebuilt {
+        println!("Failed to reindex, the old indexes are kept: {}", err);
+        return false;
+    }
+
+    println!("Reindexed {} blocks in {:.2} seconds", pb.position(), pb.elapsed().as_secs_f64());
+    true
+}
+
+/// Looks `query` up in the indexes its kind, or its length if no kind is given, allows. Addresses
+/// are 20 bytes, everything else is 32. Returns the number of hits.
+pub fn search(store: &dyn BlockStore, query: &str, kind: Option<SearchKind>) -> usize {
+    let key = match hex::decode(query.strip_prefix("0x").unwrap_or(query)) {
+        Ok(key) => key,
+        Err(_) => {
+            println!("{} is not valid hex", query);
+            return 0;
+        }
+    };
+
+    let indexes = match (kind, key.len()) {
+        (Some(SearchKind::Owner), 20) => vec![Index::PublicOwner],
+        (Some(kind), 32) if kind != SearchKind::Owner => vec![kind.index()],
+        (None, 20) => vec![Index::PublicOwner],
+        (None, 32) => vec![Index::NoteCommitment, Index::Nullifier, Index::L1TxHash],
+        _ => {
+            let expected = kind.map(|kind| kind.index().name()).unwrap_or("search");
+            println!("{} has the wrong length for a {} key", query, expected);
+            return 0;
+        }
+    };
+
+    let mut blocks: HashMap<u64, Block> = HashMap::new();
+    let mut hits = 0;
+
+    println!(
+        "{:<16} {:>7} {:>5} {:<12} {:>28} {:>8} {:<42}",
+        "index", "rollup", "pos", "proof id", "public value", "asset", "public owner"
+    );
+    for index in indexes {
+        for location in store.lookup(index, &key) {
+            let block = match blocks.entry(location.rollup_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match store.get_block(location.rollup_id) {
+                    Ok(Some(block)) => entry.insert(block),
+                    Ok(None) => {
+                        println!(
+                            "{:<16} {:>7}       rollup missing from the store, run `ac-gobbler reindex`",
+                            index.name(),
+                            location.rollup_id
+                        );
+                        continue;
+                    }
+                    Err(err) => {
+                        println!("{}", err);
+                        return 0;
+                    }
+                },
+            };
+            hits += 1;
+
+            let tx = location
+                .tx_position
+                .and_then(|position| block.inner.inner_proofs.iter().find(|tx| tx.tx_position == position));
+            match tx {
+                Some(tx) => println!(
+                    "{:<16} {:>7} {:>5} {:<12} {:>28} {:>8} {:<42}",
+                    index.name(),
+                    location.rollup_id,
+                    tx.tx_position,
+                    format!("{:?}", tx.proof_id),
+                    tx.public_value.to_string(),
+                    tx.public_asset_id.to_string(),
+                    to_checksum(&tx.public_owner, None)
+                ),
+                None => println!(
+                    "{:<16} {:>7}       published in L1 block {} by {}",
+                    index.name(),
+                    location.rollup_id,
+                    block.metadata.block_number,
+                    to_checksum(&block.sequencer, None)
+                ),
+            }
+        }
+    }
+
+    if hits == 0 {
+        println!("No matches for {}", query);
+    }
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{SqliteStore, DB_FILE};
+    use crate::types::transaction::InnerProofData;
+    use ethers::types::{H256, U256};
+
+    #[test]
+    fn reindex_replaces_stale_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut block = Block::default();
+        block.inner.header.rollup_id = U256::from(3);
+        block.inner.inner_proofs.push(InnerProofData { nullifier_1: H256::repeat_byte(1), ..Default::default() });
+        let mut batch = WriteBatch::default();
+        batch.put_block(block);
+        batch.index(Index::Nullifier, H256::repeat_byte(2).as_bytes(), TxLocation { rollup_id: 9, tx_position: Some(0) });
+        store.write(batch).unwrap();
+
+        assert!(reindex(&store));
+
+        let spend = TxLocation { rollup_id: 3, tx_position: Some(0) };
+        assert_eq!(store.lookup(Index::Nullifier, H256::repeat_byte(1).as_bytes()), vec![spend]);
+        assert!(store.lookup(Index::Nullifier, H256::repeat_byte(2).as_bytes()).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::cli::export::{export_progress_bar, ExportFormat, RowWriter};
+use crate::cli::filter::ExportFilter;
+use crate::store::{BlockStore, Parts};
+use crate::types::block::Block;
+use crate::types::transaction::ProofId;
+use chrono::{DateTime, Datelike, Days};
+use ethers::types::{Address, U256};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+/// The proof types that are counted, padding isn't stored.
+const PROOF_IDS: [ProofId; 6] = [
+    ProofId::Deposit,
+    ProofId::Withdraw,
+    ProofId::Send,
+    ProofId::Account,
+    ProofId::DefiDeposit,
+    ProofId::DefiClaim,
+];
+
+const COLUMNS: [&str; 4] = ["period", "metric", "key", "value"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Period {
+    Day,
+    /// Weeks start on Monday, and are labelled by its date.
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The label of the period the L1 time falls in, `unknown` for rollups synced before
+    /// timestamps were recorded.
+    fn label(&self, timestamp: Option<U256>) -> String {
+        let date = match timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp.as_u64() as i64, 0)) {
+            Some(time) => time.date_naive(),
+            None => return String::from("unknown"),
+        };
+        match self {
+            Period::Day => date.format("%Y-%m-%d").to_string(),
+            Period::Week => (date - Days::new(date.weekday().num_days_from_monday() as u64)).format("%Y-%m-%d").to_string(),
+            Period::Month => date.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    /// Aligned columns for the terminal
+    Table,
+    Csv,
+}
+
+/// The aggregates of the rollups published within a period. Amounts are summed per asset id, as
+/// amounts of different assets can't be added up.
+#[derive(Default)]
+struct PeriodStats {
+    rollups: u64,
+    txs: [u64; PROOF_IDS.len()],
+    deposits: BTreeMap<U256, U256>,
+    withdrawals: BTreeMap<U256, U256>,
+    fees: BTreeMap<U256, U256>,
+    /// By bridge address id and input asset id, as a bridge can be called with several assets.
+    defi_inputs: BTreeMap<(U256, U256), U256>,
+    owners: HashSet<Address>,
+}
+
+fn add<K: Ord>(sums: &mut BTreeMap<K, U256>, key: K, amount: U256) {
+    let sum = sums.entry(key).or_default();
+    *sum = sum.saturating_add(amount);
+}
+
+impl PeriodStats {
+    fn add_block(&mut self, block: &Block) {
+        self.rollups += 1;
+        for tx in block.inner.inner_proofs.iter() {
+            if let Some(index) = PROOF_IDS.iter().position(|proof_id| *proof_id == tx.proof_id) {
+                self.txs[index] += 1;
+            }
+            match tx.proof_id {
+                ProofId::Deposit => add(&mut self.deposits, tx.public_asset_id, tx.public_value),
+                ProofId::Withdraw => add(&mut self.withdrawals, tx.public_asset_id, tx.public_value),
+                _ => (),
+            }
+            if !tx.public_owner.is_zero() {
+                self.owners.insert(tx.public_owner);
+            }
+        }
+        for fee in block.inner.header.fees() {
+            add(&mut self.fees, fee.asset_id, fee.amount);
+        }
+        for interaction in block.inner.header.defi_interactions() {
+            let key = (interaction.bridge_address_id(), interaction.input_asset_a().asset_id);
+            add(&mut self.defi_inputs, key, interaction.total_input_value);
+        }
+    }
+
+    /// The metric, key and value of every aggregate, with every proof type counted even when zero.
+    fn rows(&self) -> Vec<[String; 3]> {
+        let mut rows = vec![[String::from("rollups"), String::new(), self.rollups.to_string()]];
+        for (proof_id, count) in PROOF_IDS.iter().zip(self.txs.iter()) {
+            rows.push([String::from("txs"), format!("{:?}", proof_id), count.to_string()]);
+        }
+        let sums = [("deposit_volume", &self.deposits), ("withdraw_volume", &self.withdrawals), ("fees", &self.fees)];
+        for (metric, sums) in sums {
+            for (asset_id, sum) in sums.iter() {
+                rows.push([String::from(metric), asset_id.to_string(), sum.to_string()]);
+            }
+        }
+        for ((bridge_id, asset_id), sum) in self.defi_inputs.iter() {
+            rows.push([String::from("defi_input_volume"), format!("{}:{}", bridge_id, asset_id), sum.to_string()]);
+        }
+        rows.push([String::from("unique_owners"), String::new(), self.owners.len().to_string()]);
+        rows
+    }
+}
+
+/// Aggregates the rollups within the filter by the period of their L1 time, and prints one row per
+/// period and aggregate. Returns false if a block can't be read.
+pub fn stats(store: &dyn BlockStore, period: Period, format: StatsFormat, filter: ExportFilter) -> bool {
+    filter.check("stats", &[]);
+
+    let range = filter.rollup_range(0);
+    let mut periods: BTreeMap<String, PeriodStats> = BTreeMap::new();
+    let pb = export_progress_bar(store.rollup_ids(range.clone()).len() as u64, "blocks read");
+    let read = store.for_each_block(range, Parts::TXS, &mut |block| {
+        if filter.matches_block(&block) {
+            periods.entry(period.label(block.metadata.timestamp)).or_default().add_block(&block);
+        }
+        pb.inc(1);
+    });
+    pb.finish_and_clear();
+    if let Err(err) = read {
+        println!("{}", err);
+        return false;
+    }
+
+    let rows: Vec<[String; 4]> = periods
+        .iter()
+        .flat_map(|(label, stats)| {
+            stats
+                .rows()
+                .into_iter()
+                .map(move |[metric, key, value]| [label.clone(), metric, key, value])
+        })
+        .collect();
+
+    match format {
+        StatsFormat::Csv => {
+            let mut wtr = RowWriter::create("-", ExportFormat::Csv, false, COLUMNS.map(String::from).to_vec());
+            for row in rows {
+                wtr.write(row.into_iter().map(Value::String).collect());
+            }
+            wtr.finish();
+        }
+        StatsFormat::Table => print_table(&rows),
+    }
+    true
+}
+
+fn print_row(row: [&str; 4], widths: &[usize; 4]) {
+    println!(
+        "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}",
+        row[0],
+        row[1],
+        row[2],
+        row[3],
+        w0 = widths[0],
+        w1 = widths[1],
+        w2 = widths[2],
+        w3 = widths[3]
+    );
+}
+
+/// Values are right aligned, and the period is only printed on the first row of each period.
+fn print_table(rows: &[[String; 4]]) {
+    let mut widths = COLUMNS.map(str::len);
+    for row in rows.iter() {
+        for (width, value) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(value.len());
+        }
+    }
+
+    print_row(COLUMNS, &widths);
+    let mut previous: Option<&str> = None;
+    for [label, metric, key, value] in rows.iter() {
+        let shown = match previous {
+            Some(previous) if previous == label => "",
+            _ => label.as_str(),
+        };
+        print_row([shown, metric, key, value], &widths);
+        previous = Some(label);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transaction::InnerProofData;
+
+    /// 2022-09-01T00:00:00Z, a Thursday.
+    const SEPTEMBER_1: u64 = 1_661_990_400;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn label(period: Period, timestamp: u64) -> String {
+        period.label(Some(U256::from(timestamp)))
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        assert_eq!(label(Period::Week, SEPTEMBER_1), "2022-08-29");
+        assert_eq!(label(Period::Week, SEPTEMBER_1 + 4 * DAY - 1), "2022-08-29");
+        assert_eq!(label(Period::Week, SEPTEMBER_1 + 4 * DAY), "2022-09-05");
+        // 2022-01-01 is a Saturday, its week starts in the year before.
+        assert_eq!(label(Period::Week, 1_640_995_200), "2021-12-27");
+    }
+
+    #[test]
+    fn months_and_days_follow_utc() {
+        assert_eq!(label(Period::Month, SEPTEMBER_1 - 1), "2022-08");
+        assert_eq!(label(Period::Month, SEPTEMBER_1), "2022-09");
+        assert_eq!(label(Period::Day, SEPTEMBER_1 + DAY - 1), "2022-09-01");
+        assert_eq!(label(Period::Day, SEPTEMBER_1 + DAY), "2022-09-02");
+    }
+
+    #[test]
+    fn rollups_without_timestamps_are_unknown() {
+        for period in [Period::Day, Period::Week, Period::Month] {
+            assert_eq!(period.label(None), "unknown");
+        }
+    }
+
+    #[test]
+    fn sums_amounts_per_asset_and_counts_every_proof_type() {
+        let mut block = Block::default();
+        for (proof_id, asset_id, value, owner) in [
+            (ProofId::Deposit, 0u64, 5u64, 1u8),
+            (ProofId::Deposit, 0, 7, 1),
+            (ProofId::Deposit, 1, 2, 2),
+            (ProofId::Withdraw, 0, 3, 3),
+        ] {
+            block.inner.inner_proofs.push(InnerProofData {
+                proof_id,
+                public_asset_id: U256::from(asset_id),
+                public_value: U256::from(value),
+                public_owner: Address::repeat_byte(owner),
+                ..Default::default()
+            });
+        }
+        let mut stats = PeriodStats::default();
+        stats.add_block(&block);
+        stats.add_block(&Block::default());
+
+        let rows = stats.rows();
+        let row = |metric: &str, key: &str| {
+            rows.iter()
+                .find(|row| row[0] == metric && row[1] == key)
+                .map(|row| row[2].clone())
+        };
+        assert_eq!(row("rollups", "").as_deref(), Some("2"));
+        assert_eq!(row("txs", "Deposit").as_deref(), Some("3"));
+        assert_eq!(row("txs", "DefiClaim").as_deref(), Some("0"));
+        assert_eq!(row("deposit_volume", "0").as_deref(), Some("12"));
+        assert_eq!(row("deposit_volume", "1").as_deref(), Some("2"));
+        assert_eq!(row("withdraw_volume", "0").as_deref(), Some("3"));
+        assert_eq!(row("unique_owners", "").as_deref(), Some("3"));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::types::block::Header;
+use ethers::types::{H256, U256};
+use crate::store::{BlockStore, Parts};
+
+/// A single place where two consecutive stored rollups do not line up.
+#[derive(Debug)]
+pub struct ChainBreak {
+    pub rollup_id: u64,
+    pub field: &'static str,
+    pub expected: String,
+    pub found: String,
+    /// Set when the previous stored rollup is not `rollup_id - 1`, i.e. the break is explained by
+    /// rollups missing from the store rather than by the chain itself.
+    pub after_gap: bool,
+}
+
+fn root_pairs(prev: &Header, next: &Header) -> [(&'static str, H256, H256); 4] {
+    [
+        ("data_root", prev.new_data_root, next.old_data_root),
+        ("null_root", prev.new_null_root, next.old_null_root),
+        ("data_roots_root", prev.new_data_roots_root, next.old_data_roots_root),
+        ("defi_root", prev.new_defi_root, next.old_defi_root),
+    ]
+}
+
+/// Compares the state a rollup ends in with the state the next stored rollup claims to start from.
+fn compare(prev: &Header, next: &Header, after_gap: bool) -> Vec<ChainBreak> {
+    let rollup_id = next.rollup_id.as_u64();
+    let mut breaks = vec![];
+
+    for (field, expected, found) in root_pairs(prev, next) {
+        if expected != found {
+            breaks.push(ChainBreak {
+                rollup_id,
+                field,
+                expected: format!("{:?}", expected),
+                found: format!("{:?}", found),
+                after_gap,
+            });
+        }
+    }
+
+    // Every rollup reserves two leaves in the data tree per tx slot, padding included.
+    let expected_start = prev.data_start_index + prev.rollup_size * U256::from(2);
+    if expected_start != next.data_start_index {
+        breaks.push(ChainBreak {
+            rollup_id,
+            field: "data_start_index",
+            expected: expected_start.to_string(),
+            found: next.data_start_index.to_string(),
+            after_gap,
+        });
+    }
+
+    breaks
+}
+
+pub fn verify_chain(store: &dyn BlockStore) -> bool {
+    let keys = store.rollup_ids(0..=u64::MAX);
+
+    if keys.is_empty() {
+        println!("No rollups stored, nothing to verify");
+        return true;
+    }
+
+    let mut gaps: Vec<(u64, u64)> = vec![];
+    let mut breaks: Vec<ChainBreak> = vec![];
+
+    if keys[0] != 0 {
+        gaps.push((0, keys[0] - 1));
+    }
+
+    let mut prev: Option<(u64, Header)> = None;
+    let read = store.for_each_block(0..=u64::MAX, Parts::HEAD, &mut |block| {
+        let key = block.inner.header.rollup_id.as_u64();
+        let header = block.inner.header;
+
+        if let Some((prev_id, prev_header)) = &prev {
+            let after_gap = key != prev_id + 1;
+            if after_gap {
+                gaps.push((prev_id + 1, key - 1));
+            }
+            breaks.extend(compare(prev_header, &header, after_gap));
+        }
+        prev = Some((key, header));
+    });
+    if let Err(err) = read {
+        println!("{}", err);
+        return false;
+    }
+
+    for (from, to) in gaps.iter() {
+        println!("Gap: rollups {}..={} are missing from the store", from, to);
+    }
+
+    for b in breaks.iter() {
+        let cause = match b.after_gap {
+            true => " (previous rollup missing from store)",
+            false => "",
+        };
+        println!(
+            "Break at rollup {}: {} expected {} found {}{}",
+            b.rollup_id, b.field, b.expected, b.found, cause
+        );
+    }
+
+    let unexplained = breaks.iter().filter(|b| !b.after_gap).count();
+    println!(
+        "Verified {} rollups: {} gaps, {} breaks ({} explained by gaps)",
+        keys.len(),
+        gaps.len(),
+        breaks.len(),
+        breaks.len() - unexplained
+    );
+
+    gaps.is_empty() && breaks.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(rollup_id: u64, data_start_index: u64, root: u8) -> Header {
+        Header {
+            rollup_id: U256::from(rollup_id),
+            rollup_size: U256::from(32),
+            data_start_index: U256::from(data_start_index),
+            old_data_root: H256::repeat_byte(root),
+            new_data_root: H256::repeat_byte(root + 1),
+            old_null_root: H256::repeat_byte(root),
+            new_null_root: H256::repeat_byte(root + 1),
+            old_data_roots_root: H256::repeat_byte(root),
+            new_data_roots_root: H256::repeat_byte(root + 1),
+            old_defi_root: H256::repeat_byte(root),
+            new_defi_root: H256::repeat_byte(root + 1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn consecutive_rollups_line_up() {
+        assert!(compare(&header(1, 64, 1), &header(2, 128, 2), false).is_empty());
+    }
+
+    #[test]
+    fn reports_every_root_that_does_not_line_up() {
+        let mut next = header(2, 128, 2);
+        next.old_null_root = H256::repeat_byte(9);
+        next.old_defi_root = H256::repeat_byte(9);
+
+        let breaks = compare(&header(1, 64, 1), &next, true);
+        let fields: Vec<&str> = breaks.iter().map(|chain_break| chain_break.field).collect();
+        assert_eq!(fields, vec!["null_root", "defi_root"]);
+        assert!(breaks.iter().all(|chain_break| chain_break.rollup_id == 2 && chain_break.after_gap));
+    }
+
+    #[test]
+    fn data_start_index_advances_by_two_leaves_per_slot() {
+        let breaks = compare(&header(1, 64, 1), &header(2, 64 + 32, 2), false);
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].field, "data_start_index");
+        assert_eq!(breaks[0].expected, "128");
+        assert_eq!(breaks[0].found, "96");
+    }
+}
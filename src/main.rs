@@ -1,26 +1,37 @@
 mod types;
 mod bindings;
 mod cli;
+mod store;
 
 use std::path::PathBuf;
 use std::sync::Arc;
-use crate::types::block::Block;
 use ethers::{
     providers::{Http, Provider},
+    types::{H256, U256},
 };
-use microkv::MicroKV;
 use clap::Parser;
-use crate::cli::collector::sync_blocks;
-use crate::cli::export::{export_defi_csv, export_fees_csv, export_transactions_csv};
+use crate::cli::collector::{sync_blocks, sync_defi_results};
+use crate::cli::export::{export_defi, export_defi_lifecycle, export_fees, export_rollups, export_transactions, ExportFormat, ExportTarget};
+use crate::cli::filter::ExportFilter;
+use crate::cli::columns::ColumnSelection;
+use crate::cli::export_sqlite::export_sqlite;
+use crate::cli::export_parquet::{export_parquet, ParquetOptions};
+use crate::cli::stats::{stats, Period, StatsFormat};
+use crate::cli::verify::verify_chain;
+use crate::cli::data_tree::{merkle_path, verify_data_tree};
+use crate::cli::decode::{decode_block, decode_raw, BlockSelector, OutputFormat};
+use crate::cli::nullifiers::lookup_nullifier;
+use crate::cli::check::{check_db, repair};
+use crate::cli::migrate::migrate;
+use crate::cli::migrate_microkv::migrate_microkv;
+use crate::cli::search::{reindex, search, SearchKind};
+use crate::store::BlockStore;
+use crate::store::sqlite::{SqliteStore, DB_FILE};
+use crate::cli::bridge_call_data::{decode_bridge_call_data, encode_bridge_call_data, parse_u256};
+use crate::types::bridge_call_data::BridgeCallData;
 
 pub const DEPLOYMENT_BLOCK: u64 = 14923081;
 
-pub fn decode_block(db: &MicroKV, rollup_id: u64) {
-    let id = format!("{:?}", rollup_id);
-    let block = db.get::<Block>(id).unwrap().unwrap();
-    println!("{:#?}", block);
-}
-
 #[derive(Parser, Debug)]
 #[clap(name = "gobbler", version)]
 #[command(author = "LHerskind <lasse@aztecprotocol.com>")]
@@ -44,41 +55,253 @@ pub enum Commands {
         data_path: String,
     },
     #[clap(name = "decode", about = "Print the contents of a block in a semi readable manner")]
+    #[clap(group(clap::ArgGroup::new("block").required(true).args(["rollup_id", "tx_hash", "block_number"])))]
     Decode {
         #[clap(
         help = "The rollup id of the block to decode"
         )]
-        rollup_id: u64,
+        rollup_id: Option<u64>,
+        #[clap(long, help = "The hash of the L1 transaction that published the block")]
+        tx_hash: Option<H256>,
+        #[clap(long, help = "The L1 block number the block was published in")]
+        block_number: Option<u64>,
+        #[clap(long, value_enum, default_value_t = OutputFormat::Debug, help = "The format to print the block in")]
+        format: OutputFormat,
+        #[clap(long, help = "Print the raw calldata with every byte range labelled by the field it decodes to")]
+        annotate: bool,
+        #[clap(
+        long,
+        default_value_t = String::from("http://localhost:8545"),
+        help = "The RPC url to an ethereum node, used to fetch the calldata when annotating"
+        )]
+        rpc_url: String,
         #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
         data_path: String,
     },
-    #[clap(name = "export-txs", about = "Exports inner transactions to a csv file")]
+    #[clap(name = "decode-raw", about = "Decodes rollup calldata given as hex, without a database")]
+    DecodeRaw {
+        #[clap(help = "A file with the hex calldata, or the hex itself. Read from stdin if omitted or `-`")]
+        input: Option<String>,
+        #[clap(long, value_enum, default_value_t = OutputFormat::Debug, help = "The format to print the block in")]
+        format: OutputFormat,
+        #[clap(long, help = "Print the calldata with every byte range labelled by the field it decodes to")]
+        annotate: bool,
+    },
+    #[clap(name = "export-txs", about = "Exports inner transactions as csv or json lines")]
     ExportTxs {
-        #[clap(long, default_value_t = String::from("./export/txs.csv"), help = "The file to write csv to")]
+        #[clap(long, default_value_t = String::from("./export/txs.csv"), help = "The file to write to, or - for stdout")]
         export_path: String,
-        #[clap(long, short, help = "Export only deposits and withdrawals")]
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv, help = "The format to write the rows in")]
+        format: ExportFormat,
+        #[clap(long, short, conflicts_with = "proof_ids", help = "Export only deposits and withdrawals, short for --proof-id deposit,withdraw")]
         l1_only: bool,
+        #[clap(long, help = "Append only rollups newer than the last export, as recorded in the manifest next to it")]
+        incremental: bool,
+        #[clap(long, help = "Append only rollups after this rollup id")]
+        since: Option<u64>,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(flatten)]
+        columns: ColumnSelection,
         #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
         data_path: String,
     },
-    #[clap(name = "export-fees", about = "Exports fee transactions to a csv file")]
+    #[clap(name = "export-fees", about = "Exports fee transactions as csv or json lines")]
     ExportFees {
-        #[clap(long, default_value_t = String::from("./export/fees.csv"), help = "The file to write csv to")]
+        #[clap(long, default_value_t = String::from("./export/fees.csv"), help = "The file to write to, or - for stdout")]
         export_path: String,
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv, help = "The format to write the rows in")]
+        format: ExportFormat,
+        #[clap(long, help = "Append only rollups newer than the last export, as recorded in the manifest next to it")]
+        incremental: bool,
+        #[clap(long, help = "Append only rollups after this rollup id")]
+        since: Option<u64>,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(flatten)]
+        columns: ColumnSelection,
         #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
         data_path: String,
     },
-    #[clap(name = "export-defi", about = "Exports defi transactions to a csv file")]
+    #[clap(name = "export-defi", about = "Exports defi transactions as csv or json lines")]
     ExportDefi {
-        #[clap(long, default_value_t = String::from("./export/defi.csv"), help = "The file to write csv to")]
+        #[clap(long, default_value_t = String::from("./export/defi.csv"), help = "The file to write to, or - for stdout")]
+        export_path: String,
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv, help = "The format to write the rows in")]
+        format: ExportFormat,
+        #[clap(long, help = "Append only rollups newer than the last export, as recorded in the manifest next to it")]
+        incremental: bool,
+        #[clap(long, help = "Append only rollups after this rollup id")]
+        since: Option<u64>,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(flatten)]
+        columns: ColumnSelection,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "export-defi-lifecycle", about = "Exports when defi interactions were submitted and finalised as csv or json lines. Always written in full, without --incremental or --since, as pending rows change once finalised")]
+    ExportDefiLifecycle {
+        #[clap(long, default_value_t = String::from("./export/defi_lifecycle.csv"), help = "The file to write to, or - for stdout")]
+        export_path: String,
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv, help = "The format to write the rows in")]
+        format: ExportFormat,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(flatten)]
+        columns: ColumnSelection,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "export-rollups", about = "Exports one row per rollup with its txs, fees and L1 cost as csv or json lines")]
+    ExportRollups {
+        #[clap(long, default_value_t = String::from("./export/rollups.csv"), help = "The file to write to, or - for stdout")]
+        export_path: String,
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv, help = "The format to write the rows in")]
+        format: ExportFormat,
+        #[clap(long, help = "Append only rollups newer than the last export, as recorded in the manifest next to it")]
+        incremental: bool,
+        #[clap(long, help = "Append only rollups after this rollup id")]
+        since: Option<u64>,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(flatten)]
+        columns: ColumnSelection,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "export-sqlite", about = "Exports rollups, inner txs, fees, defi interactions and proofs to a SQLite database")]
+    ExportSqlite {
+        #[clap(long, default_value_t = String::from("./export/export.sqlite"), help = "The file to write the database to")]
+        export_path: String,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "export-parquet", about = "Exports the rollups, inner txs, fees, defi interactions and proofs as typed Parquet files")]
+    ExportParquet {
+        #[clap(long, default_value_t = String::from("./export/parquet/"), help = "The dir to write the Parquet files to")]
         export_path: String,
+        #[clap(flatten)]
+        options: ParquetOptions,
+        #[clap(long, help = "Append only rollups newer than the last export, as recorded in the manifest next to it")]
+        incremental: bool,
+        #[clap(long, help = "Append only rollups after this rollup id")]
+        since: Option<u64>,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "stats", about = "Aggregates the stored rollups per day, week or month as a table or csv")]
+    Stats {
+        #[clap(long, value_enum, default_value_t = Period::Day, help = "The period to aggregate over")]
+        period: Period,
+        #[clap(long, value_enum, default_value_t = StatsFormat::Table, help = "The format to print the aggregates in")]
+        format: StatsFormat,
+        #[clap(flatten)]
+        filter: ExportFilter,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "verify-chain", about = "Checks that the stored rollups form a continuous chain of state roots")]
+    VerifyChain {
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "verify-data-tree", about = "Rebuilds the data tree from the stored note commitments and checks every data root against it")]
+    VerifyDataTree {
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "merkle-path", about = "Prints the sibling path of a note commitment in the rebuilt data tree")]
+    MerklePath {
+        #[clap(help = "The note commitment to print the path of")]
+        commitment: H256,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "nullifier", about = "Looks up whether and where a nullifier was spent")]
+    Nullifier {
+        #[clap(help = "The nullifier to look up")]
+        nullifier: H256,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "reindex", about = "Rebuilds the commitment, nullifier, L1 tx hash and public owner indexes")]
+    Reindex {
         #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
         data_path: String,
+    },
+    #[clap(name = "search", about = "Finds the rollups and txs a commitment, nullifier, L1 tx hash or public owner occurs in")]
+    Search {
+        #[clap(help = "The value to search for, as hex")]
+        query: String,
+        #[clap(long, value_enum, help = "What the value is. Inferred from its length if omitted")]
+        kind: Option<SearchKind>,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "migrate", about = "Upgrades the database to the schema version of this build, keeping a backup")]
+    Migrate {
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "check-db", about = "Checks that every stored rollup can be read and is complete, optionally repairing it from L1")]
+    CheckDb {
+        #[clap(long, help = "Fetch the rollups with problems from L1 again")]
+        repair: bool,
+        #[clap(
+        long,
+        default_value_t = String::from("http://localhost:8545"),
+        help = "The RPC url to an ethereum node, used when repairing"
+        )]
+        rpc_url: String,
+        #[clap(long, default_value_t = String::from("./data/"), help = "The path to the dir of the database")]
+        data_path: String,
+    },
+    #[clap(name = "bridge-calldata", about = "Decodes or encodes bridge call data")]
+    BridgeCalldata {
+        #[command(subcommand)]
+        command: BridgeCalldataCommands,
     }
 }
 
-fn get_db(path: String) -> MicroKV {
-    MicroKV::open_with_base_path("gobbler", PathBuf::from(path)).expect("Failed to open database")
+#[derive(Debug, Parser)]
+pub enum BridgeCalldataCommands {
+    #[clap(name = "decode", about = "Prints the fields of encoded bridge call data")]
+    Decode {
+        #[clap(value_parser = parse_u256, help = "The bridge call data, as hex (0x prefixed) or decimal")]
+        value: U256,
+    },
+    #[clap(name = "encode", about = "Encodes bridge call data from its fields")]
+    Encode {
+        #[clap(long, help = "The id of the bridge address")]
+        bridge_address_id: u32,
+        #[clap(long, help = "The asset id of the first input")]
+        input_asset_a: u32,
+        #[clap(long, help = "The asset id of the second input, if used")]
+        input_asset_b: Option<u32>,
+        #[clap(long, help = "The asset id of the first output")]
+        output_asset_a: u32,
+        #[clap(long, help = "The asset id of the second output, if used")]
+        output_asset_b: Option<u32>,
+        #[clap(long, default_value_t = 0, help = "The aux data passed to the bridge")]
+        aux_data: u64,
+    },
+}
+
+fn get_db(path: String) -> SqliteStore {
+    let path = PathBuf::from(path);
+    std::fs::create_dir_all(&path).expect("Failed to create data dir");
+    let store = SqliteStore::open(&path.join(DB_FILE))
+        .and_then(|store| migrate_microkv(&path, &store).map(|_| store))
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            std::process::exit(1);
+        });
+    store
 }
 
 #[tokio::main]
@@ -88,38 +311,165 @@ async fn main() {
     match cli.command {
         Commands::Sync { rpc_url, data_path } => {
             let client = Arc::new(Provider::<Http>::try_from(rpc_url).unwrap());
-            let db = get_db(data_path).set_auto_commit(true);
+            let db = get_db(data_path);
 
-            let loaded_block = |db: &MicroKV| {
-                let keys = db.keys().unwrap();
-                if keys.len() == 0 {
-                    return (0, DEPLOYMENT_BLOCK);
+            let (l2_starting_block, l1_starting_block) = match db.latest_block() {
+                Ok(Some(block)) => (block.inner.header.rollup_id.as_u64(), block.metadata.block_number.as_u64()),
+                Ok(None) => (0, DEPLOYMENT_BLOCK),
+                Err(err) => {
+                    println!("{}", err);
+                    std::process::exit(1);
                 }
-                let max_key = keys.iter().map(|key| match key.parse() {
-                    Ok(key) => key,
-                    Err(_) => 0,
-                }).max().unwrap();
-                let id = format!("{:?}", max_key);
-                let block = db.get::<Block>(id).unwrap().unwrap();
-                (max_key, block.metadata.block_number.as_u64())
             };
 
-            let (l2_starting_block, l1_starting_block) = loaded_block(&db);
             println!("Synchronizing from Aztec Connect block {}, Ethereum L1 block: {}", l2_starting_block, l1_starting_block);
-            sync_blocks(&client, &db, l1_starting_block).await;
+            if !sync_blocks(&client, &db, l1_starting_block).await {
+                std::process::exit(1);
+            }
+
+            let results_starting_block = db.latest_defi_result_block().unwrap_or(DEPLOYMENT_BLOCK);
+            println!("Collecting defi interaction results from Ethereum L1 block: {}", results_starting_block);
+            sync_defi_results(&client, &db, results_starting_block).await;
             println!("Sync completed");
         },
-        Commands::Decode { rollup_id, data_path } => {
-            decode_block(&get_db(data_path), rollup_id);
+        Commands::Decode { rollup_id, tx_hash, block_number, format, annotate, rpc_url, data_path } => {
+            let selector = match (rollup_id, tx_hash, block_number) {
+                (Some(rollup_id), _, _) => BlockSelector::RollupId(rollup_id),
+                (_, Some(tx_hash), _) => BlockSelector::TxHash(tx_hash),
+                (_, _, Some(block_number)) => BlockSelector::BlockNumber(block_number),
+                _ => unreachable!("clap requires one of them"),
+            };
+            if !decode_block(&get_db(data_path), selector, format, annotate.then_some(rpc_url)).await {
+                std::process::exit(1);
+            }
+        },
+        Commands::DecodeRaw { input, format, annotate } => {
+            if !decode_raw(input, format, annotate) {
+                std::process::exit(1);
+            }
         },
-        Commands::ExportTxs { export_path, l1_only, data_path } => {
-            export_transactions_csv(&get_db(data_path), export_path, l1_only);
+        Commands::ExportTxs { export_path, format, l1_only, incremental, since, filter, columns, data_path } => {
+            let target = ExportTarget { path: export_path, format, incremental, since, columns };
+            if !export_transactions(&get_db(data_path), target, filter, l1_only) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportFees { export_path, format, incremental, since, filter, columns, data_path } => {
+            let target = ExportTarget { path: export_path, format, incremental, since, columns };
+            if !export_fees(&get_db(data_path), target, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportDefi { export_path, format, incremental, since, filter, columns, data_path } => {
+            let target = ExportTarget { path: export_path, format, incremental, since, columns };
+            if !export_defi(&get_db(data_path), target, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportDefiLifecycle { export_path, format, filter, columns, data_path } => {
+            let target = ExportTarget { path: export_path, format, incremental: false, since: None, columns };
+            if !export_defi_lifecycle(&get_db(data_path), target, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportRollups { export_path, format, incremental, since, filter, columns, data_path } => {
+            let target = ExportTarget { path: export_path, format, incremental, since, columns };
+            if !export_rollups(&get_db(data_path), target, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportSqlite { export_path, filter, data_path } => {
+            if !export_sqlite(&get_db(data_path), export_path, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportParquet { export_path, options, incremental, since, filter, data_path } => {
+            if !export_parquet(&get_db(data_path), export_path, options, incremental, since, filter) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Stats { period, format, filter, data_path } => {
+            if !stats(&get_db(data_path), period, format, filter) {
+                std::process::exit(1);
+            }
         }
-        Commands::ExportFees { export_path, data_path } => {
-            export_fees_csv(&get_db(data_path), export_path);
+        Commands::VerifyChain { data_path } => {
+            if !verify_chain(&get_db(data_path)) {
+                std::process::exit(1);
+            }
         }
-        Commands::ExportDefi { export_path, data_path } => {
-            export_defi_csv(&get_db(data_path), export_path);
+        Commands::VerifyDataTree { data_path } => {
+            if !verify_data_tree(&get_db(data_path)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::MerklePath { commitment, data_path } => {
+            if !merkle_path(&get_db(data_path), commitment) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Nullifier { nullifier, data_path } => {
+            if !lookup_nullifier(&get_db(data_path), nullifier) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Reindex { data_path } => {
+            if !reindex(&get_db(data_path)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Search { query, kind, data_path } => {
+            if search(&get_db(data_path), &query, kind) == 0 {
+                std::process::exit(1);
+            }
+        }
+        Commands::Migrate { data_path } => {
+            if !migrate(&PathBuf::from(data_path)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::CheckDb { repair: should_repair, rpc_url, data_path } => {
+            let db = get_db(data_path);
+            let mut problems = check_db(&db);
+            if should_repair && !problems.is_empty() {
+                let client = Arc::new(Provider::<Http>::try_from(rpc_url).unwrap());
+                if let Err(err) = repair(&client, &db, &problems).await {
+                    println!("{}", err);
+                    std::process::exit(1);
+                }
+                println!("Checking again after repairing");
+                problems = check_db(&db);
+            }
+            if problems.iter().any(|problem| !problem.is_expected()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::BridgeCalldata { command } => {
+            let valid = match command {
+                BridgeCalldataCommands::Decode { value } => decode_bridge_call_data(value),
+                BridgeCalldataCommands::Encode {
+                    bridge_address_id,
+                    input_asset_a,
+                    input_asset_b,
+                    output_asset_a,
+                    output_asset_b,
+                    aux_data,
+                } => {
+                    let bit_config = u32::from(input_asset_b.is_some()) | u32::from(output_asset_b.is_some()) << 1;
+                    encode_bridge_call_data(BridgeCallData {
+                        bridge_address_id,
+                        input_asset_id_a: input_asset_a,
+                        input_asset_id_b: input_asset_b.unwrap_or_default(),
+                        output_asset_id_a: output_asset_a,
+                        output_asset_id_b: output_asset_b.unwrap_or_default(),
+                        bit_config,
+                        aux_data,
+                    })
+                }
+            };
+            if !valid {
+                std::process::exit(1);
+            }
         }
     }
 }
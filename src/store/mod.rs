@@ -0,0 +1,156 @@
+pub mod records;
+pub mod schema;
+pub mod sqlite;
+
+use crate::types::block::Block;
+use crate::types::defi_interaction::DefiInteractionResult;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// The secondary indexes kept next to the blocks, each mapping a key to the txs it occurs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Index {
+    NoteCommitment,
+    Nullifier,
+    /// The hash of the L1 tx that published a rollup, which points at the rollup rather than a tx in it.
+    L1TxHash,
+    PublicOwner,
+}
+
+impl Index {
+    pub const ALL: [Index; 4] = [Index::NoteCommitment, Index::Nullifier, Index::L1TxHash, Index::PublicOwner];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Index::NoteCommitment => "note_commitment",
+            Index::Nullifier => "nullifier",
+            Index::L1TxHash => "l1_tx_hash",
+            Index::PublicOwner => "public_owner",
+        }
+    }
+}
+
+/// Where in the chain a tx is. Entries that point at a whole rollup have no tx position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub rollup_id: u64,
+    pub tx_position: Option<u64>,
+}
+
+/// The parts of every block a scan reads besides its header and metadata. The parts left out are
+/// empty in the blocks handed out, so a scan over a few fields doesn't decode every tx and proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parts {
+    pub txs: bool,
+    pub proof: bool,
+}
+
+impl Parts {
+    pub const HEAD: Parts = Parts { txs: false, proof: false };
+    pub const TXS: Parts = Parts { txs: true, proof: false };
+    pub const ALL: Parts = Parts { txs: true, proof: true };
+}
+
+/// Writes that are applied together, or not at all.
+#[derive(Debug, Default)]
+pub struct WriteBatch {
+    pub blocks: Vec<Block>,
+    pub defi_results: Vec<DefiInteractionResult>,
+    pub index_entries: Vec<(Index, Vec<u8>, TxLocation)>,
+}
+
+impl WriteBatch {
+    pub fn put_block(&mut self, block: Block) {
+        self.blocks.push(block);
+    }
+
+    pub fn put_defi_result(&mut self, result: DefiInteractionResult) {
+        self.defi_results.push(result);
+    }
+
+    pub fn index(&mut self, index: Index, key: &[u8], location: TxLocation) {
+        self.index_entries.push((index, key.to_vec(), location));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.defi_results.is_empty() && self.index_entries.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Storage of the synced blocks, the defi interaction results and the indexes over them. Blocks are
+/// keyed by rollup id and results by interaction nonce, and both are kept in key order.
+pub trait BlockStore: Sync {
+    fn get_block(&self, rollup_id: u64) -> Result<Option<Block>, StoreError>;
+
+    /// The first rollup published in the given L1 block.
+    fn get_block_by_block_number(&self, block_number: u64) -> Result<Option<Block>, StoreError>;
+
+    /// The block with the highest rollup id.
+    fn latest_block(&self) -> Result<Option<Block>, StoreError>;
+
+    /// The stored rollup ids within `range`, in increasing order.
+    fn rollup_ids(&self, range: RangeInclusive<u64>) -> Vec<u64>;
+
+    /// Hands the stored records within `range` to `f` in increasing rollup id order, along with the
+    /// rollup id they are stored under, whether or not they can be read. Only the `parts` asked
+    /// for are read.
+    fn for_each_record(
+        &self,
+        range: RangeInclusive<u64>,
+        parts: Parts,
+        f: &mut dyn FnMut(u64, Result<Block, StoreError>),
+    );
+
+    /// Hands the stored blocks within `range` to `f` in increasing rollup id order, with the
+    /// `parts` asked for. Fails at the first record that can't be read, without handing on the
+    /// blocks after it.
+    fn for_each_block(
+        &self,
+        range: RangeInclusive<u64>,
+        parts: Parts,
+        f: &mut dyn FnMut(Block),
+    ) -> Result<(), StoreError> {
+        let mut failed = None;
+        self.for_each_record(range, parts, &mut |rollup_id, block| match (block, &failed) {
+            (_, Some(_)) => (),
+            (Ok(block), None) => f(block),
+            (Err(err), None) => {
+                failed = Some(StoreError(format!(
+                    "Rollup {} can't be read ({}), run `ac-gobbler check-db`",
+                    rollup_id, err
+                )))
+            }
+        });
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Every stored defi interaction result, in interaction nonce order.
+    fn defi_results(&self) -> Result<Vec<DefiInteractionResult>, StoreError>;
+
+    /// The highest L1 block a stored defi interaction result was emitted in.
+    fn latest_defi_result_block(&self) -> Option<u64>;
+
+    /// The txs `key` was indexed for, in chain order.
+    fn lookup(&self, index: Index, key: &[u8]) -> Vec<TxLocation>;
+
+    /// Removes every entry of the index, so it can be rebuilt from the blocks.
+    fn clear_index(&self, index: Index) -> Result<(), StoreError>;
+
+    fn block_count(&self) -> u64;
+
+    fn write(&self, batch: WriteBatch) -> Result<(), StoreError>;
+
+    /// Runs `f` in a single transaction, which the writes and reads it makes through the store
+    /// join. The transaction is committed if `f` succeeds and rolled back otherwise, so other
+    /// readers of the store never see part of what `f` wrote.
+    fn in_transaction(&self, f: &mut dyn FnMut() -> Result<(), StoreError>) -> Result<(), StoreError>;
+}
@@ -0,0 +1,534 @@
+use crate::types::block::{Block, FeeAsset, Header, InnerBlock, Metadata, NUMBER_OF_BRIDGE_CALLS};
+use crate::types::bridge_call_data::BridgeCallData;
+use crate::types::defi_interaction::DefiInteraction;
+use crate::types::rollup_proof::Proof;
+use crate::types::transaction::InnerProofData;
+use bincode::Options;
+use ethers::types::{Address, H256, U256};
+use serde::{Deserialize, Serialize};
+
+/// The layout of the bincode encoded block records this build writes. Bincode decodes by position,
+/// so any change to the fields of `Block` or the types it holds is a new layout: the old one is
+/// frozen in a module below, `decode_block` learns to convert it, and stores get a migration that
+/// rewrites their blocks.
+///
+/// 0. The blocks of the MicroKV data dirs, without tx positions, bridge call data, interaction
+///    nonces, slots or the L1 cost of the block.
+/// 1. The blocks of the SQLite store, as a `BlockRecord` with the proof stored next to it and the
+///    txs last.
+pub const RECORD_VERSION: u32 = 1;
+
+/// Bincode as `bincode::serialize` writes it, but refusing trailing bytes, so a record is never
+/// mistaken for an older layout that happens to be a prefix of it.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+/// The fields of a block other than its txs and proof.
+#[derive(Serialize, Deserialize)]
+pub struct BlockHead {
+    pub metadata: Metadata,
+    pub header: Header,
+    pub num_real_txs: U256,
+    pub encoded_inner_tx_data_length: U256,
+    pub next_expected_defi_hashes: Vec<H256>,
+    pub sequencer: Address,
+}
+
+/// A block in the current layout, without its proof. The proof is a third of a block without txs
+/// and only read by a few commands, so it is kept apart. The txs come after the rest of the block,
+/// so scans that don't need them decode the head alone.
+#[derive(Serialize, Deserialize)]
+pub struct BlockRecord {
+    pub head: BlockHead,
+    pub inner_proofs: Vec<InnerProofData>,
+}
+
+impl BlockHead {
+    pub fn into_block(self, inner_proofs: Vec<InnerProofData>, proof: Option<Proof>) -> Block {
+        Block {
+            metadata: self.metadata,
+            inner: InnerBlock {
+                header: self.header,
+                num_real_txs: self.num_real_txs,
+                encoded_inner_tx_data_length: self.encoded_inner_tx_data_length,
+                inner_proofs,
+                proof,
+            },
+            next_expected_defi_hashes: self.next_expected_defi_hashes,
+            sequencer: self.sequencer,
+        }
+    }
+}
+
+/// Encodes `block` as a record of the current layout and its proof, if it has one.
+pub fn encode_block(block: Block) -> bincode::Result<(Vec<u8>, Option<Vec<u8>>)> {
+    let proof = block.inner.proof.as_ref().map(bincode::serialize).transpose()?;
+    let record = BlockRecord {
+        head: BlockHead {
+            metadata: block.metadata,
+            header: block.inner.header,
+            num_real_txs: block.inner.num_real_txs,
+            encoded_inner_tx_data_length: block.inner.encoded_inner_tx_data_length,
+            next_expected_defi_hashes: block.next_expected_defi_hashes,
+            sequencer: block.sequencer,
+        },
+        inner_proofs: block.inner.inner_proofs,
+    };
+    Ok((bincode::serialize(&record)?, proof))
+}
+
+/// Decodes a block record written with the layout of `version`, converted to the current layout.
+/// Records of the current layout come without their proof.
+pub fn decode_block(version: u32, data: &[u8]) -> bincode::Result<Block> {
+    match version {
+        0 => Block::try_from(options().deserialize::<v0::Block>(data)?)
+            .map_err(|err| Box::new(bincode::ErrorKind::Custom(err))),
+        RECORD_VERSION => {
+            let record = options().deserialize::<BlockRecord>(data)?;
+            Ok(record.head.into_block(record.inner_proofs, None))
+        }
+        _ => Err(Box::new(bincode::ErrorKind::Custom(format!("Unknown block record version {}", version)))),
+    }
+}
+
+/// Decodes a record of the current layout up to its txs, into a block without txs or proof.
+pub fn decode_block_head(data: &[u8]) -> bincode::Result<Block> {
+    let head: BlockHead = options().allow_trailing_bytes().deserialize(data)?;
+    Ok(head.into_block(vec![], None))
+}
+
+pub fn decode_proof(data: &[u8]) -> bincode::Result<Proof> {
+    options().deserialize(data)
+}
+
+/// The bridge call slot at `slot` of a rollup, with the fields that only depend on where it is set.
+fn interaction_in_slot(mut interaction: DefiInteraction, rollup_id: U256, slot: usize) -> Result<DefiInteraction, String> {
+    let interaction_nonce = rollup_id
+        .checked_mul(U256::from(NUMBER_OF_BRIDGE_CALLS))
+        .and_then(|nonce| nonce.checked_add(U256::from(slot)))
+        .ok_or_else(|| format!("Interaction nonce of slot {} of rollup {} overflows", slot, rollup_id))?;
+    interaction.slot = slot as u64;
+    interaction.interaction_nonce = interaction_nonce;
+    Ok(interaction)
+}
+
+/// Layout 0 kept only the fees, bridge calls and defi interaction hashes in use, in the order of
+/// their slots. Bridge calls and defi interaction hashes fill the slots from the first, so their
+/// slots are where they are in the list, and the unused slots after them are added back as unused
+/// bridge calls and zero hashes. The slots of fees are only known when no fee asset before them
+/// went without fees, so they are numbered in order and the fee slots without fees stay missing.
+///
+/// Padding txs weren't kept either, so the txs are placed at the start of the rollup, which is
+/// where they are unless a partially filled inner rollup is followed by another. These blocks lack
+/// a layout version, so `check-db --repair` refetches them with their exact positions.
+impl TryFrom<v0::Block> for Block {
+    type Error = String;
+    fn try_from(block: v0::Block) -> Result<Self, Self::Error> {
+        let header = block.inner.header;
+        let rollup_id = header.rollup_id;
+        let mut defi_interaction_slots: Vec<DefiInteraction> = header
+            .defi_interactions
+            .into_iter()
+            .enumerate()
+            .map(|(slot, interaction)| interaction.upgrade(rollup_id, slot))
+            .collect::<Result<_, _>>()?;
+        for slot in defi_interaction_slots.len()..NUMBER_OF_BRIDGE_CALLS {
+            let unused = DefiInteraction::new(U256::zero(), BridgeCallData::default(), U256::zero());
+            defi_interaction_slots.push(interaction_in_slot(unused, rollup_id, slot)?);
+        }
+
+        let mut prev_defi_interaction_hash_slots = header.prev_defi_interaction_hashes;
+        let slots = prev_defi_interaction_hash_slots.len().max(NUMBER_OF_BRIDGE_CALLS);
+        prev_defi_interaction_hash_slots.resize(slots, H256::zero());
+
+        let inner_proofs = block
+            .inner
+            .inner_proofs
+            .into_iter()
+            .enumerate()
+            .map(|(position, tx)| tx.upgrade(header.data_start_index, position as u64))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Block {
+            metadata: block.metadata.into(),
+            inner: InnerBlock {
+                header: Header {
+                    rollup_id,
+                    rollup_size: header.rollup_size,
+                    data_start_index: header.data_start_index,
+                    old_data_root: header.old_data_root,
+                    new_data_root: header.new_data_root,
+                    old_null_root: header.old_null_root,
+                    new_null_root: header.new_null_root,
+                    old_data_roots_root: header.old_data_roots_root,
+                    new_data_roots_root: header.new_data_roots_root,
+                    old_defi_root: header.old_defi_root,
+                    new_defi_root: header.new_defi_root,
+                    defi_interaction_slots,
+                    fee_slots: header
+                        .fees
+                        .into_iter()
+                        .enumerate()
+                        .map(|(slot, fee)| FeeAsset {
+                            slot: slot as u64,
+                            asset_id: fee.asset_id,
+                            amount: fee.amount,
+                        })
+                        .collect(),
+                    prev_defi_interaction_hash_slots,
+                    prev_defi_interaction_hash: header.prev_defi_interaction_hash,
+                    rollup_beneficiary: header.rollup_beneficiary,
+                    num_rollup_txs: header.num_rollup_txs,
+                },
+                num_real_txs: block.inner.num_real_txs,
+                encoded_inner_tx_data_length: block.inner.encoded_inner_tx_data_length,
+                inner_proofs,
+                proof: Some(block.inner.proof),
+            },
+            next_expected_defi_hashes: block.next_expected_defi_hashes,
+            sequencer: block.sequencer,
+        })
+    }
+}
+
+/// Blocks as the MicroKV data dirs of the first releases hold them.
+pub mod v0 {
+    use super::interaction_in_slot;
+    use crate::types::block;
+    use crate::types::bridge_call_data::BridgeCallData;
+    use crate::types::defi_interaction::{self, AssetType};
+    use crate::types::rollup_proof::Proof;
+    use crate::types::transaction::{self, ProofId};
+    use ethers::types::{Address, TxHash, H256, U256, U64};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct FeeAsset {
+        pub asset_id: U256,
+        pub amount: U256,
+    }
+
+    /// Only the fees, bridge calls and defi interaction hashes in use are kept.
+    #[derive(Serialize, Deserialize)]
+    pub struct Header {
+        pub rollup_id: U256,
+        pub rollup_size: U256,
+        pub data_start_index: U256,
+        pub old_data_root: H256,
+        pub new_data_root: H256,
+        pub old_null_root: H256,
+        pub new_null_root: H256,
+        pub old_data_roots_root: H256,
+        pub new_data_roots_root: H256,
+        pub old_defi_root: H256,
+        pub new_defi_root: H256,
+        pub defi_interactions: Vec<DefiInteraction>,
+        pub fees: Vec<FeeAsset>,
+        pub prev_defi_interaction_hashes: Vec<H256>,
+        pub prev_defi_interaction_hash: H256,
+        pub rollup_beneficiary: Address,
+        pub num_rollup_txs: U256,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Metadata {
+        pub tx_hash: TxHash,
+        pub block_number: U64,
+        pub timestamp: Option<U256>,
+    }
+
+    /// Padding txs are not kept.
+    #[derive(Serialize, Deserialize)]
+    pub struct InnerBlock {
+        pub header: Header,
+        pub num_real_txs: U256,
+        pub encoded_inner_tx_data_length: U256,
+        pub inner_proofs: Vec<InnerProofData>,
+        pub proof: Proof,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Block {
+        pub metadata: Metadata,
+        pub inner: InnerBlock,
+        pub next_expected_defi_hashes: Vec<H256>,
+        pub sequencer: Address,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct InnerProofData {
+        pub proof_id: ProofId,
+        pub note_commitment_1: H256,
+        pub note_commitment_2: H256,
+        pub nullifier_1: H256,
+        pub nullifier_2: H256,
+        pub public_value: U256,
+        pub public_owner: Address,
+        pub public_asset_id: U256,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Asset {
+        pub asset_type: AssetType,
+        pub asset_id: U256,
+    }
+
+    /// The asset types were decoded from bit 30 of the asset id rather than bit 29, so virtual
+    /// assets were recorded as real ones.
+    #[derive(Serialize, Deserialize)]
+    pub struct DefiInteraction {
+        pub bridge_address_id: U256,
+        pub input_asset_a: Asset,
+        pub input_asset_b: Asset,
+        pub output_asset_a: Asset,
+        pub output_asset_b: Asset,
+        pub aux_data: U256,
+        pub total_input_value: U256,
+    }
+
+    impl From<Metadata> for block::Metadata {
+        fn from(metadata: Metadata) -> Self {
+            block::Metadata {
+                tx_hash: metadata.tx_hash,
+                block_number: metadata.block_number,
+                timestamp: metadata.timestamp,
+                ..block::Metadata::default()
+            }
+        }
+    }
+
+    impl InnerProofData {
+        /// The tx at `position` of a rollup, with the leaf indexes of its notes derived from it.
+        pub fn upgrade(self, data_start_index: U256, position: u64) -> Result<transaction::InnerProofData, String> {
+            let mut tx = transaction::InnerProofData {
+                proof_id: self.proof_id,
+                note_commitment_1: self.note_commitment_1,
+                note_commitment_2: self.note_commitment_2,
+                nullifier_1: self.nullifier_1,
+                nullifier_2: self.nullifier_2,
+                public_value: self.public_value,
+                public_owner: self.public_owner,
+                public_asset_id: self.public_asset_id,
+                ..Default::default()
+            };
+            tx.set_position(data_start_index, position)?;
+            Ok(tx)
+        }
+    }
+
+    impl DefiInteraction {
+        /// The bridge call data is encoded again from its fields, and decoded like a fresh one.
+        pub fn upgrade(self, rollup_id: U256, slot: usize) -> Result<defi_interaction::DefiInteraction, String> {
+            let in_use = |asset: &Asset| !matches!(asset.asset_type, AssetType::Unused);
+            let bridge_call_data = BridgeCallData {
+                bridge_address_id: self.bridge_address_id.low_u32(),
+                input_asset_id_a: self.input_asset_a.asset_id.low_u32(),
+                input_asset_id_b: self.input_asset_b.asset_id.low_u32(),
+                output_asset_id_a: self.output_asset_a.asset_id.low_u32(),
+                output_asset_id_b: self.output_asset_b.asset_id.low_u32(),
+                bit_config: in_use(&self.input_asset_b) as u32 | (in_use(&self.output_asset_b) as u32) << 1,
+                aux_data: self.aux_data.low_u64(),
+            };
+            // The asset ids were masked to 30 bits when decoded, so they fit again.
+            let encoded = U256::try_from(bridge_call_data).unwrap();
+            interaction_in_slot(
+                defi_interaction::DefiInteraction::new(encoded, bridge_call_data, self.total_input_value),
+                rollup_id,
+                slot,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::defi_interaction::AssetType;
+    use crate::types::rollup_proof::Proof;
+    use crate::types::block::{FeeAsset, Metadata};
+    use crate::types::defi_interaction::DefiInteraction;
+    use crate::types::layout::LayoutVersion;
+    use crate::types::transaction::{InnerProofData, ProofId};
+    use ethers::types::{Address, H256, U256, U64};
+    use sha2::{Digest, Sha256};
+
+    /// A block with every field that has a place in the record set, so any change to the layout
+    /// changes its encoding.
+    fn sample_block() -> Block {
+        let mut block = Block {
+            metadata: Metadata {
+                tx_hash: H256::repeat_byte(1),
+                block_number: U64::from(15_000_000),
+                timestamp: Some(U256::from(1_661_990_400)),
+                layout_version: Some(LayoutVersion::AztecConnect),
+                calldata_size: Some(20_000),
+                gas_used: Some(U256::from(300_000)),
+                effective_gas_price: Some(U256::from(30_000_000_000u64)),
+            },
+            ..Default::default()
+        };
+        let header = &mut block.inner.header;
+        header.rollup_id = U256::from(7);
+        header.rollup_size = U256::from(32);
+        header.data_start_index = U256::from(448);
+        header.new_data_root = H256::repeat_byte(2);
+        header.fee_slots.push(FeeAsset { slot: 0, asset_id: U256::zero(), amount: U256::from(5) });
+        let mut interaction = DefiInteraction::try_from((U256::from(1) | U256::from(1) << 92, U256::from(100))).unwrap();
+        interaction.interaction_nonce = U256::from(7 * 32);
+        header.defi_interaction_slots.push(interaction);
+        header.prev_defi_interaction_hash_slots.push(H256::repeat_byte(3));
+        header.rollup_beneficiary = Address::repeat_byte(4);
+
+        let mut tx = InnerProofData { proof_id: ProofId::Deposit, public_value: U256::from(10), ..Default::default() };
+        tx.set_position(block.inner.header.data_start_index, 1).unwrap();
+        block.inner.inner_proofs.push(tx);
+        block.inner.num_real_txs = U256::from(1);
+        block.next_expected_defi_hashes.push(H256::repeat_byte(5));
+        block.sequencer = Address::repeat_byte(6);
+        block.inner.proof = Some(Proof { public_input_hash: H256::repeat_byte(7), ..Default::default() });
+        block
+    }
+
+    fn asset(asset_type: AssetType, asset_id: u32) -> v0::Asset {
+        v0::Asset { asset_type, asset_id: U256::from(asset_id) }
+    }
+
+    /// A rollup as the first releases recorded it: two deposits, one fee and one bridge call with a
+    /// virtual input, which they took for a real asset.
+    pub(crate) fn baseline_block(rollup_id: u64) -> v0::Block {
+        let tx = |nullifier: u8| v0::InnerProofData {
+            proof_id: ProofId::Deposit,
+            note_commitment_1: H256::repeat_byte(nullifier + 1),
+            note_commitment_2: H256::repeat_byte(nullifier + 2),
+            nullifier_1: H256::repeat_byte(nullifier),
+            nullifier_2: H256::zero(),
+            public_value: U256::from(10),
+            public_owner: Address::repeat_byte(9),
+            public_asset_id: U256::zero(),
+        };
+        v0::Block {
+            metadata: v0::Metadata {
+                tx_hash: H256::repeat_byte(1),
+                block_number: U64::from(15_000_000),
+                timestamp: Some(U256::from(1_661_990_400)),
+            },
+            inner: v0::InnerBlock {
+                header: v0::Header {
+                    rollup_id: U256::from(rollup_id),
+                    rollup_size: U256::from(32),
+                    data_start_index: U256::from(64 * rollup_id),
+                    old_data_root: H256::repeat_byte(2),
+                    new_data_root: H256::repeat_byte(3),
+                    old_null_root: H256::zero(),
+                    new_null_root: H256::zero(),
+                    old_data_roots_root: H256::zero(),
+                    new_data_roots_root: H256::zero(),
+                    old_defi_root: H256::zero(),
+                    new_defi_root: H256::zero(),
+                    defi_interactions: vec![v0::DefiInteraction {
+                        bridge_address_id: U256::from(5),
+                        input_asset_a: asset(AssetType::Real, (1 << 29) | 17),
+                        input_asset_b: asset(AssetType::Real, 1),
+                        output_asset_a: asset(AssetType::Real, 2),
+                        output_asset_b: asset(AssetType::Unused, 0),
+                        aux_data: U256::from(42),
+                        total_input_value: U256::from(100),
+                    }],
+                    fees: vec![v0::FeeAsset { asset_id: U256::from(1), amount: U256::from(5) }],
+                    prev_defi_interaction_hashes: vec![H256::repeat_byte(4)],
+                    prev_defi_interaction_hash: H256::repeat_byte(5),
+                    rollup_beneficiary: Address::repeat_byte(6),
+                    num_rollup_txs: U256::from(2),
+                },
+                num_real_txs: U256::from(2),
+                encoded_inner_tx_data_length: U256::from(370),
+                inner_proofs: vec![tx(10), tx(20)],
+                proof: Proof::default(),
+            },
+            next_expected_defi_hashes: vec![],
+            sequencer: Address::repeat_byte(7),
+        }
+    }
+
+    #[test]
+    fn decodes_baseline_records() {
+        let data = bincode::serialize(&baseline_block(3)).unwrap();
+        assert!(decode_block(RECORD_VERSION, &data).is_err());
+        let block = decode_block(0, &data).unwrap();
+
+        assert_eq!(block.metadata.timestamp, Some(U256::from(1_661_990_400)));
+        assert_eq!(block.metadata.layout_version, None);
+        assert!(block.inner.proof.is_some());
+
+        let positions: Vec<_> = block
+            .inner
+            .inner_proofs
+            .iter()
+            .map(|tx| (tx.tx_position, tx.note_commitment_1_index, tx.note_commitment_2_index))
+            .collect();
+        assert_eq!(positions, vec![(0, 192, 193), (1, 194, 195)]);
+        assert_eq!(block.inner.inner_proofs[1].nullifier_1, H256::repeat_byte(20));
+
+        let header = &block.inner.header;
+        assert_eq!(header.fee_slots.len(), 1);
+        assert_eq!((header.fee_slots[0].slot, header.fee_slots[0].amount), (0, U256::from(5)));
+        assert_eq!(header.defi_interaction_slots.len(), NUMBER_OF_BRIDGE_CALLS);
+        assert_eq!(header.defi_interactions().count(), 1);
+        assert_eq!(header.prev_defi_interaction_hash_slots.len(), NUMBER_OF_BRIDGE_CALLS);
+        assert_eq!(header.prev_defi_interaction_hash_slots[0], H256::repeat_byte(4));
+        assert_eq!(header.prev_defi_interaction_hashes().count(), 1);
+
+        let interaction = &header.defi_interaction_slots[0];
+        let expected = U256::from(5)
+            | U256::from((1u64 << 29) | 17) << 32
+            | U256::from(1) << 62
+            | U256::from(2) << 92
+            | U256::from(1) << 152
+            | U256::from(42) << 184;
+        assert_eq!(interaction.encoded_bridge_call_data, expected);
+        assert_eq!(interaction.bridge_call_data.bit_config, 1);
+        assert_eq!(interaction.interaction_nonce, U256::from(3 * 32));
+        assert!(matches!(interaction.input_asset_a().asset_type, AssetType::Virtual));
+        assert_eq!(interaction.input_asset_a().origin_nonce, Some(U256::from(17)));
+        assert!(matches!(interaction.input_asset_b().asset_type, AssetType::Real));
+        assert!(matches!(interaction.output_asset_b().asset_type, AssetType::Unused));
+        assert_eq!(interaction.aux_data(), U256::from(42));
+
+        let unused = &header.defi_interaction_slots[31];
+        assert_eq!((unused.slot, unused.interaction_nonce), (31, U256::from(3 * 32 + 31)));
+        assert!(!unused.is_in_use());
+    }
+
+    #[test]
+    fn record_layout_matches_record_version() {
+        // If this fails the record layout changed: bump RECORD_VERSION, freeze the previous layout
+        // in this module and add a schema migration that rewrites the blocks.
+        let (record, proof) = encode_block(sample_block()).unwrap();
+        let digest = Sha256::digest([record, proof.unwrap()].concat());
+        assert_eq!(
+            (RECORD_VERSION, format!("{:x}", digest)),
+            (1, String::from("e87065dccb79ea1b0e6e656a474b47065204e808675c95afb1ecb5841f305c62"))
+        );
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        let (data, proof) = encode_block(sample_block()).unwrap();
+        let mut block = decode_block(RECORD_VERSION, &data).unwrap();
+        assert!(block.inner.proof.is_none());
+        block.inner.proof = Some(decode_proof(proof.as_ref().unwrap()).unwrap());
+        assert_eq!(encode_block(block).unwrap(), (data, proof));
+    }
+
+    #[test]
+    fn refuses_trailing_bytes_and_unknown_versions() {
+        let (mut data, _) = encode_block(sample_block()).unwrap();
+        assert!(decode_block(0, &data).is_err());
+        data.push(0);
+        assert!(decode_block(RECORD_VERSION, &data).is_err());
+        assert!(decode_block(RECORD_VERSION + 1, &data).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::store::records;
+use crate::store::StoreError;
+use rusqlite::{params, Connection, Transaction};
+
+/// The schema version this build reads and writes, kept in the `user_version` of the database.
+///
+/// 0. The blocks, defi results and index entries tables of the first SQLite stores, which didn't
+///    record their version. Blocks are records of layout 0, the MicroKV one, proof included.
+/// 1. Blocks in record layout `records::RECORD_VERSION` with their proofs in a column of their
+///    own, and an index on their L1 block number.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The tables of version 0, which the migrations build on.
+const CREATE_TABLES: &str = "
+    CREATE TABLE blocks (
+        rollup_id INTEGER PRIMARY KEY,
+        block_number INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE defi_results (
+        interaction_nonce INTEGER PRIMARY KEY,
+        block_number INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE index_entries (
+        name TEXT NOT NULL,
+        key BLOB NOT NULL,
+        rollup_id INTEGER NOT NULL,
+        tx_position INTEGER NOT NULL,
+        PRIMARY KEY (name, key, rollup_id, tx_position)
+    ) WITHOUT ROWID;
+";
+
+/// A step that upgrades a store from version `to - 1` to version `to`. Steps that change how a
+/// record is encoded rewrite the affected rows within the same transaction.
+pub struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    pub apply: fn(&Transaction) -> Result<(), StoreError>,
+}
+
+/// The steps from each earlier schema version, in order.
+pub static MIGRATIONS: [Migration; 1] = [Migration {
+    to: 1,
+    description: "convert blocks to the current record layout with their proofs apart, index them by L1 block",
+    apply: split_proofs,
+}];
+
+/// Rows are rewritten this many at a time, so a store is never read into memory as a whole.
+const MIGRATION_CHUNK_SIZE: i64 = 256;
+
+/// Rewrites every block from record layout 0 into the current one, moving its proof to a column of
+/// its own. A block that can't be read fails the migration, which rolls it back.
+fn split_proofs(tx: &Transaction) -> Result<(), StoreError> {
+    tx.execute_batch(
+        "ALTER TABLE blocks ADD COLUMN proof BLOB;
+        CREATE INDEX blocks_by_block_number ON blocks (block_number);",
+    )?;
+
+    let mut select =
+        tx.prepare("SELECT rollup_id, data FROM blocks WHERE rollup_id > ?1 ORDER BY rollup_id LIMIT ?2")?;
+    let mut update = tx.prepare("UPDATE blocks SET data = ?2, proof = ?3 WHERE rollup_id = ?1")?;
+    let mut after = -1;
+    loop {
+        let rows = select
+            .query_map(params![after, MIGRATION_CHUNK_SIZE], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let last = match rows.last() {
+            Some((rollup_id, _)) => *rollup_id,
+            None => return Ok(()),
+        };
+
+        for (rollup_id, data) in rows {
+            let block = records::decode_block(0, &data).map_err(|err| {
+                StoreError(format!("Rollup {} isn't a record of layout 0 ({})", rollup_id, err))
+            })?;
+            let (data, proof) = records::encode_block(block)?;
+            update.execute(params![rollup_id, data, proof])?;
+        }
+        after = last;
+    }
+}
+
+fn has_tables(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'blocks'",
+        [],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// The schema version of the store, or `None` if it is empty.
+pub fn stored_version(conn: &Connection) -> rusqlite::Result<Option<u32>> {
+    if !has_tables(conn)? {
+        return Ok(None);
+    }
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(Some(version))
+}
+
+/// Creates the tables of an empty store by running every migration from the first version, so a
+/// new store always ends up identical to a migrated one.
+pub fn create(conn: &mut Connection) -> Result<(), StoreError> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(CREATE_TABLES)?;
+    for migration in MIGRATIONS.iter() {
+        (migration.apply)(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Applies the migrations from `from` to the current version in a single transaction, calling
+/// `on_step` before each of them.
+pub fn upgrade(conn: &mut Connection, from: u32, mut on_step: impl FnMut(&Migration)) -> Result<(), StoreError> {
+    let tx = conn.transaction()?;
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > from) {
+        on_step(migration);
+        (migration.apply)(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_stores_are_at_the_current_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(stored_version(&conn).unwrap(), None);
+        create(&mut conn).unwrap();
+        assert_eq!(stored_version(&conn).unwrap(), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn unversioned_stores_are_at_version_0() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(CREATE_TABLES).unwrap();
+        assert_eq!(stored_version(&conn).unwrap(), Some(0));
+    }
+}
@@ -0,0 +1,399 @@
+use crate::store::records::{self, RECORD_VERSION};
+use crate::store::schema::{self, Migration, SCHEMA_VERSION};
+use crate::store::{BlockStore, Index, Parts, StoreError, TxLocation, WriteBatch};
+use crate::types::block::Block;
+use crate::types::defi_interaction::DefiInteractionResult;
+use rayon::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The file the store is kept in, within the data dir.
+pub const DB_FILE: &str = "gobbler.sqlite";
+
+/// Blocks are read this many at a time, and deserialized in parallel.
+const CHUNK_SIZE: usize = 256;
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError(err.to_string())
+    }
+}
+
+impl From<bincode::Error> for StoreError {
+    fn from(err: bincode::Error) -> Self {
+        StoreError(err.to_string())
+    }
+}
+
+/// SQLite stores integers as i64, so the ends of ranges over u64 keys are clamped to it.
+fn sql_int(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Index entries without a tx position are stored with -1, as columns of the primary key can't be null.
+fn sql_tx_position(tx_position: Option<u64>) -> i64 {
+    tx_position.map(sql_int).unwrap_or(-1)
+}
+
+/// A `BlockStore` in a single SQLite file, with blocks and results stored bincode encoded.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    fn connect(path: &Path) -> Result<Connection, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Ok(conn)
+    }
+
+    /// Opens the store, creating it if it is empty. Stores at another schema version are refused,
+    /// as their records can't be read reliably.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let mut conn = SqliteStore::connect(path)?;
+        match schema::stored_version(&conn)? {
+            None => schema::create(&mut conn)?,
+            Some(SCHEMA_VERSION) => (),
+            Some(version) if version < SCHEMA_VERSION => {
+                return Err(StoreError(format!(
+                    "The database at {:?} is at schema version {}, while this build uses version {}. \
+                    Run `ac-gobbler migrate` to upgrade it",
+                    path, version, SCHEMA_VERSION
+                )))
+            }
+            Some(version) => {
+                return Err(StoreError(format!(
+                    "The database at {:?} was written by a newer build at schema version {}, while this build \
+                    only reads up to version {}",
+                    path, version, SCHEMA_VERSION
+                )))
+            }
+        }
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+
+    /// The schema version of the store at `path`, or `None` if there is no store.
+    pub fn schema_version(path: &Path) -> Result<Option<u32>, StoreError> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(schema::stored_version(&SqliteStore::connect(path)?)?)
+    }
+
+    /// Upgrades the store at `path` to the current schema version, after copying it to `backup`.
+    pub fn upgrade(path: &Path, backup: &Path, on_step: impl FnMut(&Migration)) -> Result<(), StoreError> {
+        let mut conn = SqliteStore::connect(path)?;
+        let version = match schema::stored_version(&conn)? {
+            Some(version) => version,
+            None => return Ok(()),
+        };
+        if version > SCHEMA_VERSION {
+            return Err(StoreError(format!(
+                "The database is at schema version {}, which is newer than this build",
+                version
+            )));
+        }
+
+        conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+        schema::upgrade(&mut conn, version, on_step)
+    }
+
+    fn block_chunk(&self, from: u64, to: u64, parts: Parts) -> Vec<(u64, Vec<u8>, Option<Vec<u8>>)> {
+        let conn = self.conn.lock().unwrap();
+        let proof = if parts.proof { "proof" } else { "NULL" };
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "SELECT rollup_id, data, {} FROM blocks WHERE rollup_id >= ?1 AND rollup_id <= ?2 ORDER BY rollup_id LIMIT ?3",
+                proof
+            ))
+            .unwrap();
+        let rows = stmt
+            .query_map(params![sql_int(from), sql_int(to), CHUNK_SIZE as i64], |row| {
+                Ok((row.get::<_, i64>(0)? as u64, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    /// The block with the highest rollup id and its proof, of the blocks selected by `filter`.
+    fn query_block(&self, filter: &str, params: impl rusqlite::Params) -> Result<Option<Block>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(
+                &format!("SELECT rollup_id, data, proof FROM blocks {}", filter),
+                params,
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        row.map(|(rollup_id, data, proof)| {
+            decode_block(data, proof, Parts::ALL).map_err(|err| {
+                StoreError(format!("Rollup {} can't be read ({}), run `ac-gobbler check-db`", rollup_id, err))
+            })
+        })
+        .transpose()
+    }
+}
+
+fn decode_block(data: Vec<u8>, proof: Option<Vec<u8>>, parts: Parts) -> Result<Block, StoreError> {
+    let mut block = match parts.txs {
+        true => records::decode_block(RECORD_VERSION, &data)?,
+        false => records::decode_block_head(&data)?,
+    };
+    block.inner.proof = proof.map(|proof| records::decode_proof(&proof)).transpose()?;
+    Ok(block)
+}
+
+impl BlockStore for SqliteStore {
+    fn get_block(&self, rollup_id: u64) -> Result<Option<Block>, StoreError> {
+        self.query_block("WHERE rollup_id = ?1", [sql_int(rollup_id)])
+    }
+
+    fn latest_block(&self) -> Result<Option<Block>, StoreError> {
+        self.query_block("ORDER BY rollup_id DESC LIMIT 1", [])
+    }
+
+    fn get_block_by_block_number(&self, block_number: u64) -> Result<Option<Block>, StoreError> {
+        self.query_block("WHERE block_number = ?1 ORDER BY rollup_id LIMIT 1", [sql_int(block_number)])
+    }
+
+    fn rollup_ids(&self, range: RangeInclusive<u64>) -> Vec<u64> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare_cached("SELECT rollup_id FROM blocks WHERE rollup_id >= ?1 AND rollup_id <= ?2 ORDER BY rollup_id")
+            .unwrap();
+        let rows = stmt
+            .query_map(params![sql_int(*range.start()), sql_int(*range.end())], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap() as u64).collect()
+    }
+
+    fn for_each_record(
+        &self,
+        range: RangeInclusive<u64>,
+        parts: Parts,
+        f: &mut dyn FnMut(u64, Result<Block, StoreError>),
+    ) {
+        let mut from = *range.start();
+        loop {
+            let chunk = self.block_chunk(from, *range.end(), parts);
+            let last = match chunk.last() {
+                Some((rollup_id, _, _)) => *rollup_id,
+                None => break,
+            };
+            let full = chunk.len() == CHUNK_SIZE;
+
+            let records: Vec<(u64, Result<Block, StoreError>)> = chunk
+                .into_par_iter()
+                .map(|(rollup_id, data, proof)| (rollup_id, decode_block(data, proof, parts)))
+                .collect();
+            records.into_iter().for_each(|(rollup_id, block)| f(rollup_id, block));
+
+            if !full || last >= *range.end() {
+                break;
+            }
+            from = last + 1;
+        }
+    }
+
+    fn defi_results(&self) -> Result<Vec<DefiInteractionResult>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT interaction_nonce, data FROM defi_results ORDER BY interaction_nonce")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+        rows.map(|row| {
+            let (nonce, data) = row?;
+            bincode::deserialize(&data).map_err(|err| {
+                StoreError(format!("The defi interaction result of nonce {} can't be read ({})", nonce, err))
+            })
+        })
+        .collect()
+    }
+
+    fn latest_defi_result_block(&self) -> Option<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT MAX(block_number) FROM defi_results", [], |row| row.get::<_, Option<i64>>(0))
+            .unwrap()
+            .map(|block_number| block_number as u64)
+    }
+
+    fn lookup(&self, index: Index, key: &[u8]) -> Vec<TxLocation> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT rollup_id, tx_position FROM index_entries WHERE name = ?1 AND key = ?2 ORDER BY rollup_id, tx_position",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map(params![index.name(), key], |row| {
+                Ok(TxLocation {
+                    rollup_id: row.get::<_, i64>(0)? as u64,
+                    tx_position: u64::try_from(row.get::<_, i64>(1)?).ok(),
+                })
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    fn clear_index(&self, index: Index) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM index_entries WHERE name = ?1", [index.name()])?;
+        Ok(())
+    }
+
+    fn block_count(&self) -> u64 {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM blocks", [], |row| row.get::<_, i64>(0))
+            .unwrap() as u64
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), StoreError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        // A savepoint commits on its own, or joins the transaction of `in_transaction`.
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.savepoint()?;
+        {
+            let mut put_block = tx.prepare_cached(
+                "INSERT OR REPLACE INTO blocks (rollup_id, block_number, data, proof) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for block in batch.blocks {
+                let rollup_id = sql_int(block.inner.header.rollup_id.as_u64());
+                let block_number = sql_int(block.metadata.block_number.as_u64());
+                let (data, proof) = records::encode_block(block)?;
+                put_block.execute(params![rollup_id, block_number, data, proof])?;
+            }
+
+            let mut put_result = tx.prepare_cached(
+                "INSERT OR REPLACE INTO defi_results (interaction_nonce, block_number, data) VALUES (?1, ?2, ?3)",
+            )?;
+            for result in batch.defi_results.iter() {
+                put_result.execute(params![
+                    sql_int(result.interaction_nonce.as_u64()),
+                    sql_int(result.block_number.as_u64()),
+                    bincode::serialize(result)?
+                ])?;
+            }
+
+            // Re-syncing a block indexes the same entries again, which must not duplicate them.
+            let mut put_entry = tx.prepare_cached(
+                "INSERT OR IGNORE INTO index_entries (name, key, rollup_id, tx_position) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (index, key, location) in batch.index_entries.iter() {
+                put_entry.execute(params![
+                    index.name(),
+                    key,
+                    sql_int(location.rollup_id),
+                    sql_tx_position(location.tx_position)
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn in_transaction(&self, f: &mut dyn FnMut() -> Result<(), StoreError>) -> Result<(), StoreError> {
+        self.conn.lock().unwrap().execute_batch("BEGIN IMMEDIATE")?;
+        let result = f();
+        let conn = self.conn.lock().unwrap();
+        match result {
+            Ok(()) => conn.execute_batch("COMMIT")?,
+            Err(_) => conn.execute_batch("ROLLBACK")?,
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rollup_proof::Proof;
+    use crate::types::transaction::InnerProofData;
+    use ethers::types::{Address, H256, U256};
+
+    #[test]
+    fn scans_read_only_the_parts_asked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in 0..2 {
+            let mut block = Block::default();
+            block.inner.header.rollup_id = U256::from(rollup_id);
+            block.inner.inner_proofs.push(InnerProofData::default());
+            block.sequencer = Address::repeat_byte(2);
+            block.inner.proof = Some(Proof { public_input_hash: H256::repeat_byte(1), ..Default::default() });
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+
+        let mut read = vec![];
+        for parts in [Parts::HEAD, Parts::TXS, Parts::ALL] {
+            store.for_each_block(0..=1, parts, &mut |block| {
+                assert_eq!(block.sequencer, Address::repeat_byte(2));
+                read.push((block.inner.inner_proofs.len(), block.inner.proof.is_some()));
+            })
+            .unwrap();
+        }
+        assert_eq!(read, vec![(0, false), (0, false), (1, false), (1, false), (1, true), (1, true)]);
+        let proof = store.get_block(1).unwrap().unwrap().inner.proof.unwrap();
+        assert_eq!(proof.public_input_hash, H256::repeat_byte(1));
+    }
+
+    #[test]
+    fn reports_records_that_cant_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let mut batch = WriteBatch::default();
+        for rollup_id in 0..3 {
+            let mut block = Block::default();
+            block.inner.header.rollup_id = U256::from(rollup_id);
+            batch.put_block(block);
+        }
+        store.write(batch).unwrap();
+        store.conn.lock().unwrap().execute("UPDATE blocks SET data = x'00' WHERE rollup_id = 1", []).unwrap();
+
+        let err = store.get_block(1).unwrap_err();
+        assert!(err.0.starts_with("Rollup 1 can't be read"), "{}", err);
+        assert!(store.get_block(2).unwrap().is_some());
+
+        let mut read = vec![];
+        let err = store
+            .for_each_block(0..=2, Parts::HEAD, &mut |block| read.push(block.inner.header.rollup_id.as_u64()))
+            .unwrap_err();
+        assert!(err.0.starts_with("Rollup 1 can't be read"), "{}", err);
+        assert_eq!(read, vec![0]);
+    }
+
+    #[test]
+    fn transactions_are_rolled_back_when_they_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join(DB_FILE)).unwrap();
+        let location = TxLocation { rollup_id: 0, tx_position: None };
+
+        let failed = store.in_transaction(&mut || {
+            let mut batch = WriteBatch::default();
+            batch.put_block(Block::default());
+            batch.index(Index::L1TxHash, &[1], location);
+            store.write(batch)?;
+            assert_eq!(store.lookup(Index::L1TxHash, &[1]), vec![location]);
+            Err(StoreError(String::from("interrupted")))
+        });
+        assert!(failed.is_err());
+        assert_eq!(store.block_count(), 0);
+        assert!(store.lookup(Index::L1TxHash, &[1]).is_empty());
+
+        store
+            .in_transaction(&mut || {
+                let mut batch = WriteBatch::default();
+                batch.put_block(Block::default());
+                store.write(batch)
+            })
+            .unwrap();
+        assert_eq!(store.block_count(), 1);
+    }
+}
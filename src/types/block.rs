@@ -1,14 +1,21 @@
 use crate::bindings::rollup_processor::RollupProcessedFilter;
 use crate::types::{
     defi_interaction::DefiInteraction,
+    layout::{Layout, LayoutVersion},
+    rollup_view::{DecodeError, RollupView},
     rollup_proof::Proof,
     transaction::{InnerProofData, ProofId},
 };
 use ethers::types::{Address, Bytes, TxHash, H256, U256, U64};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The number of DeFi interactions a single rollup can make, each gets its own interaction nonce.
+pub const NUMBER_OF_BRIDGE_CALLS: usize = 32;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FeeAsset {
+    pub slot: u64,
     pub asset_id: U256,
     pub amount: U256,
 }
@@ -26,67 +33,96 @@ pub struct Header {
     pub new_data_roots_root: H256,
     pub old_defi_root: H256,
     pub new_defi_root: H256,
-    pub defi_interactions: Vec<DefiInteraction>,
-    pub fees: Vec<FeeAsset>,
-    pub prev_defi_interaction_hashes: Vec<H256>,
+    /// Every bridge call slot of the rollup, including the unused ones.
+    pub defi_interaction_slots: Vec<DefiInteraction>,
+    /// Every fee slot of the rollup, including the ones without fees. Blocks upgraded from record
+    /// layout 0 only have the slots with fees, see `records`.
+    pub fee_slots: Vec<FeeAsset>,
+    /// Every slot of defi interaction hashes, including the unused ones after the hashes inserted.
+    /// Blocks upgraded from record layout 0 have zero hashes in those, see `records`.
+    pub prev_defi_interaction_hash_slots: Vec<H256>,
     pub prev_defi_interaction_hash: H256,
     pub rollup_beneficiary: Address,
     pub num_rollup_txs: U256,
 }
 
-impl From<Bytes> for Header {
-    fn from(src: Bytes) -> Self {
-        let sl = &src;
-        let mut header = Header::default();
-
-        header.rollup_id = U256::from_big_endian(&sl[0..32]);
-        header.rollup_size = U256::from_big_endian(&sl[32..64]);
-        header.data_start_index = U256::from_big_endian(&sl[64..96]);
-        header.old_data_root = H256::from_slice(&sl[96..128]);
-        header.new_data_root = H256::from_slice(&sl[128..160]);
-        header.old_null_root = H256::from_slice(&sl[160..192]);
-        header.new_null_root = H256::from_slice(&sl[192..224]);
-        header.old_data_roots_root = H256::from_slice(&sl[224..256]);
-        header.new_data_roots_root = H256::from_slice(&sl[256..288]);
-        header.old_defi_root = H256::from_slice(&sl[288..320]);
-        header.new_defi_root = H256::from_slice(&sl[320..352]);
-
-        for i in 0..32 {
-            if i < 16 {
-                // Note: While we could practically screw up the ordering here, it don't matter much
-                // because, it does not go into the rollup state.
-                let fee = FeeAsset {
-                    asset_id: U256::from_big_endian(&sl[2400 + 32 * i..2400 + 32 * i + 32]),
-                    amount: U256::from_big_endian(&sl[2912 + 32 * i..2912 + 32 * i + 32]),
-                };
-                if !fee.amount.is_zero() {
-                    header.fees.push(fee);
-                }
-            }
-            let defi_interaction = DefiInteraction::from((
-                Bytes::from(sl[352 + 32 * i..352 + 32 * i + 32].to_vec()),
-                U256::from_big_endian(&sl[1376 + 32 * i..1376 + 32 * i + 32]),
-            ));
-            if !defi_interaction.bridge_address_id.is_zero() {
-                header.defi_interactions.push(defi_interaction);
-            }
+impl Header {
+    /// The fee slots that collected fees.
+    pub fn fees(&self) -> impl Iterator<Item = &FeeAsset> {
+        self.fee_slots.iter().filter(|fee| !fee.amount.is_zero())
+    }
+
+    /// The bridge call slots that are in use.
+    pub fn defi_interactions(&self) -> impl Iterator<Item = &DefiInteraction> {
+        self.defi_interaction_slots
+            .iter()
+            .filter(|interaction| interaction.is_in_use())
+    }
+
+    /// The non-zero defi interaction hashes inserted by this rollup.
+    pub fn prev_defi_interaction_hashes(&self) -> impl Iterator<Item = &H256> {
+        self.prev_defi_interaction_hash_slots
+            .iter()
+            .filter(|hash| !hash.is_zero())
+    }
+}
+
+impl TryFrom<&RollupView<'_>> for Header {
+    type Error = DecodeError;
+    fn try_from(view: &RollupView<'_>) -> Result<Self, Self::Error> {
+        let layout = view.layout();
+        let mut header = Header {
+            rollup_id: view.rollup_id(),
+            rollup_size: view.rollup_size(),
+            data_start_index: view.data_start_index(),
+            old_data_root: view.old_data_root(),
+            new_data_root: view.new_data_root(),
+            old_null_root: view.old_null_root(),
+            new_null_root: view.new_null_root(),
+            old_data_roots_root: view.old_data_roots_root(),
+            new_data_roots_root: view.new_data_roots_root(),
+            old_defi_root: view.old_defi_root(),
+            new_defi_root: view.new_defi_root(),
+            prev_defi_interaction_hash: view.prev_defi_interaction_hash(),
+            rollup_beneficiary: view.rollup_beneficiary(),
+            num_rollup_txs: view.num_rollup_txs(),
+            ..Default::default()
+        };
+
+        for i in 0..layout.number_of_fee_assets {
+            header.fee_slots.push(FeeAsset {
+                slot: i as u64,
+                asset_id: view.fee_asset_id(i),
+                amount: view.total_tx_fee(i),
+            });
+        }
+
+        for i in 0..layout.number_of_bridge_calls {
+            let mut defi_interaction = DefiInteraction::try_from((
+                view.encoded_bridge_call_data(i),
+                view.defi_deposit_sum(i),
+            ))
+            .map_err(|err| {
+                let offset = layout.bridge_call_data_offset() + 32 * i;
+                DecodeError::new(offset, format!("Invalid bridge call data in slot {}: {}", i, err))
+            })?;
+            defi_interaction.slot = i as u64;
+            let interaction_nonce = header
+                .rollup_id
+                .checked_mul(U256::from(layout.number_of_bridge_calls))
+                .and_then(|nonce| nonce.checked_add(U256::from(i)))
+                .ok_or_else(|| DecodeError::new(0, format!("Interaction nonce of slot {} overflows", i)))?;
+            defi_interaction.interaction_nonce = interaction_nonce;
+            header.defi_interaction_slots.push(defi_interaction);
 
             // Note: The notes that we will be adding to the defi tree, up to 32 this will be the one from the last
             // rollup we executed. Hashes in n..32 will be the the "empty defi interaction result hash".
-            let prev_defi_interaction_hash_i =
-                H256::from_slice(&sl[3424 + 32 * i..3424 + 32 * i + 32]);
-            if !prev_defi_interaction_hash_i.is_zero() {
-                header
-                    .prev_defi_interaction_hashes
-                    .push(prev_defi_interaction_hash_i);
-            }
+            header
+                .prev_defi_interaction_hash_slots
+                .push(view.prev_defi_interaction_hash_slot(i));
         }
 
-        header.prev_defi_interaction_hash = H256::from_slice(&sl[4448..4448 + 32]);
-        header.rollup_beneficiary = Address::from_slice(&sl[4480 + 12..4480 + 32]);
-        header.num_rollup_txs = U256::from_big_endian(&sl[4512..4512 + 32]);
-
-        return header;
+        Ok(header)
     }
 }
 
@@ -95,6 +131,21 @@ pub struct Metadata {
     pub tx_hash: TxHash,
     pub block_number: U64,
     pub timestamp: Option<U256>,
+    /// The calldata layout the block was decoded with, `None` for blocks synced before it was recorded.
+    pub layout_version: Option<LayoutVersion>,
+    /// The size of the input of the L1 tx in bytes, `None` for blocks synced before it was recorded.
+    pub calldata_size: Option<u64>,
+    /// The gas used by the L1 tx, `None` for blocks synced before it was recorded.
+    pub gas_used: Option<U256>,
+    /// The price paid per gas by the L1 tx in wei, `None` for blocks synced before it was recorded.
+    pub effective_gas_price: Option<U256>,
+}
+
+impl Metadata {
+    /// What publishing the rollup cost in wei, when the gas was recorded.
+    pub fn gas_cost(&self) -> Option<U256> {
+        Some(self.gas_used? * self.effective_gas_price?)
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -102,39 +153,43 @@ pub struct InnerBlock {
     pub header: Header,
     pub num_real_txs: U256,
     pub encoded_inner_tx_data_length: U256,
+    /// Empty for blocks read from the store without their txs, see `store::Parts`.
     pub inner_proofs: Vec<InnerProofData>,
-    pub proof: Proof,
+    /// `None` for blocks read from the store without their proof, see `store::Parts`.
+    pub proof: Option<Proof>,
 }
 
-impl From<Bytes> for InnerBlock {
-    fn from(calldata: Bytes) -> Self {
-        let sl = &calldata;
-        let mut inner_block = InnerBlock::default();
-
-        inner_block.header = Header::from(calldata.clone());
-        inner_block.num_real_txs = U256::from_big_endian(&sl[4544..4548]);
-        inner_block.encoded_inner_tx_data_length = U256::from_big_endian(&sl[4548..4552]);
+impl TryFrom<&RollupView<'_>> for InnerBlock {
+    type Error = DecodeError;
+    fn try_from(view: &RollupView<'_>) -> Result<Self, Self::Error> {
+        let header = Header::try_from(view)?;
+        let mut inner_proofs = vec![];
 
-        let mut start = 4552;
-        let end = start + inner_block.encoded_inner_tx_data_length.as_usize();
-
-        while start < end {
-            let proof_id = ProofId::try_from(sl[start]).unwrap();
-            let proof_size = proof_id.data_size();
-            let data = Bytes::from(sl[start..start + proof_size].to_vec());
-            let inner_proof = InnerProofData::try_from(data).unwrap();
+        for tx in view.inner_txs() {
+            let tx = tx?;
+            let mut inner_proof = InnerProofData::try_from(tx.data)
+                .map_err(|err| DecodeError::new(tx.offset, format!("Invalid tx: {}", err)))?;
+            // The data start index is the third word of the header.
+            inner_proof
+                .set_position(header.data_start_index, tx.position)
+                .map_err(|err| DecodeError::new(64, err))?;
 
             match inner_proof.proof_id {
                 ProofId::Padding => (),
-                _ => inner_block.inner_proofs.push(inner_proof),
+                _ => inner_proofs.push(inner_proof),
             }
-
-            start += proof_size;
         }
 
-        inner_block.proof = Proof::from(Bytes::from(sl[start..calldata.len()].to_vec()));
+        let proof = Proof::try_from(view.proof_data())
+            .map_err(|err| DecodeError::new(view.proof_data_offset(), format!("Invalid proof: {}", err)))?;
 
-        return inner_block;
+        Ok(InnerBlock {
+            header,
+            num_real_txs: view.num_real_txs(),
+            encoded_inner_tx_data_length: view.encoded_inner_tx_data_length(),
+            inner_proofs,
+            proof: Some(proof),
+        })
     }
 }
 
@@ -146,8 +201,9 @@ pub struct Block {
     pub sequencer: Address,
 }
 
-impl From<(TxHash, U64, U256, Bytes, RollupProcessedFilter)> for Block {
-    fn from(
+impl TryFrom<(TxHash, U64, U256, Bytes, RollupProcessedFilter)> for Block {
+    type Error = String;
+    fn try_from(
         (tx_hash, block_number, timestamp, proof_calldata, rollup_processed_event): (
             TxHash,
             U64,
@@ -155,29 +211,67 @@ impl From<(TxHash, U64, U256, Bytes, RollupProcessedFilter)> for Block {
             Bytes,
             RollupProcessedFilter,
         ),
-    ) -> Self {
-        let mut block = Block::default();
-        block.inner = InnerBlock::from(proof_calldata);
-        block.metadata = Metadata {
-            tx_hash,
-            block_number,
-            timestamp: Some(timestamp),
-        };
+    ) -> Result<Self, Self::Error> {
+        let layout = Layout::for_block(block_number.as_u64())?;
+        let view = RollupView::new(&proof_calldata, layout).map_err(|err| err.to_string())?;
+        Ok(Block {
+            metadata: Metadata {
+                tx_hash,
+                block_number,
+                timestamp: Some(timestamp),
+                layout_version: Some(layout.version),
+                ..Metadata::default()
+            },
+            inner: InnerBlock::try_from(&view).map_err(|err| err.to_string())?,
+            next_expected_defi_hashes: rollup_processed_event
+                .next_expected_defi_hashes
+                .iter()
+                .map(|x| H256::from_slice(&x[0..32]))
+                .collect(),
+            sequencer: rollup_processed_event.sender,
+        })
+    }
+}
 
-        block.next_expected_defi_hashes = rollup_processed_event
-            .next_expected_defi_hashes
-            .iter()
-            .map(|x| H256::from_slice(&x[0..32]))
-            .collect();
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
 
-        block.sequencer = rollup_processed_event.sender;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::layout::AZTEC_CONNECT;
+    use crate::types::rollup_view::tests::calldata_with_txs;
 
-        return block;
+    #[test]
+    fn refuses_bridge_call_data_above_248_bits() {
+        let layout = &AZTEC_CONNECT;
+        let mut calldata = calldata_with_txs(&[]);
+        let offset = layout.bridge_call_data_offset() + 32 * 2;
+        calldata[offset] = 1;
+
+        let view = RollupView::new(&calldata, layout).unwrap();
+        assert_eq!(InnerBlock::try_from(&view).unwrap_err().offset, offset);
+
+        calldata[offset] = 0;
+        calldata[offset + 31] = 1;
+        let view = RollupView::new(&calldata, layout).unwrap();
+        let inner = InnerBlock::try_from(&view).unwrap();
+        assert_eq!(inner.header.defi_interaction_slots[2].bridge_address_id(), U256::one());
+        assert_eq!(inner.header.defi_interaction_slots[2].interaction_nonce, U256::from(2));
     }
-}
 
-impl ToString for Block {
-    fn to_string(&self) -> String {
-        return serde_json::to_string(self).unwrap();
+    #[test]
+    fn refuses_leaf_indexes_above_64_bits() {
+        let layout = &AZTEC_CONNECT;
+        let mut calldata = calldata_with_txs(&[0]);
+        calldata[64 + 23] = 1;
+
+        let view = RollupView::new(&calldata, layout).unwrap();
+        let err = InnerBlock::try_from(&view).unwrap_err();
+        assert_eq!(err.offset, 64);
+        assert!(err.message.contains("doesn't fit in 64 bits"));
     }
 }
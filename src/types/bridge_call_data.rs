@@ -0,0 +1,265 @@
+use crate::types::defi_interaction::{Asset, AssetType};
+use ethers::types::U256;
+use ethers::utils::rlp::DecoderError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Shl, Shr};
+
+/// Asset ids with this bit set refer to the interaction nonce that created them rather than a real asset.
+pub const VIRTUAL_ASSET_ID_FLAG: u32 = 1 << 29;
+
+const BRIDGE_ADDRESS_ID_SHIFT: usize = 0;
+const INPUT_ASSET_ID_A_SHIFT: usize = 32;
+const INPUT_ASSET_ID_B_SHIFT: usize = 62;
+const OUTPUT_ASSET_ID_A_SHIFT: usize = 92;
+const OUTPUT_ASSET_ID_B_SHIFT: usize = 122;
+const BIT_CONFIG_SHIFT: usize = 152;
+const AUX_DATA_SHIFT: usize = 184;
+const USED_BITS: usize = 248;
+
+const MASK_30: u64 = 0x3fffffff;
+const MASK_32: u64 = 0xffffffff;
+
+/// The 248 bits that tell the rollup processor which bridge to call, with what assets and aux data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeCallData {
+    pub bridge_address_id: u32,
+    pub input_asset_id_a: u32,
+    pub input_asset_id_b: u32,
+    pub output_asset_id_a: u32,
+    pub output_asset_id_b: u32,
+    pub bit_config: u32,
+    pub aux_data: u64,
+}
+
+impl BridgeCallData {
+    /// Bridge address ids start from 1, so an id of 0 is a header slot without a bridge call.
+    pub fn in_use(&self) -> bool {
+        self.bridge_address_id != 0
+    }
+
+    pub fn second_input_in_use(&self) -> bool {
+        self.bit_config & 1 == 1
+    }
+
+    pub fn second_output_in_use(&self) -> bool {
+        self.bit_config & 2 == 2
+    }
+
+    fn asset(asset_id: u32, in_use: bool) -> Asset {
+        match in_use {
+            false => Asset {
+                asset_type: AssetType::Unused,
+                asset_id: U256::zero(),
+                origin_nonce: None,
+            },
+            true => Asset {
+                asset_type: match asset_id & VIRTUAL_ASSET_ID_FLAG {
+                    0 => AssetType::Real,
+                    _ => AssetType::Virtual,
+                },
+                asset_id: U256::from(asset_id),
+                origin_nonce: None,
+            },
+        }
+    }
+
+    /// Virtual inputs carry the nonce of the interaction that created them in their asset id.
+    fn input(asset_id: u32, in_use: bool) -> Asset {
+        let mut asset = Self::asset(asset_id, in_use);
+        if let AssetType::Virtual = asset.asset_type {
+            asset.origin_nonce = Some(U256::from(asset_id & !VIRTUAL_ASSET_ID_FLAG));
+        }
+        asset
+    }
+
+    pub fn input_asset_a(&self) -> Asset {
+        Self::input(self.input_asset_id_a, self.in_use())
+    }
+
+    pub fn input_asset_b(&self) -> Asset {
+        Self::input(self.input_asset_id_b, self.in_use() && self.second_input_in_use())
+    }
+
+    pub fn output_asset_a(&self) -> Asset {
+        Self::asset(self.output_asset_id_a, self.in_use())
+    }
+
+    pub fn output_asset_b(&self) -> Asset {
+        Self::asset(self.output_asset_id_b, self.in_use() && self.second_output_in_use())
+    }
+}
+
+impl TryFrom<U256> for BridgeCallData {
+    type Error = DecoderError;
+    fn try_from(src: U256) -> Result<Self, Self::Error> {
+        if !src.shr(USED_BITS).is_zero() {
+            return Err(DecoderError::Custom("Bridge call data uses more than 248 bits"));
+        }
+
+        let field = |shift: usize, mask: u64| (src.shr(shift) & U256::from(mask)).as_u64();
+
+        Ok(BridgeCallData {
+            bridge_address_id: field(BRIDGE_ADDRESS_ID_SHIFT, MASK_32) as u32,
+            input_asset_id_a: field(INPUT_ASSET_ID_A_SHIFT, MASK_30) as u32,
+            input_asset_id_b: field(INPUT_ASSET_ID_B_SHIFT, MASK_30) as u32,
+            output_asset_id_a: field(OUTPUT_ASSET_ID_A_SHIFT, MASK_30) as u32,
+            output_asset_id_b: field(OUTPUT_ASSET_ID_B_SHIFT, MASK_30) as u32,
+            bit_config: field(BIT_CONFIG_SHIFT, MASK_32) as u32,
+            aux_data: field(AUX_DATA_SHIFT, u64::MAX),
+        })
+    }
+}
+
+impl TryFrom<BridgeCallData> for U256 {
+    type Error = DecoderError;
+    fn try_from(src: BridgeCallData) -> Result<Self, Self::Error> {
+        let asset_ids = [
+            src.input_asset_id_a,
+            src.input_asset_id_b,
+            src.output_asset_id_a,
+            src.output_asset_id_b,
+        ];
+        if asset_ids.iter().any(|id| u64::from(*id) > MASK_30) {
+            return Err(DecoderError::Custom("Asset id does not fit in 30 bits"));
+        }
+
+        Ok(U256::from(src.bridge_address_id).shl(BRIDGE_ADDRESS_ID_SHIFT)
+            | U256::from(src.input_asset_id_a).shl(INPUT_ASSET_ID_A_SHIFT)
+            | U256::from(src.input_asset_id_b).shl(INPUT_ASSET_ID_B_SHIFT)
+            | U256::from(src.output_asset_id_a).shl(OUTPUT_ASSET_ID_A_SHIFT)
+            | U256::from(src.output_asset_id_b).shl(OUTPUT_ASSET_ID_B_SHIFT)
+            | U256::from(src.bit_config).shl(BIT_CONFIG_SHIFT)
+            | U256::from(src.aux_data).shl(AUX_DATA_SHIFT))
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.asset_type {
+            AssetType::Unused => write!(f, "unused"),
+            AssetType::Real => write!(f, "{}", self.asset_id),
+            AssetType::Virtual => match self.origin_nonce {
+                Some(nonce) => write!(f, "virtual (nonce {})", nonce),
+                None => write!(f, "virtual (nonce {})", self.asset_id.as_u32() & !VIRTUAL_ASSET_ID_FLAG),
+            },
+        }
+    }
+}
+
+impl fmt::Display for BridgeCallData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bridge address id: {}", self.bridge_address_id)?;
+        writeln!(f, "input asset a:     {}", self.input_asset_a())?;
+        writeln!(f, "input asset b:     {}", self.input_asset_b())?;
+        writeln!(f, "output asset a:    {}", self.output_asset_a())?;
+        writeln!(f, "output asset b:    {}", self.output_asset_b())?;
+        writeln!(f, "bit config:        {:#b}", self.bit_config)?;
+        write!(f, "aux data:          {}", self.aux_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(bridge_call_data: BridgeCallData) -> U256 {
+        U256::try_from(bridge_call_data).unwrap()
+    }
+
+    #[test]
+    fn asset_ids_fill_30_bits_each() {
+        let max = MASK_30 as u32;
+        for (shift, bridge_call_data) in [
+            (INPUT_ASSET_ID_A_SHIFT, BridgeCallData { input_asset_id_a: max, ..Default::default() }),
+            (INPUT_ASSET_ID_B_SHIFT, BridgeCallData { input_asset_id_b: max, ..Default::default() }),
+            (OUTPUT_ASSET_ID_A_SHIFT, BridgeCallData { output_asset_id_a: max, ..Default::default() }),
+            (OUTPUT_ASSET_ID_B_SHIFT, BridgeCallData { output_asset_id_b: max, ..Default::default() }),
+        ] {
+            let encoded = encode(bridge_call_data);
+            assert_eq!(encoded, U256::from(MASK_30) << shift);
+            assert_eq!(BridgeCallData::try_from(encoded).unwrap(), bridge_call_data);
+        }
+
+        let too_large = BridgeCallData { output_asset_id_b: max + 1, ..Default::default() };
+        assert!(U256::try_from(too_large).is_err());
+    }
+
+    #[test]
+    fn fields_are_decoded_from_their_bits() {
+        let encoded = U256::from(7)
+            | U256::from(1) << 32
+            | U256::from(2) << 62
+            | U256::from(3) << 92
+            | U256::from(4) << 122
+            | U256::from(3) << 152
+            | U256::from(u64::MAX) << 184;
+        let bridge_call_data = BridgeCallData::try_from(encoded).unwrap();
+        assert_eq!(
+            bridge_call_data,
+            BridgeCallData {
+                bridge_address_id: 7,
+                input_asset_id_a: 1,
+                input_asset_id_b: 2,
+                output_asset_id_a: 3,
+                output_asset_id_b: 4,
+                bit_config: 3,
+                aux_data: u64::MAX,
+            }
+        );
+        assert_eq!(encode(bridge_call_data), encoded);
+    }
+
+    #[test]
+    fn bit_config_marks_second_assets_in_use() {
+        let bridge_call_data = BridgeCallData {
+            bridge_address_id: 1,
+            input_asset_id_b: 5,
+            output_asset_id_b: 6,
+            ..Default::default()
+        };
+        assert!(matches!(bridge_call_data.input_asset_b().asset_type, AssetType::Unused));
+        assert!(matches!(bridge_call_data.output_asset_b().asset_type, AssetType::Unused));
+
+        let inputs = BridgeCallData { bit_config: 1, ..bridge_call_data };
+        assert!(matches!(inputs.input_asset_b().asset_type, AssetType::Real));
+        assert!(matches!(inputs.output_asset_b().asset_type, AssetType::Unused));
+
+        let outputs = BridgeCallData { bit_config: 2, ..bridge_call_data };
+        assert!(matches!(outputs.input_asset_b().asset_type, AssetType::Unused));
+        assert_eq!(outputs.output_asset_b().asset_id, U256::from(6));
+    }
+
+    #[test]
+    fn bit_29_marks_virtual_assets() {
+        let bridge_call_data = BridgeCallData {
+            bridge_address_id: 1,
+            input_asset_id_a: VIRTUAL_ASSET_ID_FLAG | 12,
+            output_asset_id_a: VIRTUAL_ASSET_ID_FLAG - 1,
+            ..Default::default()
+        };
+        assert!(matches!(bridge_call_data.input_asset_a().asset_type, AssetType::Virtual));
+        assert_eq!(bridge_call_data.input_asset_a().origin_nonce, Some(U256::from(12)));
+        assert!(matches!(bridge_call_data.output_asset_a().asset_type, AssetType::Real));
+
+        let decoded = BridgeCallData::try_from(encode(bridge_call_data)).unwrap();
+        assert_eq!(decoded.input_asset_id_a, VIRTUAL_ASSET_ID_FLAG | 12);
+    }
+
+    #[test]
+    fn bridge_address_id_0_marks_unused_slots() {
+        let unused = BridgeCallData { input_asset_id_a: 1, bit_config: 3, ..Default::default() };
+        assert!(!unused.in_use());
+        for asset in [unused.input_asset_a(), unused.input_asset_b(), unused.output_asset_a(), unused.output_asset_b()] {
+            assert!(matches!(asset.asset_type, AssetType::Unused));
+        }
+        assert!(matches!(BridgeCallData { bridge_address_id: 1, ..unused }.input_asset_a().asset_type, AssetType::Real));
+    }
+
+    #[test]
+    fn refuses_bits_above_248() {
+        assert!(BridgeCallData::try_from(U256::from(1) << 247).is_ok());
+        assert!(BridgeCallData::try_from(U256::from(1) << 248).is_err());
+        assert!(BridgeCallData::try_from(U256::MAX).is_err());
+    }
+}
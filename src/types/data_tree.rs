@@ -0,0 +1,122 @@
+use crate::types::pedersen::Pedersen;
+use ark_bn254::Fr;
+use ark_ff::Zero;
+
+/// The height of the data tree, which holds up to 2^32 note commitments.
+pub const DEPTH: usize = 32;
+
+/// The append-only tree of note commitments, where every leaf not yet inserted is zero. Only the
+/// nodes with an inserted leaf below them are kept, the others are roots of all zero subtrees.
+pub struct DataTree {
+    pedersen: Pedersen,
+    /// The kept nodes per height, from the leaves up to the root.
+    levels: Vec<Vec<Fr>>,
+    /// The root of an all zero subtree per height.
+    zeros: Vec<Fr>,
+}
+
+impl Default for DataTree {
+    fn default() -> Self {
+        let pedersen = Pedersen::default();
+        let mut zeros = vec![Fr::zero()];
+        for height in 0..DEPTH {
+            zeros.push(pedersen.compress(&zeros[height], &zeros[height]));
+        }
+        DataTree {
+            pedersen,
+            levels: vec![vec![]; DEPTH + 1],
+            zeros,
+        }
+    }
+}
+
+impl DataTree {
+    /// The number of leaves inserted, and with it the index of the next one.
+    pub fn size(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    pub fn root(&self) -> Fr {
+        self.node(DEPTH, 0)
+    }
+
+    fn node(&self, height: usize, index: usize) -> Fr {
+        self.levels[height].get(index).copied().unwrap_or(self.zeros[height])
+    }
+
+    /// Appends `leaves` and rehashes the nodes above them, a height at a time so every height is
+    /// hashed as one batch.
+    pub fn append(&mut self, leaves: impl IntoIterator<Item = Fr>) {
+        let start = self.levels[0].len();
+        self.levels[0].extend(leaves);
+
+        for height in 0..DEPTH {
+            let first = start >> (height + 1);
+            let parents = self.levels[height].len().div_ceil(2);
+            let pairs: Vec<(Fr, Fr)> = (first..parents)
+                .map(|index| (self.node(height, 2 * index), self.node(height, 2 * index + 1)))
+                .collect();
+            let hashes = self.pedersen.compress_batch(&pairs);
+            let level = &mut self.levels[height + 1];
+            level.truncate(first);
+            level.extend(hashes);
+        }
+    }
+
+    /// The siblings of the nodes from leaf `index` up to the root, or `None` if the leaf isn't inserted.
+    pub fn path(&self, index: u64) -> Option<Vec<Fr>> {
+        if index >= self.size() {
+            return None;
+        }
+        Some((0..DEPTH).map(|height| self.node(height, (index >> height) as usize ^ 1)).collect())
+    }
+
+    /// The root that `leaf` at `index` hashes up to with the siblings of `path`.
+    pub fn root_of_path(&self, leaf: Fr, index: u64, path: &[Fr]) -> Fr {
+        path.iter().enumerate().fold(leaf, |node, (height, sibling)| match (index >> height) & 1 {
+            0 => self.pedersen.compress(&node, sibling),
+            _ => self.pedersen.compress(sibling, &node),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(range: std::ops::Range<u64>) -> Vec<Fr> {
+        range.map(|leaf| Fr::from(leaf + 1)).collect()
+    }
+
+    #[test]
+    fn appending_in_parts_gives_the_same_root() {
+        let mut whole = DataTree::default();
+        let empty = whole.root();
+        whole.append(leaves(0..7));
+
+        let mut parts = DataTree::default();
+        parts.append(leaves(0..4));
+        let after_four = parts.root();
+        parts.append(leaves(4..6));
+        parts.append(leaves(6..7));
+
+        assert_eq!(parts.size(), 7);
+        assert_eq!(parts.root(), whole.root());
+        assert_ne!(after_four, whole.root());
+        assert_ne!(empty, whole.root());
+    }
+
+    #[test]
+    fn paths_hash_up_to_the_root() {
+        let mut tree = DataTree::default();
+        tree.append(leaves(0..5));
+
+        for index in 0..5 {
+            let path = tree.path(index).unwrap();
+            assert_eq!(path.len(), DEPTH);
+            assert_eq!(tree.root_of_path(Fr::from(index + 1), index, &path), tree.root());
+        }
+        assert_ne!(tree.root_of_path(Fr::from(9u64), 2, &tree.path(2).unwrap()), tree.root());
+        assert!(tree.path(5).is_none());
+    }
+}
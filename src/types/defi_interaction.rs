@@ -1,7 +1,12 @@
-use ethers::types::{Bytes, U256};
+use crate::bindings::rollup_processor::DefiBridgeProcessedFilter;
+use crate::types::bridge_call_data::BridgeCallData;
+use ethers::types::{TxHash, H256, U256, U64};
+use ethers::utils::rlp::DecoderError;
 use serde::{Deserialize, Serialize};
-use std::ops::Shr;
-use std::str::FromStr;
+use sha2::{Digest, Sha256};
+
+/// The modulus of the field the rollup circuits work over, defi interaction hashes are reduced by it.
+const CIRCUIT_MODULUS: &str = "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001";
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub enum AssetType {
@@ -15,65 +20,226 @@ pub enum AssetType {
 pub struct Asset {
     pub asset_type: AssetType,
     pub asset_id: U256,
+    /// For virtual assets, the nonce of the interaction that created them.
+    pub origin_nonce: Option<U256>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DefiInteraction {
-    pub bridge_address_id: U256,
-    pub input_asset_a: Asset,
-    pub input_asset_b: Asset,
-    pub output_asset_a: Asset,
-    pub output_asset_b: Asset,
-    pub aux_data: U256,
+    /// The index of the bridge call slot in the rollup header.
+    pub slot: u64,
+    /// The bridge call data as it appears in the header.
+    pub encoded_bridge_call_data: U256,
+    pub bridge_call_data: BridgeCallData,
+    pub total_input_value: U256,
+    pub interaction_nonce: U256,
+}
+
+impl DefiInteraction {
+    /// An interaction of bridge call data that was already decoded from `encoded_bridge_call_data`.
+    pub fn new(encoded_bridge_call_data: U256, bridge_call_data: BridgeCallData, total_input_value: U256) -> Self {
+        DefiInteraction {
+            // Depends on where in the header the bridge call is, set when decoding the header
+            slot: 0,
+            encoded_bridge_call_data,
+            bridge_call_data,
+            total_input_value,
+            // Depends on the rollup and slot, set when decoding the header
+            interaction_nonce: U256::zero(),
+        }
+    }
+
+    /// Whether the slot holds a bridge call, unused slots are left zero.
+    pub fn is_in_use(&self) -> bool {
+        self.bridge_call_data.in_use()
+    }
+
+    pub fn bridge_address_id(&self) -> U256 {
+        U256::from(self.bridge_call_data.bridge_address_id)
+    }
+
+    pub fn aux_data(&self) -> U256 {
+        U256::from(self.bridge_call_data.aux_data)
+    }
+
+    pub fn input_asset_a(&self) -> Asset {
+        self.bridge_call_data.input_asset_a()
+    }
+
+    pub fn input_asset_b(&self) -> Asset {
+        self.bridge_call_data.input_asset_b()
+    }
+
+    pub fn output_asset_a(&self) -> Asset {
+        self.created_by_interaction(self.bridge_call_data.output_asset_a())
+    }
+
+    pub fn output_asset_b(&self) -> Asset {
+        self.created_by_interaction(self.bridge_call_data.output_asset_b())
+    }
+
+    /// Virtual outputs are created by this interaction, so their origin is its nonce.
+    fn created_by_interaction(&self, mut output: Asset) -> Asset {
+        if let AssetType::Virtual = output.asset_type {
+            output.origin_nonce = Some(self.interaction_nonce);
+        }
+        output
+    }
+
+    /// The nonces of the interactions that created the virtual inputs of this interaction.
+    pub fn input_origins(&self) -> Vec<U256> {
+        [self.input_asset_a(), self.input_asset_b()]
+            .iter()
+            .filter_map(|input| input.origin_nonce)
+            .collect()
+    }
+}
+
+impl TryFrom<(U256, U256)> for DefiInteraction {
+    type Error = DecoderError;
+    fn try_from((encoded_bridge_call_data, total_input_value): (U256, U256)) -> Result<Self, Self::Error> {
+        let bridge_call_data = BridgeCallData::try_from(encoded_bridge_call_data)?;
+        Ok(DefiInteraction::new(encoded_bridge_call_data, bridge_call_data, total_input_value))
+    }
+}
+
+/// The outcome of a DeFi interaction, as emitted by `DefiBridgeProcessed` in the rollup that made the
+/// interaction or, for async bridges, in the tx that finalised it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DefiInteractionResult {
+    pub encoded_bridge_call_data: U256,
+    pub interaction_nonce: U256,
     pub total_input_value: U256,
+    pub total_output_value_a: U256,
+    pub total_output_value_b: U256,
+    pub result: bool,
+    pub tx_hash: TxHash,
+    pub block_number: U64,
+}
+
+impl DefiInteractionResult {
+    /// The hash the rollup processor queues for insertion into the defi tree by a later rollup.
+    pub fn hash(&self) -> H256 {
+        let mut encoded = [0u8; 192];
+        for (i, word) in [
+            self.encoded_bridge_call_data,
+            self.interaction_nonce,
+            self.total_input_value,
+            self.total_output_value_a,
+            self.total_output_value_b,
+            U256::from(self.result as u8),
+        ]
+        .iter()
+        .enumerate()
+        {
+            word.to_big_endian(&mut encoded[32 * i..32 * i + 32]);
+        }
+
+        let digest = U256::from_big_endian(&Sha256::digest(encoded));
+        let hash = digest % U256::from(CIRCUIT_MODULUS);
+        let mut out = [0u8; 32];
+        hash.to_big_endian(&mut out);
+        H256::from(out)
+    }
+}
+
+impl From<(TxHash, U64, DefiBridgeProcessedFilter)> for DefiInteractionResult {
+    fn from((tx_hash, block_number, event): (TxHash, U64, DefiBridgeProcessedFilter)) -> Self {
+        DefiInteractionResult {
+            encoded_bridge_call_data: event.encoded_bridge_call_data,
+            interaction_nonce: event.nonce,
+            total_input_value: event.total_input_value,
+            total_output_value_a: event.total_output_value_a,
+            total_output_value_b: event.total_output_value_b,
+            result: event.result,
+            tx_hash,
+            block_number,
+        }
+    }
 }
 
-impl From<(Bytes, U256)> for DefiInteraction {
-    fn from((bridge_calldata, total_input_value): (Bytes, U256)) -> Self {
-        let mask_32 = U256::from_str("0xffffffff").unwrap();
-        let mask_30 = U256::from_str("0x3fffffff").unwrap();
-        let mut defi_interaction = DefiInteraction::default();
-        defi_interaction.total_input_value = total_input_value;
-        defi_interaction.aux_data = U256::from_big_endian(&bridge_calldata[1..9]);
-
-        let src = U256::from_big_endian(&bridge_calldata[0..32]);
-        defi_interaction.bridge_address_id = src & mask_32;
-
-        let input_asset_id_a = src.shr(32) & mask_30;
-        let input_asset_id_b = src.shr(62) & mask_30;
-        let output_asset_id_a = src.shr(92) & mask_30;
-        let output_asset_id_b = src.shr(122) & mask_30;
-        let bit_config = src.shr(152) & mask_32;
-
-        let second_input_in_use = bit_config.bit(0);
-        let second_output_in_use = bit_config.bit(1);
-
-        fn asset_in_use(asset_id: U256) -> Asset {
-            Asset {
-                asset_id,
-                asset_type: match asset_id.bit(30) {
-                    true => AssetType::Virtual,
-                    false => AssetType::Real,
-                },
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::bridge_call_data::VIRTUAL_ASSET_ID_FLAG;
+
+    fn interaction(bridge_call_data: BridgeCallData) -> DefiInteraction {
+        let encoded = U256::try_from(bridge_call_data).unwrap();
+        DefiInteraction::try_from((encoded, U256::from(100))).unwrap()
+    }
+
+    #[test]
+    fn virtual_inputs_link_to_the_nonce_in_their_asset_id() {
+        let mut interaction = interaction(BridgeCallData {
+            bridge_address_id: 1,
+            input_asset_id_a: VIRTUAL_ASSET_ID_FLAG | 70,
+            input_asset_id_b: VIRTUAL_ASSET_ID_FLAG | 33,
+            bit_config: 1,
+            ..Default::default()
+        });
+        interaction.interaction_nonce = U256::from(96);
+
+        assert_eq!(interaction.input_asset_a().origin_nonce, Some(U256::from(70)));
+        assert_eq!(interaction.input_origins(), vec![U256::from(70), U256::from(33)]);
+        assert_eq!(interaction.output_asset_a().origin_nonce, None);
+    }
+
+    #[test]
+    fn virtual_outputs_are_created_by_the_interaction() {
+        let mut interaction = interaction(BridgeCallData {
+            bridge_address_id: 1,
+            input_asset_id_a: 2,
+            output_asset_id_a: VIRTUAL_ASSET_ID_FLAG,
+            output_asset_id_b: VIRTUAL_ASSET_ID_FLAG,
+            ..Default::default()
+        });
+        interaction.interaction_nonce = U256::from(97);
+
+        assert_eq!(interaction.input_asset_a().origin_nonce, None);
+        assert!(interaction.input_origins().is_empty());
+        assert_eq!(interaction.output_asset_a().origin_nonce, Some(U256::from(97)));
+        // The second output is unused, as the bit config doesn't say otherwise.
+        assert_eq!(interaction.output_asset_b().origin_nonce, None);
+    }
+
+    #[test]
+    fn empty_slots_have_no_assets() {
+        let interaction = interaction(BridgeCallData::default());
+        assert!(!interaction.is_in_use());
+        for asset in [interaction.input_asset_a(), interaction.output_asset_a()] {
+            assert!(matches!(asset.asset_type, AssetType::Unused));
         }
+    }
 
-        fn asset_possible_in_use(in_use: bool, asset_id: U256) -> Asset {
-            return match in_use {
-                true => asset_in_use(asset_id),
-                false => Asset {
-                    asset_type: AssetType::Unused,
-                    asset_id: U256::from(0),
-                },
-            };
+    fn result(result: bool) -> DefiInteractionResult {
+        DefiInteractionResult {
+            encoded_bridge_call_data: U256::from(1) | U256::from(1) << 92,
+            interaction_nonce: U256::from(32),
+            total_input_value: U256::exp10(18),
+            total_output_value_a: U256::exp10(17) * 5,
+            total_output_value_b: U256::zero(),
+            result,
+            ..Default::default()
         }
+    }
+
+    #[test]
+    fn result_hashes_are_reduced_sha256_of_the_event_fields() {
+        assert_eq!(
+            result(true).hash(),
+            "0x15f4122146310c240f927725d465f6bd8872eb6f1d6345b90ed40ae782dec88f".parse().unwrap()
+        );
+        assert_eq!(
+            result(false).hash(),
+            "0x1ffcec74f75f1e07ab46f807b396c3e6171a040c15922ab2cbe53f1e45e3d183".parse().unwrap()
+        );
+    }
 
-        defi_interaction.input_asset_a = asset_in_use(input_asset_id_a);
-        defi_interaction.output_asset_a = asset_in_use(output_asset_id_a);
-        defi_interaction.input_asset_b =
-            asset_possible_in_use(second_input_in_use, input_asset_id_b);
-        defi_interaction.output_asset_b =
-            asset_possible_in_use(second_output_in_use, output_asset_id_b);
-        return defi_interaction;
+    #[test]
+    fn result_hashes_ignore_where_the_result_was_emitted() {
+        let mut other = result(true);
+        other.tx_hash = TxHash::repeat_byte(1);
+        other.block_number = U64::from(100);
+        assert_eq!(other.hash(), result(true).hash());
     }
 }
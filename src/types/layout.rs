@@ -0,0 +1,132 @@
+use crate::types::block::NUMBER_OF_BRIDGE_CALLS;
+use serde::{Deserialize, Serialize};
+
+/// The rollup calldata layouts the decoder knows about, recorded with every stored block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayoutVersion {
+    /// The Aztec Connect RollupProcessor, 32 bridge calls and 16 fee assets per rollup.
+    #[default]
+    AztecConnect,
+}
+
+/// Describes where the fields of a rollup header are in the calldata. The header is a sequence of
+/// 32 byte words, where the number of bridge calls and fee assets decide how long each list is.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub version: LayoutVersion,
+    pub number_of_bridge_calls: usize,
+    pub number_of_fee_assets: usize,
+}
+
+/// The layout of the Aztec Connect rollup processor, the only one rollups have been published with.
+pub static AZTEC_CONNECT: Layout = Layout {
+    version: LayoutVersion::AztecConnect,
+    number_of_bridge_calls: NUMBER_OF_BRIDGE_CALLS,
+    number_of_fee_assets: 16,
+};
+
+impl Layout {
+    pub fn for_version(version: LayoutVersion) -> &'static Layout {
+        match version {
+            LayoutVersion::AztecConnect => &AZTEC_CONNECT,
+        }
+    }
+
+    /// The layout used by the rollup processor at the given L1 block.
+    pub fn for_block(block_number: u64) -> Result<&'static Layout, String> {
+        match block_number >= crate::DEPLOYMENT_BLOCK {
+            true => Ok(&AZTEC_CONNECT),
+            false => Err(format!("No rollup calldata layout is known for L1 block {}", block_number)),
+        }
+    }
+
+    // The rollup id, size, data start index and the old and new data, null, data roots and defi roots.
+    pub fn bridge_call_data_offset(&self) -> usize {
+        11 * 32
+    }
+
+    pub fn defi_deposit_sums_offset(&self) -> usize {
+        self.bridge_call_data_offset() + 32 * self.number_of_bridge_calls
+    }
+
+    pub fn asset_ids_offset(&self) -> usize {
+        self.defi_deposit_sums_offset() + 32 * self.number_of_bridge_calls
+    }
+
+    pub fn total_tx_fees_offset(&self) -> usize {
+        self.asset_ids_offset() + 32 * self.number_of_fee_assets
+    }
+
+    pub fn prev_defi_interaction_hashes_offset(&self) -> usize {
+        self.total_tx_fees_offset() + 32 * self.number_of_fee_assets
+    }
+
+    pub fn prev_defi_interaction_hash_offset(&self) -> usize {
+        self.prev_defi_interaction_hashes_offset() + 32 * self.number_of_bridge_calls
+    }
+
+    pub fn rollup_beneficiary_offset(&self) -> usize {
+        self.prev_defi_interaction_hash_offset() + 32
+    }
+
+    pub fn num_rollup_txs_offset(&self) -> usize {
+        self.rollup_beneficiary_offset() + 32
+    }
+
+    /// The end of the header, followed by the 4 byte number of real txs.
+    pub fn num_real_txs_offset(&self) -> usize {
+        self.num_rollup_txs_offset() + 32
+    }
+
+    pub fn encoded_inner_tx_data_length_offset(&self) -> usize {
+        self.num_real_txs_offset() + 4
+    }
+
+    pub fn inner_txs_offset(&self) -> usize {
+        self.encoded_inner_tx_data_length_offset() + 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aztec_connect_offsets_match_the_rollup_processor() {
+        let layout = &AZTEC_CONNECT;
+        assert_eq!(layout.bridge_call_data_offset(), 352);
+        assert_eq!(layout.defi_deposit_sums_offset(), 1376);
+        assert_eq!(layout.asset_ids_offset(), 2400);
+        assert_eq!(layout.total_tx_fees_offset(), 2912);
+        assert_eq!(layout.prev_defi_interaction_hashes_offset(), 3424);
+        assert_eq!(layout.prev_defi_interaction_hash_offset(), 4448);
+        assert_eq!(layout.rollup_beneficiary_offset(), 4480);
+        assert_eq!(layout.num_rollup_txs_offset(), 4512);
+        assert_eq!(layout.num_real_txs_offset(), 4544);
+        assert_eq!(layout.encoded_inner_tx_data_length_offset(), 4548);
+        assert_eq!(layout.inner_txs_offset(), 4552);
+    }
+
+    #[test]
+    fn offsets_follow_the_number_of_slots() {
+        let layout = Layout {
+            version: LayoutVersion::AztecConnect,
+            number_of_bridge_calls: 4,
+            number_of_fee_assets: 2,
+        };
+        assert_eq!(layout.defi_deposit_sums_offset(), 352 + 4 * 32);
+        assert_eq!(layout.asset_ids_offset(), 352 + 8 * 32);
+        assert_eq!(layout.total_tx_fees_offset(), 352 + 10 * 32);
+        assert_eq!(layout.prev_defi_interaction_hashes_offset(), 352 + 12 * 32);
+        assert_eq!(layout.inner_txs_offset(), 352 + 19 * 32 + 8);
+    }
+
+    #[test]
+    fn blocks_before_the_deployment_have_no_layout() {
+        let deployment_block = crate::DEPLOYMENT_BLOCK;
+        assert_eq!(Layout::for_block(deployment_block).unwrap().version, LayoutVersion::AztecConnect);
+        assert_eq!(Layout::for_block(u64::MAX).unwrap().version, LayoutVersion::AztecConnect);
+        assert!(Layout::for_block(deployment_block - 1).is_err());
+        assert!(Layout::for_block(0).is_err());
+    }
+}
@@ -1,4 +1,9 @@
 pub mod block;
+pub mod bridge_call_data;
+pub mod data_tree;
 pub mod defi_interaction;
+pub mod layout;
+pub mod pedersen;
 pub mod rollup_proof;
+pub mod rollup_view;
 pub mod transaction;
\ No newline at end of file
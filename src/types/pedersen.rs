@@ -0,0 +1,166 @@
+use ark_bn254::{Fq, Fr};
+use ark_ec::short_weierstrass::{Affine, Projective, SWCurveConfig};
+use ark_ec::{CurveConfig, CurveGroup};
+use ark_ff::{BigInteger, Field, MontFp, PrimeField, Zero};
+use ethers::types::{H256, U256};
+use ethers::utils::keccak256;
+use rayon::prelude::*;
+
+/// Grumpkin, the curve over the scalar field of BN254 that the rollup circuits hash with:
+/// `y^2 = x^3 - 17`, with a group order of the BN254 base field.
+pub struct Grumpkin;
+
+impl CurveConfig for Grumpkin {
+    type BaseField = Fr;
+    type ScalarField = Fq;
+
+    const COFACTOR: &'static [u64] = &[0x1];
+    const COFACTOR_INV: Fq = Fq::ONE;
+}
+
+impl SWCurveConfig for Grumpkin {
+    const COEFF_A: Fr = Fr::ZERO;
+    const COEFF_B: Fr = MontFp!("-17");
+    /// `(1, sqrt(-16))`.
+    const GENERATOR: Affine<Grumpkin> =
+        Affine::new_unchecked(Fr::ONE, MontFp!("17631683881184975370165255887551781615748388533673675138860"));
+
+    fn mul_by_a(_: Fr) -> Fr {
+        Fr::ZERO
+    }
+}
+
+type Point = Affine<Grumpkin>;
+
+/// Bits of the scalar per table lookup.
+const WINDOW_BITS: usize = 8;
+const WINDOWS: usize = 256 / WINDOW_BITS;
+
+/// Every multiple of a generator that a window of a scalar can select, so a multiplication is one
+/// addition per window.
+struct FixedBase {
+    table: Vec<Point>,
+}
+
+impl FixedBase {
+    fn new(generator: Point) -> Self {
+        let mut base = Projective::from(generator);
+        let mut multiples = Vec::with_capacity(WINDOWS << WINDOW_BITS);
+        for _ in 0..WINDOWS {
+            let mut multiple = Projective::zero();
+            for _ in 0..1 << WINDOW_BITS {
+                multiples.push(multiple);
+                multiple += base;
+            }
+            base = multiple;
+        }
+        FixedBase { table: Projective::normalize_batch(&multiples) }
+    }
+
+    fn mul_add(&self, scalar: &Fr, sum: &mut Projective<Grumpkin>) {
+        for (window, byte) in scalar.into_bigint().to_bytes_le().into_iter().enumerate() {
+            *sum += &self.table[(window << WINDOW_BITS) + byte as usize];
+        }
+    }
+}
+
+/// Derives generators like barretenberg's `hash_to_curve`: counting up from seed 1, the keccak256
+/// hash of the seed as a 32 byte word, with its top bit cleared, is the x coordinate and the top bit
+/// picks the odd or even y. Seeds whose x has no point on the curve are skipped.
+fn derive_generators(count: usize) -> Vec<Point> {
+    let mut generators = vec![];
+    let mut seed = 0u64;
+    while generators.len() < count {
+        seed += 1;
+        let mut word = [0u8; 32];
+        U256::from(seed).to_big_endian(&mut word);
+        let mut hash = keccak256(word);
+        let odd = hash[0] & 0x80 != 0;
+        hash[0] &= 0x7f;
+
+        let x = Fr::from_be_bytes_mod_order(&hash);
+        if let Some(y) = (x * x * x + Grumpkin::COEFF_B).sqrt() {
+            let y = if y.into_bigint().is_odd() == odd { y } else { -y };
+            generators.push(Point::new_unchecked(x, y));
+        }
+    }
+    generators
+}
+
+/// The Pedersen compression the data tree is built with, `x(left * G0 + right * G1)` on Grumpkin.
+/// The x coordinate of the point at infinity is taken as zero.
+pub struct Pedersen {
+    generators: [FixedBase; 2],
+}
+
+impl Default for Pedersen {
+    fn default() -> Self {
+        let [g0, g1]: [Point; 2] = derive_generators(2).try_into().unwrap();
+        Pedersen {
+            generators: [FixedBase::new(g0), FixedBase::new(g1)],
+        }
+    }
+}
+
+impl Pedersen {
+    fn commit(&self, left: &Fr, right: &Fr) -> Projective<Grumpkin> {
+        let mut sum = Projective::zero();
+        self.generators[0].mul_add(left, &mut sum);
+        self.generators[1].mul_add(right, &mut sum);
+        sum
+    }
+
+    pub fn compress(&self, left: &Fr, right: &Fr) -> Fr {
+        self.commit(left, right).into_affine().x
+    }
+
+    /// Compresses every pair, in parallel and with a single inversion for the whole batch.
+    pub fn compress_batch(&self, pairs: &[(Fr, Fr)]) -> Vec<Fr> {
+        let points: Vec<Projective<Grumpkin>> = pairs.par_iter().map(|(left, right)| self.commit(left, right)).collect();
+        Projective::normalize_batch(&points).into_iter().map(|point| point.x).collect()
+    }
+}
+
+/// Reads a 32 byte word as a field element, or `None` if it is not below the modulus.
+pub fn field(word: &H256) -> Option<Fr> {
+    Fr::from_bigint(ark_ff::BigInt(U256::from_big_endian(word.as_bytes()).0))
+}
+
+pub fn word(value: &Fr) -> H256 {
+    let mut bytes = [0u8; 32];
+    U256(value.into_bigint().0).to_big_endian(&mut bytes);
+    H256(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_are_on_the_curve() {
+        assert!(Grumpkin::GENERATOR.is_on_curve());
+        let generators = derive_generators(2);
+        assert!(generators.iter().all(|generator| generator.is_on_curve() && !generator.infinity));
+        assert_ne!(generators[0], generators[1]);
+    }
+
+    #[test]
+    fn windows_multiply_like_the_group() {
+        let pedersen = Pedersen::default();
+        let [g0, g1]: [Point; 2] = derive_generators(2).try_into().unwrap();
+        let (left, right) = (-Fr::from(5u64), Fr::from(u64::MAX) * Fr::from(u64::MAX));
+        let expected = g0 * Fq::from_le_bytes_mod_order(&left.into_bigint().to_bytes_le())
+            + g1 * Fq::from_le_bytes_mod_order(&right.into_bigint().to_bytes_le());
+
+        assert_eq!(pedersen.compress(&left, &right), expected.into_affine().x);
+        assert_ne!(pedersen.compress(&left, &right), pedersen.compress(&right, &left));
+        assert_eq!(pedersen.compress_batch(&[(left, right), (Fr::ZERO, Fr::ZERO)]), vec![pedersen.compress(&left, &right), Fr::ZERO]);
+    }
+
+    #[test]
+    fn words_round_trip_below_the_modulus() {
+        let value = -Fr::ONE;
+        assert_eq!(field(&word(&value)), Some(value));
+        assert_eq!(field(&H256::repeat_byte(0xff)), None);
+    }
+}
@@ -1,7 +1,12 @@
-use ethers::types::{Bytes, H256, U256};
+use ethers::types::{H256, U256};
+use ethers::utils::rlp::DecoderError;
 use serde::{Deserialize, Serialize};
 use std::ops::Shl;
 
+/// The size of the encoded proof: the public input hash, the 4 limbs of the 4 recursive coordinates
+/// and 22 words for the points and evaluations of the proof itself.
+pub const PROOF_SIZE: usize = 544 + 32 * 22;
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct G1Point {
     pub x: U256,
@@ -29,11 +34,16 @@ pub struct Proof {
     pub pi_z_omega: G1Point,
 }
 
-impl From<Bytes> for Proof {
-    fn from(src: Bytes) -> Self {
-        let mut proof = Proof::default();
-
-        proof.public_input_hash = H256::from_slice(&src[0..32]);
+impl TryFrom<&[u8]> for Proof {
+    type Error = DecoderError;
+    fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        if src.len() < PROOF_SIZE {
+            return Err(DecoderError::Custom("Proof is too short"));
+        }
+        let mut proof = Proof {
+            public_input_hash: H256::from_slice(&src[0..32]),
+            ..Default::default()
+        };
 
         // The G1Points that we need for the recursive proof need to go into the snark circuit.
         // This is another field, so they are split into smaller chunks for the circuit to be happy
@@ -68,12 +78,12 @@ impl From<Bytes> for Proof {
             && recursive_p2_x.lt(&q)
             && recursive_p2_y.lt(&q))
         {
-            panic!("Pisso lorto, frem med modolo");
+            return Err(DecoderError::Custom("Recursive proof point is not in the field"));
         }
 
         proof.w1 = G1Point {
             x: U256::from_big_endian(&src[544..544 + 32]),
-            y: U256::from_big_endian(&src[544 + 32 * 1..544 + 32 * 2]),
+            y: U256::from_big_endian(&src[544 + 32..544 + 32 * 2]),
         };
 
         proof.w2 = G1Point {
@@ -118,6 +128,6 @@ impl From<Bytes> for Proof {
             y: U256::from_big_endian(&src[544 + 32 * 21..544 + 32 * 22]),
         };
 
-        return proof;
+        Ok(proof)
     }
 }
@@ -0,0 +1,335 @@
+use crate::types::layout::Layout;
+use crate::types::rollup_proof::PROOF_SIZE;
+use crate::types::transaction::ProofId;
+use ethers::types::{Address, H256, U256};
+use std::fmt;
+
+/// Why rollup calldata can't be decoded, with the byte offset in the calldata where it went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl DecodeError {
+    pub fn new(offset: usize, message: impl Into<String>) -> Self {
+        DecodeError { offset, message: message.into() }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+/// A borrowed view over rollup calldata that reads fields on demand and iterates the inner txs in
+/// place, so calldata is only copied into the fields of an `InnerBlock` once. It reads calldata as
+/// fetched from L1 or given to `decode-raw` and `decode --annotate`. The store keeps blocks decoded
+/// rather than their calldata, so exports don't read through a view: `BlockStore::for_each_block`
+/// decodes chunks of stored records in parallel, and only the `store::Parts` a scan asks for.
+#[derive(Debug, Clone, Copy)]
+pub struct RollupView<'a> {
+    calldata: &'a [u8],
+    layout: &'a Layout,
+}
+
+impl<'a> RollupView<'a> {
+    /// Checks that the header, the inner txs and the proof fit in the calldata, so the fields can be
+    /// read without further checks. The txs themselves are checked as they are iterated.
+    pub fn new(calldata: &'a [u8], layout: &'a Layout) -> Result<Self, DecodeError> {
+        let view = RollupView { calldata, layout };
+
+        let inner_txs = layout.inner_txs_offset();
+        if calldata.len() < inner_txs {
+            let message = format!("Calldata ends within the header, which is {} bytes", inner_txs);
+            return Err(DecodeError::new(calldata.len(), message));
+        }
+        let proof = view.proof_data_offset();
+        if calldata.len() < proof {
+            let message = format!("Calldata ends within the inner txs, which end at byte {}", proof);
+            return Err(DecodeError::new(calldata.len(), message));
+        }
+        if calldata.len() < proof + PROOF_SIZE {
+            let message = format!("Calldata ends within the proof, which is {} bytes", PROOF_SIZE);
+            return Err(DecodeError::new(calldata.len(), message));
+        }
+        Ok(view)
+    }
+
+    pub fn calldata(&self) -> &'a [u8] {
+        self.calldata
+    }
+
+    pub fn layout(&self) -> &'a Layout {
+        self.layout
+    }
+
+    fn word(&self, offset: usize, i: usize) -> &'a [u8] {
+        &self.calldata[offset + 32 * i..offset + 32 * i + 32]
+    }
+
+    fn u256(&self, offset: usize, i: usize) -> U256 {
+        U256::from_big_endian(self.word(offset, i))
+    }
+
+    fn h256(&self, offset: usize, i: usize) -> H256 {
+        H256::from_slice(self.word(offset, i))
+    }
+
+    pub fn rollup_id(&self) -> U256 {
+        self.u256(0, 0)
+    }
+
+    pub fn rollup_size(&self) -> U256 {
+        self.u256(0, 1)
+    }
+
+    pub fn data_start_index(&self) -> U256 {
+        self.u256(0, 2)
+    }
+
+    pub fn old_data_root(&self) -> H256 {
+        self.h256(0, 3)
+    }
+
+    pub fn new_data_root(&self) -> H256 {
+        self.h256(0, 4)
+    }
+
+    pub fn old_null_root(&self) -> H256 {
+        self.h256(0, 5)
+    }
+
+    pub fn new_null_root(&self) -> H256 {
+        self.h256(0, 6)
+    }
+
+    pub fn old_data_roots_root(&self) -> H256 {
+        self.h256(0, 7)
+    }
+
+    pub fn new_data_roots_root(&self) -> H256 {
+        self.h256(0, 8)
+    }
+
+    pub fn old_defi_root(&self) -> H256 {
+        self.h256(0, 9)
+    }
+
+    pub fn new_defi_root(&self) -> H256 {
+        self.h256(0, 10)
+    }
+
+    pub fn encoded_bridge_call_data(&self, slot: usize) -> U256 {
+        self.u256(self.layout.bridge_call_data_offset(), slot)
+    }
+
+    pub fn defi_deposit_sum(&self, slot: usize) -> U256 {
+        self.u256(self.layout.defi_deposit_sums_offset(), slot)
+    }
+
+    pub fn fee_asset_id(&self, slot: usize) -> U256 {
+        self.u256(self.layout.asset_ids_offset(), slot)
+    }
+
+    pub fn total_tx_fee(&self, slot: usize) -> U256 {
+        self.u256(self.layout.total_tx_fees_offset(), slot)
+    }
+
+    pub fn prev_defi_interaction_hash_slot(&self, slot: usize) -> H256 {
+        self.h256(self.layout.prev_defi_interaction_hashes_offset(), slot)
+    }
+
+    pub fn prev_defi_interaction_hash(&self) -> H256 {
+        self.h256(self.layout.prev_defi_interaction_hash_offset(), 0)
+    }
+
+    pub fn rollup_beneficiary(&self) -> Address {
+        Address::from_slice(&self.word(self.layout.rollup_beneficiary_offset(), 0)[12..32])
+    }
+
+    pub fn num_rollup_txs(&self) -> U256 {
+        self.u256(self.layout.num_rollup_txs_offset(), 0)
+    }
+
+    pub fn num_real_txs(&self) -> U256 {
+        let offset = self.layout.num_real_txs_offset();
+        U256::from_big_endian(&self.calldata[offset..offset + 4])
+    }
+
+    pub fn encoded_inner_tx_data_length(&self) -> U256 {
+        let offset = self.layout.encoded_inner_tx_data_length_offset();
+        U256::from_big_endian(&self.calldata[offset..offset + 4])
+    }
+
+    /// Byte offset of the rollup proof, following the inner txs.
+    pub fn proof_data_offset(&self) -> usize {
+        self.layout.inner_txs_offset() + self.encoded_inner_tx_data_length().as_usize()
+    }
+
+    /// Iterates the encoded inner txs, padding included.
+    pub fn inner_txs(&self) -> InnerTxs<'a> {
+        InnerTxs {
+            calldata: self.calldata,
+            start: self.layout.inner_txs_offset(),
+            end: self.proof_data_offset(),
+            position: 0,
+        }
+    }
+
+    pub fn proof_data(&self) -> &'a [u8] {
+        &self.calldata[self.proof_data_offset()..]
+    }
+}
+
+/// A single encoded inner tx within the rollup calldata.
+#[derive(Debug, Clone, Copy)]
+pub struct InnerTxView<'a> {
+    /// Position of the tx in the rollup, padding slots included.
+    pub position: u64,
+    /// Byte offset of the tx in the calldata.
+    pub offset: usize,
+    pub proof_id: ProofId,
+    pub data: &'a [u8],
+}
+
+pub struct InnerTxs<'a> {
+    calldata: &'a [u8],
+    start: usize,
+    end: usize,
+    position: u64,
+}
+
+impl<'a> Iterator for InnerTxs<'a> {
+    type Item = Result<InnerTxView<'a>, DecodeError>;
+
+    /// Stops after the first tx that can't be read, as the ones after it can't be found.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start >= self.end {
+            return None;
+        }
+
+        let offset = self.start;
+        self.start = self.end;
+        let proof_id = match ProofId::try_from(self.calldata[offset]) {
+            Ok(proof_id) => proof_id,
+            Err(_) => {
+                let message = format!("Unknown proof id {}", self.calldata[offset]);
+                return Some(Err(DecodeError::new(offset, message)));
+            }
+        };
+        let size = proof_id.data_size();
+        if offset + size > self.end {
+            let message = format!("{:?} tx of {} bytes runs past the inner txs, which end at byte {}", proof_id, size, self.end);
+            return Some(Err(DecodeError::new(offset, message)));
+        }
+
+        let tx = InnerTxView {
+            position: self.position,
+            offset,
+            proof_id,
+            data: &self.calldata[offset..offset + size],
+        };
+        self.start = offset + size;
+        self.position += 1;
+        Some(Ok(tx))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::layout::AZTEC_CONNECT;
+
+    /// Aztec Connect calldata with the given encoded inner txs and a zeroed header and proof.
+    pub(crate) fn calldata_with_txs(txs: &[u8]) -> Vec<u8> {
+        let layout = &AZTEC_CONNECT;
+        let mut calldata = vec![0; layout.inner_txs_offset()];
+        let length = layout.encoded_inner_tx_data_length_offset();
+        calldata[length..length + 4].copy_from_slice(&(txs.len() as u32).to_be_bytes());
+        calldata.extend_from_slice(txs);
+        calldata.extend_from_slice(&[0; PROOF_SIZE]);
+        calldata
+    }
+
+    #[test]
+    fn reads_header_fields_in_place() {
+        let layout = &AZTEC_CONNECT;
+        let mut calldata = calldata_with_txs(&[0]);
+        calldata[31] = 5;
+        calldata[32 * 10 + 31] = 6;
+        calldata[layout.bridge_call_data_offset() + 32 * 31 + 31] = 7;
+        calldata[layout.defi_deposit_sums_offset() + 31] = 8;
+        calldata[layout.asset_ids_offset() + 32 * 15 + 31] = 9;
+        calldata[layout.rollup_beneficiary_offset() + 12] = 10;
+        calldata[layout.num_real_txs_offset() + 3] = 1;
+        let view = RollupView::new(&calldata, layout).unwrap();
+
+        assert_eq!(view.rollup_id(), U256::from(5));
+        assert_eq!(view.new_defi_root(), H256::from_low_u64_be(6));
+        assert_eq!(view.encoded_bridge_call_data(31), U256::from(7));
+        assert_eq!(view.defi_deposit_sum(0), U256::from(8));
+        assert_eq!(view.fee_asset_id(15), U256::from(9));
+        assert_eq!(view.rollup_beneficiary().as_bytes()[0], 10);
+        assert_eq!(view.num_real_txs(), U256::one());
+        assert_eq!(view.encoded_inner_tx_data_length(), U256::one());
+    }
+
+    #[test]
+    fn iterates_txs_and_padding_in_order() {
+        let mut txs = vec![3];
+        txs.extend_from_slice(&[7; 128]);
+        txs.push(0);
+        txs.push(1);
+        txs.extend_from_slice(&[8; 184]);
+        let calldata = calldata_with_txs(&txs);
+        let view = RollupView::new(&calldata, &AZTEC_CONNECT).unwrap();
+
+        let txs: Vec<InnerTxView> = view.inner_txs().map(Result::unwrap).collect();
+        let start = AZTEC_CONNECT.inner_txs_offset();
+        assert_eq!(txs.len(), 3);
+        assert_eq!((txs[0].position, txs[0].offset, txs[0].proof_id), (0, start, ProofId::Send));
+        assert_eq!((txs[1].position, txs[1].offset, txs[1].proof_id), (1, start + 129, ProofId::Padding));
+        assert_eq!((txs[2].position, txs[2].offset, txs[2].proof_id), (2, start + 130, ProofId::Deposit));
+        assert_eq!(txs[2].data.len(), 185);
+        assert_eq!(view.proof_data_offset(), start + 315);
+        assert_eq!(view.proof_data().len(), PROOF_SIZE);
+    }
+
+    #[test]
+    fn stops_at_unknown_proof_ids() {
+        let calldata = calldata_with_txs(&[0, 9, 0]);
+        let view = RollupView::new(&calldata, &AZTEC_CONNECT).unwrap();
+
+        let txs: Vec<_> = view.inner_txs().collect();
+        let start = AZTEC_CONNECT.inner_txs_offset();
+        assert_eq!(txs.len(), 2);
+        assert!(txs[0].is_ok());
+        assert_eq!(txs[1].as_ref().unwrap_err().offset, start + 1);
+    }
+
+    #[test]
+    fn refuses_txs_running_past_the_inner_txs() {
+        let calldata = calldata_with_txs(&[3; 100]);
+        let view = RollupView::new(&calldata, &AZTEC_CONNECT).unwrap();
+
+        let txs: Vec<_> = view.inner_txs().collect();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].as_ref().unwrap_err().offset, AZTEC_CONNECT.inner_txs_offset());
+    }
+
+    #[test]
+    fn refuses_truncated_calldata() {
+        let calldata = calldata_with_txs(&[0; 10]);
+        let inner_txs = AZTEC_CONNECT.inner_txs_offset();
+
+        let err = RollupView::new(&calldata[..100], &AZTEC_CONNECT).unwrap_err();
+        assert_eq!(err.offset, 100);
+        let err = RollupView::new(&calldata[..inner_txs + 5], &AZTEC_CONNECT).unwrap_err();
+        assert_eq!(err.offset, inner_txs + 5);
+        let err = RollupView::new(&calldata[..calldata.len() - 1], &AZTEC_CONNECT).unwrap_err();
+        assert_eq!(err.offset, calldata.len() - 1);
+        assert!(RollupView::new(&calldata, &AZTEC_CONNECT).is_ok());
+    }
+}
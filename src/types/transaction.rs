@@ -1,4 +1,4 @@
-use ethers::types::{Address, Bytes, H256, U256};
+use ethers::types::{Address, H256, U256};
 use ethers::utils::rlp::DecoderError;
 use serde::{Deserialize, Serialize};
 
@@ -17,7 +17,7 @@ pub enum ProofId {
 impl TryFrom<u8> for ProofId {
     type Error = DecoderError;
     fn try_from(src: u8) -> Result<Self, Self::Error> {
-        return match src {
+        match src {
             0 => Ok(ProofId::Padding),
             1 => Ok(ProofId::Deposit),
             2 => Ok(ProofId::Withdraw),
@@ -26,13 +26,13 @@ impl TryFrom<u8> for ProofId {
             5 => Ok(ProofId::DefiDeposit),
             6 => Ok(ProofId::DefiClaim),
             _ => Err(DecoderError::Custom("Invalid type")),
-        };
+        }
     }
 }
 
 impl ProofId {
     pub fn data_size(&self) -> usize {
-        return match self {
+        match self {
             ProofId::Padding => 1,
             ProofId::Deposit => 185,
             ProofId::Withdraw => 185,
@@ -40,11 +40,17 @@ impl ProofId {
             ProofId::Account => 129,
             ProofId::DefiDeposit => 129,
             ProofId::DefiClaim => 129,
-        };
+        }
+    }
+
+    /// Only deposits and withdrawals have a public value, owner and asset id, the other txs leave
+    /// them zero.
+    pub fn has_public_fields(&self) -> bool {
+        matches!(self, ProofId::Deposit | ProofId::Withdraw)
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct InnerProofData {
     pub proof_id: ProofId,
     pub note_commitment_1: H256,
@@ -54,29 +60,52 @@ pub struct InnerProofData {
     pub public_value: U256,
     pub public_owner: Address,
     pub public_asset_id: U256,
+    /// Position of the tx in the rollup, padding slots included.
+    pub tx_position: u64,
+    /// Leaf index of `note_commitment_1` in the data tree, `note_commitment_2` is at the next index.
+    pub note_commitment_1_index: u64,
+    pub note_commitment_2_index: u64,
 }
 
-impl TryFrom<Bytes> for InnerProofData {
+impl InnerProofData {
+    /// Every tx slot in a rollup owns two consecutive leaves in the data tree, starting from the
+    /// rollup's `data_start_index`. Fails if the leaf indexes don't fit in 64 bits.
+    pub fn set_position(&mut self, data_start_index: U256, tx_position: u64) -> Result<(), String> {
+        let index = u64::try_from(data_start_index)
+            .ok()
+            .and_then(|index| index.checked_add(tx_position.checked_mul(2)?))
+            .filter(|index| *index < u64::MAX)
+            .ok_or_else(|| format!("Leaf index of tx {} from {} doesn't fit in 64 bits", tx_position, data_start_index))?;
+        self.tx_position = tx_position;
+        self.note_commitment_1_index = index;
+        self.note_commitment_2_index = index + 1;
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for InnerProofData {
     type Error = DecoderError;
-    fn try_from(src: Bytes) -> Result<InnerProofData, Self::Error> {
-        let mut proof = InnerProofData::default();
-        proof.proof_id = ProofId::try_from(src[0]).unwrap();
+    fn try_from(src: &[u8]) -> Result<InnerProofData, Self::Error> {
+        let proof_id = ProofId::try_from(*src.first().ok_or(DecoderError::Custom("Empty proof"))?)?;
 
-        match proof.proof_id {
-            ProofId::Padding => return Ok(proof),
-            _ => (),
+        if proof_id == ProofId::Padding {
+            return Ok(InnerProofData { proof_id, ..Default::default() });
+        }
+        if src.len() != proof_id.data_size() {
+            return Err(DecoderError::Custom("Invalid proof size"));
         }
 
-        proof.note_commitment_1 = H256::from_slice(&src[1..33]);
-        proof.note_commitment_2 = H256::from_slice(&src[33..65]);
-        proof.nullifier_1 = H256::from_slice(&src[65..97]);
-        proof.nullifier_2 = H256::from_slice(&src[97..129]);
+        let mut proof = InnerProofData {
+            proof_id,
+            note_commitment_1: H256::from_slice(&src[1..33]),
+            note_commitment_2: H256::from_slice(&src[33..65]),
+            nullifier_1: H256::from_slice(&src[65..97]),
+            nullifier_2: H256::from_slice(&src[97..129]),
+            ..Default::default()
+        };
 
         match proof.proof_id {
             ProofId::Deposit | ProofId::Withdraw => {
-                if src.len() != 185 {
-                    return Err(DecoderError::Custom("Invalid proof size"));
-                }
                 proof.public_value = U256::from_big_endian(&src[129..161]);
                 proof.public_owner = Address::from_slice(&src[161..181]);
                 proof.public_asset_id = U256::from_big_endian(&src[181..185]);
@@ -84,6 +113,59 @@ impl TryFrom<Bytes> for InnerProofData {
             _ => (),
         };
 
-        return Ok(proof);
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(proof_id: u8, size: usize) -> Vec<u8> {
+        let mut data = vec![0; size];
+        data[0] = proof_id;
+        data
+    }
+
+    #[test]
+    fn every_slot_owns_two_leaves() {
+        let mut tx = InnerProofData::default();
+        tx.set_position(U256::from(1024), 0).unwrap();
+        assert_eq!((tx.note_commitment_1_index, tx.note_commitment_2_index), (1024, 1025));
+        tx.set_position(U256::from(1024), 31).unwrap();
+        assert_eq!(tx.tx_position, 31);
+        assert_eq!((tx.note_commitment_1_index, tx.note_commitment_2_index), (1086, 1087));
+    }
+
+    #[test]
+    fn refuses_leaf_indexes_above_64_bits() {
+        let mut tx = InnerProofData::default();
+        assert!(tx.set_position(U256::from(u64::MAX) + 1, 0).is_err());
+        assert!(tx.set_position(U256::from(u64::MAX - 2), 1).is_err());
+        assert!(tx.set_position(U256::from(u64::MAX - 3), 1).is_ok());
+        assert_eq!(tx.note_commitment_2_index, u64::MAX);
+    }
+
+    #[test]
+    fn reads_public_fields_of_deposits_and_withdrawals() {
+        let mut data = encoded(2, 185);
+        data[160] = 9;
+        data[161..181].copy_from_slice(Address::repeat_byte(4).as_bytes());
+        data[184] = 3;
+
+        let tx = InnerProofData::try_from(data.as_slice()).unwrap();
+        assert_eq!(tx.proof_id, ProofId::Withdraw);
+        assert_eq!(tx.public_value, U256::from(9));
+        assert_eq!(tx.public_owner, Address::repeat_byte(4));
+        assert_eq!(tx.public_asset_id, U256::from(3));
+    }
+
+    #[test]
+    fn refuses_txs_of_the_wrong_size() {
+        assert!(InnerProofData::try_from(encoded(1, 129).as_slice()).is_err());
+        assert!(InnerProofData::try_from(encoded(3, 185).as_slice()).is_err());
+        assert!(InnerProofData::try_from(encoded(7, 129).as_slice()).is_err());
+        assert!(InnerProofData::try_from(&[][..]).is_err());
+        assert!(InnerProofData::try_from(encoded(3, 129).as_slice()).is_ok());
     }
 }